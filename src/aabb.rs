@@ -0,0 +1,103 @@
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// An axis-aligned bounding box described by its minimum and maximum corners.
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        return Aabb { min, max }
+    }
+
+    /// An inverted box which contains nothing, useful as the identity when
+    /// accumulating bounds with `surrounding` or `grow`.
+    pub fn empty() -> Aabb {
+        return Aabb {
+            min: Vec3::from_uniform_value(f32::INFINITY),
+            max: Vec3::from_uniform_value(f32::NEG_INFINITY)
+        }
+    }
+
+    pub fn surrounding(self, other: Aabb) -> Aabb {
+        return Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max)
+        }
+    }
+
+    pub fn grow(self, p: Vec3) -> Aabb {
+        return Aabb {
+            min: self.min.min(p),
+            max: self.max.max(p)
+        }
+    }
+
+    pub fn centroid(self) -> Vec3 {
+        return 0.5 * (self.min + self.max);
+    }
+
+    pub fn extent(self) -> Vec3 {
+        return self.max - self.min;
+    }
+
+    /// Returns the axis (0 = x, 1 = y, 2 = z) along which the box is the widest.
+    pub fn longest_axis(self) -> usize {
+        let d = self.extent();
+        if d.x > d.y && d.x > d.z {
+            return 0;
+        } else if d.y > d.z {
+            return 1;
+        }
+        return 2;
+    }
+
+    pub fn surface_area(self) -> f32 {
+        let d = self.extent();
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+        return 2.0 * (d.x * d.y + d.y * d.z + d.z * d.x);
+    }
+
+    /// Slab test against the box.
+    ///
+    /// # Parameters
+    ///
+    /// - `r` : &Ray
+    /// The ray being traced
+    /// - `inv_dir` : Vec3
+    /// The reciprocal of the ray's direction, precomputed once per ray
+    /// - `t_min`, `t_max` : f32
+    /// The parametric interval the ray is tested over
+    ///
+    /// # Returns
+    ///
+    /// True if the ray overlaps the box anywhere inside [t_min, t_max].
+    pub fn hit(&self, r: &Ray, inv_dir: Vec3, t_min: f32, t_max: f32) -> bool {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+
+        for axis in 0..3 {
+            let mut near = (self.min[axis] - r.origin[axis]) * inv_dir[axis];
+            let mut far = (self.max[axis] - r.origin[axis]) * inv_dir[axis];
+
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+
+            // Written so that a NaN from 0 * inf leaves the interval untouched.
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+
+            if t1 < t0 {
+                return false;
+            }
+        }
+
+        return true;
+    }
+}
@@ -0,0 +1,223 @@
+use std::f32::consts::PI;
+
+use crate::render::Framebuffer;
+use crate::tonemap::luminance;
+use crate::vec3::Vec3;
+
+/// The radiance arriving from outside the scene, seen by every ray which misses it.
+pub enum Background {
+    Constant(Vec3),
+    /// Blends from `bottom` straight down to `top` straight up.
+    Gradient { bottom: Vec3, top: Vec3 },
+    Environment(EnvironmentMap)
+}
+
+impl Background {
+    /// The white to blue sky the renderer has always used.
+    pub fn sky() -> Background {
+        return Background::Gradient {
+            bottom: Vec3::from_uniform_value(1.0),
+            top: Vec3::new(0.5, 0.7, 1.0)
+        }
+    }
+
+    /// Radiance arriving along `direction`, which does not need to be normalized.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Background::Constant(color) => return *color,
+            Background::Gradient { bottom, top } => {
+                let t = (direction.unit_vector().y + 1.0) * 0.5;
+                return (1.0 - t) * *bottom + t * *top;
+            },
+            Background::Environment(map) => return map.radiance(direction)
+        }
+    }
+
+    /// The environment map, when the background is one that light sampling can pick.
+    pub fn environment(&self) -> Option<&EnvironmentMap> {
+        match self {
+            Background::Environment(map) => return Some(map),
+            _ => return None
+        }
+    }
+}
+
+/// A piecewise constant distribution over [0, 1), one piece per entry of `weights`.
+struct Distribution1D {
+    weights: Vec<f32>,
+    /// Running sum of the weights normalized to end at 1, one longer than `weights`.
+    cdf: Vec<f32>,
+    total: f32
+}
+
+impl Distribution1D {
+    fn new(weights: Vec<f32>) -> Distribution1D {
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        cdf.push(0.0);
+        let mut sum = 0.0;
+        for weight in &weights {
+            sum += *weight;
+            cdf.push(sum);
+        }
+        if sum > 0.0 {
+            for value in cdf.iter_mut() {
+                *value /= sum;
+            }
+        }
+        return Distribution1D { weights, cdf, total: sum };
+    }
+
+    /// Maps the uniform number `u` to a point in [0, 1), returning it with the index of
+    /// its piece.
+    fn sample(&self, u: f32) -> (f32, usize) {
+        // The last entry whose cdf is at most u.
+        let index = self.cdf.partition_point(|&c| c <= u).saturating_sub(1).min(self.weights.len() - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 { (u - self.cdf[index]) / width } else { 0.0 };
+        let x = (index as f32 + offset.clamp(0.0, 1.0)) / self.weights.len() as f32;
+        return (x.min(1.0 - f32::EPSILON), index);
+    }
+
+    /// Density of `sample` choosing a point in piece `index`.
+    fn pdf(&self, index: usize) -> f32 {
+        return self.weights[index] * self.weights.len() as f32 / self.total;
+    }
+}
+
+/// An equirectangular (latitude/longitude) image surrounding the scene, with the top
+/// row straight up and the horizontal center looking down +x before rotation.
+///
+/// Sampling follows the image's luminance, each pixel weighted by the solid angle it
+/// covers, so light sampling mostly picks the sun and other bright regions.
+pub struct EnvironmentMap {
+    image: Framebuffer,
+    /// Rotation around the y axis, in radians.
+    rotation: f32,
+    intensity: f32,
+    /// Distribution of the rows, then of the pixels within each row.
+    rows: Distribution1D,
+    columns: Vec<Distribution1D>
+}
+
+impl EnvironmentMap {
+    /// # Parameters
+    ///
+    /// - `image` : Framebuffer
+    /// The linear latitude/longitude image
+    /// - `rotation` : f32
+    /// Rotation around the vertical axis in degrees
+    /// - `intensity` : f32
+    /// Scale applied to every pixel
+    pub fn new(image: Framebuffer, rotation: f32, intensity: f32) -> EnvironmentMap {
+        let (width, height) = (image.width as usize, image.height as usize);
+
+        let mut columns = Vec::with_capacity(height);
+        let mut row_weights = Vec::with_capacity(height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            let weights: Vec<f32> = image.pixels[y * width..(y + 1) * width].iter()
+                .map(|p| luminance(*p).max(0.0) * sin_theta)
+                .collect();
+            let row = Distribution1D::new(weights);
+            row_weights.push(row.total);
+            columns.push(row);
+        }
+
+        return EnvironmentMap {
+            image,
+            rotation: rotation.to_radians(),
+            intensity,
+            rows: Distribution1D::new(row_weights),
+            columns
+        }
+    }
+
+    /// Whether any pixel is bright enough to be sampled.
+    pub fn is_dark(&self) -> bool {
+        return self.rows.total <= 0.0;
+    }
+
+    /// Image coordinates of `direction`, u around the horizon and v from the top.
+    fn direction_to_uv(&self, direction: Vec3) -> (f32, f32) {
+        let d = direction.unit_vector();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = d.z.atan2(d.x) - self.rotation;
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0) + 0.5;
+        return (if u >= 1.0 { u - 1.0 } else { u }, theta / PI);
+    }
+
+    fn uv_to_direction(&self, u: f32, v: f32) -> Vec3 {
+        let theta = PI * v;
+        let phi = 2.0 * PI * (u - 0.5) + self.rotation;
+        let sin_theta = theta.sin();
+        return Vec3::new(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin());
+    }
+
+    fn pixel_at(&self, u: f32, v: f32) -> (usize, usize) {
+        let x = ((u * self.image.width as f32) as usize).min(self.image.width as usize - 1);
+        let y = ((v * self.image.height as f32) as usize).min(self.image.height as usize - 1);
+        return (x, y);
+    }
+
+    /// Bilinearly filtered radiance, wrapping around the horizon.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(direction);
+        let (width, height) = (self.image.width as i64, self.image.height as i64);
+
+        let x = u * width as f32 - 0.5;
+        let y = v * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let texel = |x: i64, y: i64| {
+            let x = x.rem_euclid(width);
+            let y = y.clamp(0, height - 1);
+            return self.image.pixels[(y * width + x) as usize];
+        };
+        let upper = (1.0 - fx) * texel(x0, y0) + fx * texel(x0 + 1, y0);
+        let lower = (1.0 - fx) * texel(x0, y0 + 1) + fx * texel(x0 + 1, y0 + 1);
+        return ((1.0 - fy) * upper + fy * lower) * self.intensity;
+    }
+
+    /// Picks a direction with a density proportional to the pixel luminance.
+    ///
+    /// # Returns
+    ///
+    /// The unit direction and its solid angle density, or `None` when the map is black
+    /// or the sample lands on a pole.
+    pub fn sample(&self, u: (f32, f32)) -> Option<(Vec3, f32)> {
+        if self.is_dark() {
+            return None;
+        }
+
+        let (v, y) = self.rows.sample(u.0);
+        let (u, x) = self.columns[y].sample(u.1);
+        let pdf = self.pixel_pdf(x, y, v);
+        if pdf <= 0.0 {
+            return None;
+        }
+        return Some((self.uv_to_direction(u, v), pdf));
+    }
+
+    /// The solid angle density `sample` picks `direction` with.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        if self.is_dark() {
+            return 0.0;
+        }
+        let (u, v) = self.direction_to_uv(direction);
+        let (x, y) = self.pixel_at(u, v);
+        return self.pixel_pdf(x, y, v);
+    }
+
+    /// Converts the density over the image, uniform within each pixel, to solid angle:
+    /// the mapping stretches a unit of image area over 2 pi^2 sin(theta) steradians.
+    fn pixel_pdf(&self, x: usize, y: usize, v: f32) -> f32 {
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 || self.columns[y].total <= 0.0 {
+            return 0.0;
+        }
+        let image_pdf = self.rows.pdf(y) * self.columns[y].pdf(x);
+        return image_pdf / (2.0 * PI * PI * sin_theta);
+    }
+}
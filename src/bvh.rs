@@ -0,0 +1,341 @@
+use crate::aabb::Aabb;
+use crate::hitable_list::HitableList;
+use crate::material::MaterialHandle;
+use crate::ray::Ray;
+use crate::shapes::{ Hitable, HitRecord, MaterialAccessor };
+use crate::vec3::Vec3;
+
+/// Number of buckets the centroids are binned into when evaluating the SAH.
+const SAH_BUCKETS: usize = 12;
+
+/// Leaves never hold more primitives than this, even if the SAH prefers it.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Relative cost of visiting an interior node versus intersecting a primitive.
+const TRAVERSAL_COST: f32 = 0.125;
+
+#[derive(Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    /// Leaves: index of the first primitive. Interior nodes: index of the second child,
+    /// the first child always directly follows its parent.
+    offset: usize,
+    /// Number of primitives in a leaf, 0 for interior nodes.
+    count: usize,
+    /// Axis the interior node was split along, used to visit the nearer child first.
+    axis: usize
+}
+
+#[derive(Clone, Copy)]
+struct PrimitiveInfo {
+    index: usize,
+    bounds: Aabb,
+    centroid: Vec3
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    count: usize,
+    bounds: Aabb
+}
+
+/// A bounding volume hierarchy built once from a `HitableList` and traversed in place
+/// of the linear scan in `hitable_list::hit`.
+///
+/// Nodes are stored flattened in depth first order and the primitives are reordered
+/// so that every leaf references a contiguous range.
+pub struct Bvh<T: Hitable + MaterialAccessor> {
+    nodes: Vec<BvhNode>,
+    primitives: Vec<T>
+}
+
+impl<T: Hitable + MaterialAccessor> Bvh<T> {
+    pub fn build(world: HitableList<T>) -> Bvh<T> {
+        return Bvh::from_primitives(world.list);
+    }
+
+    /// The primitives in BVH order, `HitRecord::primitive` indexes into this.
+    pub fn primitives(&self) -> &[T] {
+        return &self.primitives;
+    }
+
+    pub fn from_primitives(list: Vec<T>) -> Bvh<T> {
+        let mut infos: Vec<PrimitiveInfo> = list.iter().enumerate().map(|(i, p)| {
+            let bounds = p.bounding_box();
+            PrimitiveInfo { index: i, bounds, centroid: bounds.centroid() }
+        }).collect();
+
+        let mut nodes: Vec<BvhNode> = Vec::with_capacity(2 * list.len().max(1));
+        if !infos.is_empty() {
+            build_recursive(&mut nodes, &mut infos, 0);
+        }
+
+        // Reorder the primitives so each leaf points at a contiguous run.
+        let mut slots: Vec<Option<T>> = list.into_iter().map(Some).collect();
+        let primitives = infos.iter()
+            .map(|info| slots[info.index].take().expect("Primitive referenced twice by the BVH"))
+            .collect();
+
+        return Bvh { nodes, primitives };
+    }
+}
+
+fn build_recursive(nodes: &mut Vec<BvhNode>, infos: &mut [PrimitiveInfo], first: usize) -> usize {
+    let node_index = nodes.len();
+    let bounds = infos.iter().fold(Aabb::empty(), |acc, info| acc.surrounding(info.bounds));
+    nodes.push(BvhNode { bounds, offset: first, count: infos.len(), axis: 0 });
+
+    if infos.len() == 1 {
+        return node_index;
+    }
+
+    let centroid_bounds = infos.iter().fold(Aabb::empty(), |acc, info| acc.grow(info.centroid));
+    let axis = centroid_bounds.longest_axis();
+    let axis_min = centroid_bounds.min[axis];
+    let axis_extent = centroid_bounds.max[axis] - axis_min;
+
+    // Every centroid is in the same spot, splitting cannot separate anything.
+    if axis_extent <= 0.0 {
+        if infos.len() <= MAX_LEAF_SIZE {
+            return node_index;
+        }
+        return split_node(nodes, infos, first, node_index, axis, infos.len() / 2);
+    }
+
+    let bucket_of = |centroid: Vec3| -> usize {
+        let b = (SAH_BUCKETS as f32 * (centroid[axis] - axis_min) / axis_extent) as usize;
+        return b.min(SAH_BUCKETS - 1);
+    };
+
+    let mut buckets = [Bucket { count: 0, bounds: Aabb::empty() }; SAH_BUCKETS];
+    for info in infos.iter() {
+        let b = bucket_of(info.centroid);
+        buckets[b].count += 1;
+        buckets[b].bounds = buckets[b].bounds.surrounding(info.bounds);
+    }
+
+    // Sweep from both ends so each split candidate's cost is computed in linear time.
+    let mut costs = [0.0f32; SAH_BUCKETS - 1];
+    let mut left_bounds = Aabb::empty();
+    let mut left_count = 0;
+    for i in 0..SAH_BUCKETS - 1 {
+        left_bounds = left_bounds.surrounding(buckets[i].bounds);
+        left_count += buckets[i].count;
+        costs[i] = left_count as f32 * left_bounds.surface_area();
+    }
+
+    let mut right_bounds = Aabb::empty();
+    let mut right_count = 0;
+    for i in (1..SAH_BUCKETS).rev() {
+        right_bounds = right_bounds.surrounding(buckets[i].bounds);
+        right_count += buckets[i].count;
+        costs[i - 1] += right_count as f32 * right_bounds.surface_area();
+    }
+
+    let mut best_split = 0;
+    let mut best_cost = f32::INFINITY;
+    for (i, cost) in costs.iter().enumerate() {
+        if *cost < best_cost {
+            best_cost = *cost;
+            best_split = i;
+        }
+    }
+
+    let area = bounds.surface_area();
+    let split_cost = if area > 0.0 { TRAVERSAL_COST + best_cost / area } else { f32::INFINITY };
+    let leaf_cost = infos.len() as f32;
+
+    if infos.len() <= MAX_LEAF_SIZE && leaf_cost <= split_cost {
+        return node_index;
+    }
+
+    let mut mid = partition(infos, |info| bucket_of(info.centroid) <= best_split);
+    if mid == 0 || mid == infos.len() {
+        // The buckets failed to separate anything, fall back to an equal counts split.
+        infos.sort_by(|a, b| a.centroid[axis].partial_cmp(&b.centroid[axis]).unwrap_or(std::cmp::Ordering::Equal));
+        mid = infos.len() / 2;
+    }
+
+    return split_node(nodes, infos, first, node_index, axis, mid);
+}
+
+fn split_node(
+    nodes: &mut Vec<BvhNode>,
+    infos: &mut [PrimitiveInfo],
+    first: usize,
+    node_index: usize,
+    axis: usize,
+    mid: usize) -> usize {
+
+    let (left, right) = infos.split_at_mut(mid);
+    build_recursive(nodes, left, first);
+    let second = build_recursive(nodes, right, first + mid);
+
+    nodes[node_index].offset = second;
+    nodes[node_index].count = 0;
+    nodes[node_index].axis = axis;
+    return node_index;
+}
+
+/// In place partition, returns the number of elements satisfying the predicate which
+/// are moved to the front of the slice.
+fn partition<F: Fn(&PrimitiveInfo) -> bool>(infos: &mut [PrimitiveInfo], pred: F) -> usize {
+    let mut mid = 0;
+    for i in 0..infos.len() {
+        if pred(&infos[i]) {
+            infos.swap(i, mid);
+            mid += 1;
+        }
+    }
+    return mid;
+}
+
+impl<T: Hitable + MaterialAccessor> Hitable for Bvh<T> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_dir = Vec3::new(1.0 / r.direction.x, 1.0 / r.direction.y, 1.0 / r.direction.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut temp_rec = HitRecord::default();
+        let mut hit_anything = false;
+        let mut closest = t_max;
+
+        let mut stack = [0usize; 64];
+        let mut stack_size = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+
+            if node.bounds.hit(r, inv_dir, t_min, closest) {
+                if node.count > 0 {
+                    for (i, primitive) in self.primitives[node.offset..node.offset + node.count].iter().enumerate() {
+                        if primitive.hit(r, t_min, closest, &mut temp_rec) {
+                            hit_anything = true;
+                            closest = temp_rec.t;
+                            *rec = temp_rec;
+                            if primitive.material() != MaterialHandle::UNASSIGNED {
+                                rec.material = primitive.material();
+                            }
+                            rec.primitive = node.offset + i;
+                        }
+                    }
+                } else {
+                    // Descend into the child closer to the ray origin first so `closest`
+                    // shrinks as early as possible.
+                    if dir_is_neg[node.axis] {
+                        stack[stack_size] = current + 1;
+                        current = node.offset;
+                    } else {
+                        stack[stack_size] = node.offset;
+                        current += 1;
+                    }
+                    stack_size += 1;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size];
+        }
+
+        return hit_anything;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        if self.nodes.is_empty() {
+            return Aabb::empty();
+        }
+        return self.nodes[0].bounds;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{ Rng, SeedableRng, rngs::StdRng };
+
+    use super::*;
+    use crate::hitable_list;
+    use crate::material::{Lambertian, MaterialHandle, MaterialRegistry};
+    use crate::shapes::Sphere;
+
+    /// A registry of `count` distinct materials, so hits can be told apart by handle.
+    fn materials(count: usize) -> (MaterialRegistry, Vec<MaterialHandle>) {
+        let mut registry = MaterialRegistry::new();
+        let handles = (0..count)
+            .map(|_| registry.add(Lambertian::with_albedo(Vec3::from_uniform_value(0.5))))
+            .collect();
+        return (registry, handles);
+    }
+
+    fn random_vec(rng: &mut StdRng, scale: f32) -> Vec3 {
+        return Vec3::new(
+            rng.gen_range(-scale..scale),
+            rng.gen_range(-scale..scale),
+            rng.gen_range(-scale..scale))
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let (_, handles) = materials(509);
+        let mut spheres = Vec::new();
+        for handle in &handles[..500] {
+            let radius = rng.gen_range(0.05..1.0);
+            spheres.push(Sphere::new(random_vec(&mut rng, 20.0), radius).with_material(*handle));
+        }
+        // Concentric spheres and a huge ground sphere exercise the degenerate split paths.
+        for i in 0..8 {
+            spheres.push(Sphere::new(Vec3::zero(), 0.5 + i as f32 * 0.25).with_material(handles[500 + i]));
+        }
+        spheres.push(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 999.0).with_material(handles[508]));
+
+        let bvh = Bvh::build(HitableList::from_list(spheres.clone()));
+
+        for _ in 0..20000 {
+            let origin = random_vec(&mut rng, 30.0);
+            let direction = random_vec(&mut rng, 1.0);
+            let r = Ray::new(&origin, &direction);
+
+            let mut expected = HitRecord::default();
+            let mut actual = HitRecord::default();
+            let expected_hit = hitable_list::hit(&spheres, &r, 0.001, f32::MAX, &mut expected);
+            let actual_hit = bvh.hit(&r, 0.001, f32::MAX, &mut actual);
+
+            assert_eq!(expected_hit, actual_hit);
+            if expected_hit {
+                assert_eq!(expected.t, actual.t);
+                assert_eq!(expected.material, actual.material);
+                assert_eq!(expected.p.x, actual.p.x);
+                assert_eq!(expected.normal.y, actual.normal.y);
+            }
+        }
+    }
+
+    #[test]
+    fn axis_aligned_rays() {
+        let (_, handles) = materials(64);
+        let spheres: Vec<Sphere> = (0..64)
+            .map(|i| Sphere::new(Vec3::new(i as f32 * 2.0, 0.0, 0.0), 0.5).with_material(handles[i]))
+            .collect();
+        let bvh = Bvh::build(HitableList::from_list(spheres));
+
+        let r = Ray::new(&Vec3::new(-5.0, 0.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        let mut rec = HitRecord::default();
+        assert!(bvh.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.material, handles[0]);
+
+        let r = Ray::new(&Vec3::new(200.0, 0.0, 0.0), &Vec3::new(-1.0, 0.0, 0.0));
+        assert!(bvh.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.material, handles[63]);
+
+        let r = Ray::new(&Vec3::new(10.0, 5.0, 0.0), &Vec3::new(0.0, 1.0, 0.0));
+        assert!(!bvh.hit(&r, 0.001, f32::MAX, &mut rec));
+    }
+}
@@ -1,47 +1,187 @@
-use crate::random_utils::random_in_unit_disk;
-use crate::vec3::Vec3;
-use crate::ray::Ray;
-
-#[derive(Copy, Clone)]
-pub struct Camera {
-    pub origin : Vec3,
-    pub lower_left_corner : Vec3,
-    pub horizontal : Vec3,
-    pub vertical : Vec3,
-    pub u : Vec3,
-    pub v : Vec3,
-    pub w: Vec3,
-    lens_radius : f32,
-}
-
-impl Camera {
-
-    pub fn perspective(lookfrom: Vec3, lookat: Vec3, vup: Vec3, vfov: f32, aspect: f32, aperture: f32, focus_dist: f32) -> Camera {
-        let theta = vfov * std::f32::consts::PI / 180.0;
-        let half_height = (theta / 2.0).tan();
-        let half_width = aspect * half_height;
-
-        let w = (lookfrom - lookat).unit_vector();
-        let u = vup.cross(w).unit_vector();
-        let v = w.cross(u).unit_vector();
-
-        return Camera {
-            origin : lookfrom,
-            lower_left_corner : lookfrom - half_width * focus_dist * u - half_height * focus_dist * v - focus_dist * w,
-            horizontal : 2.0 * half_width * u * focus_dist,
-            vertical : 2.0 * half_height * v * focus_dist,
-            u : u,
-            v : v,
-            w : w,
-            lens_radius : aperture / 2.0
-        }
-    }
-
-    pub fn get_ray(self, s : f32, t : f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
-        let offset = self.u * rd.x + self.v * rd.y;
-        return Ray::new(
-            &(self.origin + offset), 
-            &(self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset));
-    }
-}
+use std::f32::consts::PI;
+
+use crate::random_utils::random_in_unit_disk;
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+
+/// How the angle away from the view direction maps to the distance from the centre of
+/// a fisheye image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle, keeping angles true.
+    Equidistant,
+    /// Distance proportional to the sine of half the angle, keeping areas true.
+    Equisolid
+}
+
+/// The way points of the image are turned into the directions rays are traced in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel rays, showing the area the perspective camera would see at the focus
+    /// distance without any foreshortening.
+    Orthographic,
+    /// A circular image of `vfov` degrees, up to 360, fitted to the shorter side.
+    Fisheye(FisheyeMapping),
+    /// The whole sphere of directions, longitude across and latitude up the image.
+    Equirectangular
+}
+
+impl Projection {
+    pub fn from_name(name: &str) -> Option<Projection> {
+        match name {
+            "perspective" => return Some(Projection::Perspective),
+            "orthographic" => return Some(Projection::Orthographic),
+            "fisheye-equidistant" => return Some(Projection::Fisheye(FisheyeMapping::Equidistant)),
+            "fisheye-equisolid" => return Some(Projection::Fisheye(FisheyeMapping::Equisolid)),
+            "equirectangular" => return Some(Projection::Equirectangular),
+            _ => return None
+        }
+    }
+
+    /// The largest field of view the projection accepts, in degrees.
+    pub fn max_fov(self) -> f32 {
+        match self {
+            Projection::Fisheye(_) => return 360.0,
+            _ => return 179.99
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Camera {
+    pub origin : Vec3,
+    pub lower_left_corner : Vec3,
+    pub horizontal : Vec3,
+    pub vertical : Vec3,
+    pub u : Vec3,
+    pub v : Vec3,
+    pub w: Vec3,
+    lens_radius : f32,
+    focus_dist : f32,
+    projection : Projection,
+    /// Half the field of view in radians, used by the fisheye projections.
+    half_fov : f32,
+    aspect : f32,
+    /// Rays are spread over the times between these two, which blurs moving objects.
+    shutter_open : f32,
+    shutter_close : f32
+}
+
+impl Camera {
+
+    /// A camera looking from `lookfrom` towards `lookat` with the given projection.
+    ///
+    /// # Parameters
+    ///
+    /// - `vfov` : f32
+    /// Vertical field of view in degrees, across the image circle for fisheyes and
+    /// unused by the equirectangular projection
+    /// - `aperture`, `focus_dist` : f32
+    /// Lens diameter and the distance in focus. Fisheye and equirectangular cameras
+    /// are pinholes and ignore the aperture.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(projection: Projection, lookfrom: Vec3, lookat: Vec3, vup: Vec3, vfov: f32, aspect: f32, aperture: f32, focus_dist: f32) -> Camera {
+        let mut camera = Camera::perspective(lookfrom, lookat, vup, vfov, aspect, aperture, focus_dist);
+        camera.projection = projection;
+        match projection {
+            Projection::Orthographic => {
+                // The frame is moved back to the camera, the rays all leaving parallel to w.
+                camera.lower_left_corner = camera.lower_left_corner + focus_dist * camera.w;
+            },
+            Projection::Fisheye(_) | Projection::Equirectangular => camera.lens_radius = 0.0,
+            Projection::Perspective => {}
+        }
+        return camera;
+    }
+
+    pub fn perspective(lookfrom: Vec3, lookat: Vec3, vup: Vec3, vfov: f32, aspect: f32, aperture: f32, focus_dist: f32) -> Camera {
+        let theta = vfov * std::f32::consts::PI / 180.0;
+        let half_height = (theta / 2.0).tan();
+        let half_width = aspect * half_height;
+
+        let w = (lookfrom - lookat).unit_vector();
+        let u = vup.cross(w).unit_vector();
+        let v = w.cross(u).unit_vector();
+
+        return Camera {
+            origin : lookfrom,
+            lower_left_corner : lookfrom - half_width * focus_dist * u - half_height * focus_dist * v - focus_dist * w,
+            horizontal : 2.0 * half_width * u * focus_dist,
+            vertical : 2.0 * half_height * v * focus_dist,
+            u : u,
+            v : v,
+            w : w,
+            lens_radius : aperture / 2.0,
+            focus_dist : focus_dist,
+            projection : Projection::Perspective,
+            half_fov : theta / 2.0,
+            aspect : aspect,
+            shutter_open : 0.0,
+            shutter_close : 0.0
+        }
+    }
+
+    /// Keeps the shutter open from `open` to `close`, equal times freezing the scene
+    /// at that instant.
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Camera {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        return self;
+    }
+
+    /// The direction seen at the point (x, y) of the image, both running from -1 to 1
+    /// across it, or `None` outside the image circle.
+    fn fisheye_direction(&self, mapping: FisheyeMapping, x: f32, y: f32) -> Option<Vec3> {
+        // The image circle fits the shorter side.
+        let (x, y) = if self.aspect >= 1.0 { (x * self.aspect, y) } else { (x, y / self.aspect) };
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.half_fov / 2.0).sin()).asin()
+        };
+        let (sin_phi, cos_phi) = if r > 0.0 { (y / r, x / r) } else { (0.0, 1.0) };
+        return Some(theta.sin() * (cos_phi * self.u + sin_phi * self.v) - theta.cos() * self.w);
+    }
+
+    /// The ray through the point (`s`, `t`) of the image, both in [0, 1] from the
+    /// lower left corner.
+    ///
+    /// # Returns
+    ///
+    /// The ray, or `None` where the projection covers no directions, outside the image
+    /// circle of a fisheye.
+    pub fn get_ray(self, s : f32, t : f32, sampler : &mut dyn Sampler) -> Option<Ray> {
+        let rd = self.lens_radius * random_in_unit_disk(sampler);
+        let offset = self.u * rd.x + self.v * rd.y;
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + sampler.get_1d() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        };
+
+        let (origin, direction) = match self.projection {
+            Projection::Perspective => (
+                self.origin + offset,
+                self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset),
+            Projection::Orthographic => {
+                let origin = self.lower_left_corner + s * self.horizontal + t * self.vertical;
+                // Aim at the point in focus straight ahead, blurring the rest with the lens.
+                let focus = origin - self.focus_dist * self.w;
+                (origin + offset, focus - origin - offset)
+            },
+            Projection::Fisheye(mapping) => (self.origin, self.fisheye_direction(mapping, 2.0 * s - 1.0, 2.0 * t - 1.0)?),
+            Projection::Equirectangular => {
+                let phi = (s - 0.5) * 2.0 * PI;
+                let theta = (t - 0.5) * PI;
+                (self.origin, theta.cos() * (phi.sin() * self.u - phi.cos() * self.w) + theta.sin() * self.v)
+            }
+        };
+        return Some(Ray::new(&origin, &direction).with_time(time));
+    }
+}
@@ -0,0 +1,533 @@
+use std::fmt;
+
+use crate::camera::Projection;
+use crate::image_writer::{BitDepth, ImageFormat};
+use crate::render;
+use crate::sampler::SamplerKind;
+use crate::scenes::{CameraSettings, RenderSettings, BUILTIN_SCENES};
+use crate::tonemap::{DisplayTransform, ToneMapOperator, Transfer};
+use crate::vec3::Vec3;
+
+pub const USAGE: &str = "Usage: raytracing-rs [options]
+
+Image:
+    --width <pixels>          Width of the image (default 800)
+    --height <pixels>         Height of the image (default 400)
+    --samples <count>         Samples per pixel (default 100)
+    --output <path>           Where to write the image, .ppm, .png, .pfm, .hdr or .exr (default image.ppm)
+    --bit-depth <bits>        Bits per channel: 8 or 16 for .ppm and .png (default 8),
+                              16 (half) or 32 (float) for .exr (default 16)
+    --layers <names>          Extra .exr layers, comma separated: normal, depth
+
+Display:
+    --exposure <stops>        Exposure compensation in EV (default 0)
+    --white-balance <kelvin>  Color temperature of the light to render as white (default: none)
+    --tonemap <name>          clamp, reinhard, reinhard-extended, hable or aces (default clamp)
+    --white-point <value>     Luminance mapped to white by reinhard-extended (default 4)
+    --transfer <curve>        srgb, linear or a gamma value such as 2.2 (default srgb)
+
+    Exposure and white balance apply to every format, .pfm, .hdr and .exr images
+    are otherwise left linear.
+
+Paths:
+    --max-depth <bounces>     Maximum number of bounces per path (default 50)
+    --diffuse-depth <count>   Maximum number of diffuse bounces (default: max depth)
+    --specular-depth <count>  Maximum number of mirror and glossy bounces (default: max depth)
+    --transmission-depth <count>
+                              Maximum number of refractions (default: max depth)
+    --roulette-depth <count>  Bounces before paths may be ended by Russian roulette (default 5)
+
+    Scene files may set the image and path settings, the command line takes precedence.
+
+Scene:
+    --scene <name|file>       Built-in scene (random, spheres, cornell) or a .toml scene file (default random)
+    --seed <value>            Seed for every random decision, same seed same image (default 0)
+    --sampler <name>          independent, stratified, halton or sobol (default sobol)
+    --threads <count>         Worker threads (default: all cores)
+
+Camera, each overrides the scene's own setting:
+    --projection <name>       perspective, orthographic, fisheye-equidistant,
+                              fisheye-equisolid or equirectangular
+    --lookfrom <x,y,z>        Camera position
+    --lookat <x,y,z>          Point the camera faces
+    --vup <x,y,z>             Up direction
+    --vfov <degrees>          Vertical field of view, up to 360 across a fisheye's
+                              image circle, unused by equirectangular
+    --aperture <diameter>     Lens aperture, 0 for a pinhole
+    --focus-dist <distance>   Distance to the plane in focus
+    --shutter <open,close>    Times the shutter is open between, moving objects
+                              blur over them (scenes run from 0 to 1)
+
+    -h, --help                Print this message";
+
+/// Everything the renderer can be configured with from the command line.
+#[derive(Clone)]
+pub struct Options {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub samples: Option<i32>,
+    pub max_depth: Option<i32>,
+    pub diffuse_depth: Option<i32>,
+    pub specular_depth: Option<i32>,
+    pub transmission_depth: Option<i32>,
+    pub roulette_depth: Option<i32>,
+    pub output: String,
+    /// None picks the output format's default.
+    pub bit_depth: Option<BitDepth>,
+    pub layers: Vec<OutputLayer>,
+    pub exposure: f32,
+    pub white_balance: Option<f32>,
+    /// Operator name, resolved by `display_transform` once the white point is known.
+    pub tonemap: String,
+    pub white_point: f32,
+    pub transfer: Transfer,
+    pub scene: String,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub threads: usize,
+    pub projection: Option<Projection>,
+    pub lookfrom: Option<Vec3>,
+    pub lookat: Option<Vec3>,
+    pub vup: Option<Vec3>,
+    pub vfov: Option<f32>,
+    pub aperture: Option<f32>,
+    pub focus_dist: Option<f32>,
+    pub shutter: Option<(f32, f32)>
+}
+
+/// Images rendered next to the color for formats with layers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputLayer {
+    /// World space normal of the first surface hit.
+    Normal,
+    /// Distance from the camera to the first surface hit.
+    Depth
+}
+
+impl OutputLayer {
+    pub fn from_name(name: &str) -> Option<OutputLayer> {
+        match name {
+            "normal" => return Some(OutputLayer::Normal),
+            "depth" => return Some(OutputLayer::Depth),
+            _ => return None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    /// `--help` was passed, not a failure but parsing stops there.
+    HelpRequested,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String, expected: String }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::HelpRequested => write!(f, "{}", USAGE),
+            CliError::UnknownFlag(flag) => write!(f, "Unknown option '{}', see --help for the list of options", flag),
+            CliError::MissingValue(flag) => write!(f, "Option '{}' requires a value", flag),
+            CliError::InvalidValue { flag, value, expected } =>
+                write!(f, "Invalid value '{}' for '{}': expected {}", value, flag, expected)
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        return Options {
+            width: None,
+            height: None,
+            samples: None,
+            max_depth: None,
+            diffuse_depth: None,
+            specular_depth: None,
+            transmission_depth: None,
+            roulette_depth: None,
+            output: String::from("image.ppm"),
+            bit_depth: None,
+            layers: Vec::new(),
+            exposure: 0.0,
+            white_balance: None,
+            tonemap: String::from("clamp"),
+            white_point: 4.0,
+            transfer: Transfer::Srgb,
+            scene: String::from("random"),
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            threads: render::default_thread_count(),
+            projection: None,
+            lookfrom: None,
+            lookat: None,
+            vup: None,
+            vfov: None,
+            aperture: None,
+            focus_dist: None,
+            shutter: None
+        }
+    }
+}
+
+/// The final image settings once the command line, the scene and the defaults are combined.
+#[derive(Clone, Copy)]
+pub struct ImageSettings {
+    pub width: i32,
+    pub height: i32,
+    pub samples: i32,
+    /// Bounces of any kind a path may take.
+    pub max_depth: i32,
+    /// Bounces of each `Lobe` a path may take, on top of `max_depth`.
+    pub diffuse_depth: i32,
+    pub specular_depth: i32,
+    pub transmission_depth: i32,
+    /// Bounces after which paths carrying little energy are randomly ended.
+    pub roulette_depth: i32
+}
+
+impl Options {
+    /// The format of the output file. `parse` only accepts outputs with a known extension.
+    pub fn output_format(&self) -> ImageFormat {
+        return ImageFormat::from_path(&self.output).expect("Output extension checked while parsing");
+    }
+
+    pub fn display_transform(&self) -> DisplayTransform {
+        return DisplayTransform {
+            exposure: self.exposure,
+            white_balance: self.white_balance,
+            operator: ToneMapOperator::from_name(&self.tonemap, self.white_point)
+                .expect("Tone map operator checked while parsing"),
+            transfer: self.transfer
+        }
+    }
+
+    pub fn output_depth(&self) -> BitDepth {
+        return self.bit_depth.unwrap_or_else(|| self.output_format().default_depth());
+    }
+
+    /// Resolves the image settings, preferring the command line over the scene file, and
+    /// rejecting resolutions with more pixels than the framebuffer can index.
+    pub fn image_settings(&self, scene_settings: RenderSettings) -> Result<ImageSettings, CliError> {
+        let width = self.width.or(scene_settings.width).unwrap_or(800);
+        let height = self.height.or(scene_settings.height).unwrap_or(400);
+        if width.checked_mul(height).is_none() {
+            return Err(invalid("--width", &format!("{}x{}", width, height),
+                &format!("a resolution of at most {} pixels", i32::MAX)));
+        }
+
+        let max_depth = self.max_depth.or(scene_settings.max_depth).unwrap_or(50);
+        return Ok(ImageSettings {
+            width,
+            height,
+            samples: self.samples.or(scene_settings.samples).unwrap_or(100),
+            max_depth,
+            diffuse_depth: self.diffuse_depth.or(scene_settings.diffuse_depth).unwrap_or(max_depth),
+            specular_depth: self.specular_depth.or(scene_settings.specular_depth).unwrap_or(max_depth),
+            transmission_depth: self.transmission_depth.or(scene_settings.transmission_depth).unwrap_or(max_depth),
+            roulette_depth: self.roulette_depth.or(scene_settings.roulette_depth).unwrap_or(5)
+        });
+    }
+
+    /// Applies the camera overrides on top of the scene's own camera, rejecting
+    /// combinations which leave the view direction undefined or the field of view out
+    /// of the projection's range.
+    pub fn camera_settings(&self, scene_camera: CameraSettings) -> Result<CameraSettings, CliError> {
+        let settings = CameraSettings {
+            projection: self.projection.unwrap_or(scene_camera.projection),
+            lookfrom: self.lookfrom.unwrap_or(scene_camera.lookfrom),
+            lookat: self.lookat.unwrap_or(scene_camera.lookat),
+            vup: self.vup.unwrap_or(scene_camera.vup),
+            vfov: self.vfov.unwrap_or(scene_camera.vfov),
+            aperture: self.aperture.unwrap_or(scene_camera.aperture),
+            focus_dist: self.focus_dist.unwrap_or(scene_camera.focus_dist),
+            shutter_open: self.shutter.map_or(scene_camera.shutter_open, |(open, _)| open),
+            shutter_close: self.shutter.map_or(scene_camera.shutter_close, |(_, close)| close)
+        };
+
+        let view = settings.lookat - settings.lookfrom;
+        if view.length_sq() == 0.0 {
+            return Err(invalid("--lookat", &format!("{}", settings.lookat), "a point different from --lookfrom"));
+        }
+        if view.unit_vector().cross(settings.vup).length_sq() < 1e-8 {
+            return Err(invalid("--vup", &format!("{}", settings.vup), "a direction not parallel to the view direction"));
+        }
+        if settings.projection != Projection::Equirectangular
+            && !(settings.vfov > 0.0 && settings.vfov <= settings.projection.max_fov()) {
+            return Err(invalid("--vfov", &settings.vfov.to_string(),
+                &format!("an angle between 0 and {} degrees for this projection", settings.projection.max_fov())));
+        }
+
+        return Ok(settings);
+    }
+}
+
+fn invalid(flag: &str, value: &str, expected: &str) -> CliError {
+    return CliError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+        expected: expected.to_string()
+    }
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<i32, CliError> {
+    match value.parse::<i32>() {
+        Ok(v) if v > 0 => return Ok(v),
+        _ => return Err(invalid(flag, value, "a positive integer"))
+    }
+}
+
+fn parse_count(flag: &str, value: &str) -> Result<i32, CliError> {
+    match value.parse::<i32>() {
+        Ok(v) if v >= 0 => return Ok(v),
+        _ => return Err(invalid(flag, value, "a non-negative integer"))
+    }
+}
+
+fn parse_float(flag: &str, value: &str, min: f32, max: f32) -> Result<f32, CliError> {
+    match value.parse::<f32>() {
+        Ok(v) if v.is_finite() && v >= min && v <= max => return Ok(v),
+        _ => return Err(invalid(flag, value, &format!("a number between {} and {}", min, max)))
+    }
+}
+
+fn parse_vec3(flag: &str, value: &str) -> Result<Vec3, CliError> {
+    let parts: Vec<Result<f32, _>> = value.split(',').map(|p| p.trim().parse::<f32>()).collect();
+    match parts.as_slice() {
+        [Ok(x), Ok(y), Ok(z)] if x.is_finite() && y.is_finite() && z.is_finite() => return Ok(Vec3::new(*x, *y, *z)),
+        _ => return Err(invalid(flag, value, "three comma separated numbers, e.g. 13,2,3"))
+    }
+}
+
+fn parse_shutter(flag: &str, value: &str) -> Result<(f32, f32), CliError> {
+    let parts: Vec<Result<f32, _>> = value.split(',').map(|p| p.trim().parse::<f32>()).collect();
+    match parts.as_slice() {
+        [Ok(open), Ok(close)] if (0.0..=1.0).contains(open) && (0.0..=1.0).contains(close) && open <= close => {
+            return Ok((*open, *close));
+        },
+        _ => return Err(invalid(flag, value, "two comma separated times in [0, 1], the first not after the second, e.g. 0,0.5"))
+    }
+}
+
+/// Parses the command line arguments, not including the program name.
+///
+/// # Parameters
+///
+/// - `args` : &[String]
+/// The arguments as passed to the process, after the program name
+///
+/// # Returns
+///
+/// The parsed options or the first problem found with them.
+pub fn parse(args: &[String]) -> Result<Options, CliError> {
+    let mut options = Options::default();
+    let mut i = 0;
+
+    while i < args.len() {
+        let flag = args[i].as_str();
+
+        if flag == "-h" || flag == "--help" {
+            return Err(CliError::HelpRequested);
+        }
+
+        let value = match args.get(i + 1) {
+            Some(value) => value.as_str(),
+            None => {
+                if flag.starts_with('-') && is_known_flag(flag) {
+                    return Err(CliError::MissingValue(flag.to_string()));
+                }
+                return Err(CliError::UnknownFlag(flag.to_string()));
+            }
+        };
+
+        match flag {
+            "--width" => options.width = Some(parse_positive(flag, value)?),
+            "--height" => options.height = Some(parse_positive(flag, value)?),
+            "--samples" => options.samples = Some(parse_positive(flag, value)?),
+            "--max-depth" => options.max_depth = Some(parse_positive(flag, value)?),
+            "--diffuse-depth" => options.diffuse_depth = Some(parse_count(flag, value)?),
+            "--specular-depth" => options.specular_depth = Some(parse_count(flag, value)?),
+            "--transmission-depth" => options.transmission_depth = Some(parse_count(flag, value)?),
+            "--roulette-depth" => options.roulette_depth = Some(parse_count(flag, value)?),
+            "--output" => {
+                if ImageFormat::from_path(value).is_none() {
+                    return Err(invalid(flag, value, "a file name ending in .ppm, .png, .pfm, .hdr or .exr"));
+                }
+                options.output = value.to_string();
+            },
+            "--bit-depth" => options.bit_depth = Some(value.parse().ok().and_then(BitDepth::from_bits)
+                .ok_or_else(|| invalid(flag, value, "8, 16 or 32"))?),
+            "--exposure" => options.exposure = parse_float(flag, value, -64.0, 64.0)?,
+            "--white-balance" => options.white_balance = Some(parse_float(flag, value, 1667.0, 25000.0)?),
+            "--tonemap" => {
+                if ToneMapOperator::from_name(value, 1.0).is_none() {
+                    return Err(invalid(flag, value, "clamp, reinhard, reinhard-extended, hable or aces"));
+                }
+                options.tonemap = value.to_string();
+            },
+            "--white-point" => options.white_point = parse_float(flag, value, 0.0001, f32::MAX)?,
+            "--transfer" => options.transfer = Transfer::from_name(value)
+                .ok_or_else(|| invalid(flag, value, "srgb, linear or a positive gamma value"))?,
+            "--layers" => options.layers = value.split(',')
+                .map(|name| OutputLayer::from_name(name.trim()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| invalid(flag, value, "a comma separated list of normal and depth"))?,
+            "--scene" => {
+                if !BUILTIN_SCENES.contains(&value) && !value.ends_with(".toml") {
+                    return Err(invalid(flag, value, &format!("one of {} or a .toml scene file", BUILTIN_SCENES.join(", "))));
+                }
+                options.scene = value.to_string();
+            },
+            "--seed" => options.seed = value.parse()
+                .map_err(|_| invalid(flag, value, "a non-negative integer"))?,
+            "--sampler" => options.sampler = SamplerKind::from_name(value)
+                .ok_or_else(|| invalid(flag, value, "independent, stratified, halton or sobol"))?,
+            "--threads" => options.threads = parse_positive(flag, value)? as usize,
+            "--lookfrom" => options.lookfrom = Some(parse_vec3(flag, value)?),
+            "--lookat" => options.lookat = Some(parse_vec3(flag, value)?),
+            "--vup" => options.vup = Some(parse_vec3(flag, value)?),
+            "--projection" => options.projection = Some(Projection::from_name(value)
+                .ok_or_else(|| invalid(flag, value, "perspective, orthographic, fisheye-equidistant, fisheye-equisolid or equirectangular"))?),
+            "--vfov" => options.vfov = Some(parse_float(flag, value, 0.01, 360.0)?),
+            "--aperture" => options.aperture = Some(parse_float(flag, value, 0.0, f32::MAX)?),
+            "--focus-dist" => options.focus_dist = Some(parse_float(flag, value, 0.0001, f32::MAX)?),
+            "--shutter" => options.shutter = Some(parse_shutter(flag, value)?),
+            _ => return Err(CliError::UnknownFlag(flag.to_string()))
+        }
+
+        i += 2;
+    }
+
+    let format = options.output_format();
+    if let Some(depth) = options.bit_depth {
+        if !format.supports(depth) {
+            return Err(invalid("--bit-depth", &depth.bits().to_string(), "a depth the output format supports, see --help"));
+        }
+    }
+    if !options.layers.is_empty() && format != ImageFormat::OpenExr {
+        return Err(invalid("--layers", &options.output, "an .exr output, only OpenEXR images hold layers"));
+    }
+
+    return Ok(options);
+}
+
+fn is_known_flag(flag: &str) -> bool {
+    return USAGE.split_whitespace().any(|word| word == flag);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Options, CliError> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        return parse(&args);
+    }
+
+    fn parse_error(args: &[&str]) -> CliError {
+        return parse_args(args).err().expect("the arguments should be rejected");
+    }
+
+    fn is_invalid(err: &CliError, expected_flag: &str) -> bool {
+        return matches!(err, CliError::InvalidValue { flag, .. } if flag == expected_flag);
+    }
+
+    fn scene_camera() -> CameraSettings {
+        return CameraSettings {
+            projection: Projection::Perspective,
+            lookfrom: Vec3::new(0.0, 0.0, 5.0),
+            lookat: Vec3::zero(),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 40.0,
+            aperture: 0.0,
+            focus_dist: 5.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0
+        }
+    }
+
+    #[test]
+    fn parses_every_kind_of_value() {
+        let options = parse_args(&[
+            "--width", "320", "--height", "200", "--diffuse-depth", "0", "--output", "out.exr",
+            "--bit-depth", "32", "--layers", "normal, depth", "--sampler", "halton", "--seed", "7",
+            "--lookfrom", "1, 2,3", "--projection", "fisheye-equisolid", "--vfov", "180", "--shutter", "0.25,0.5"
+        ]).ok().unwrap();
+
+        assert_eq!((options.width, options.height, options.diffuse_depth), (Some(320), Some(200), Some(0)));
+        assert_eq!(options.output_format(), ImageFormat::OpenExr);
+        assert_eq!(options.output_depth(), BitDepth::ThirtyTwo);
+        assert_eq!(options.layers, [OutputLayer::Normal, OutputLayer::Depth]);
+        assert_eq!((options.sampler, options.seed), (SamplerKind::Halton, 7));
+        assert_eq!(options.projection, Some(Projection::Fisheye(crate::camera::FisheyeMapping::Equisolid)));
+        assert_eq!(options.vfov, Some(180.0));
+        assert_eq!(options.shutter, Some((0.25, 0.5)));
+
+        let lookfrom = options.lookfrom.unwrap();
+        assert_eq!([lookfrom.x, lookfrom.y, lookfrom.z], [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn unknown_flags_and_missing_values() {
+        assert_eq!(parse_error(&["--help"]), CliError::HelpRequested);
+        assert_eq!(parse_error(&["--colour", "red"]), CliError::UnknownFlag(String::from("--colour")));
+        assert_eq!(parse_error(&["--width"]), CliError::MissingValue(String::from("--width")));
+        assert_eq!(parse_error(&["render.ppm"]), CliError::UnknownFlag(String::from("render.ppm")));
+    }
+
+    #[test]
+    fn out_of_range_values_name_their_flag() {
+        assert!(is_invalid(&parse_error(&["--width", "0"]), "--width"));
+        assert!(is_invalid(&parse_error(&["--samples", "-4"]), "--samples"));
+        assert!(is_invalid(&parse_error(&["--height", "tall"]), "--height"));
+        assert!(is_invalid(&parse_error(&["--specular-depth", "-1"]), "--specular-depth"));
+        assert!(is_invalid(&parse_error(&["--exposure", "NaN"]), "--exposure"));
+        assert!(is_invalid(&parse_error(&["--output", "image.jpg"]), "--output"));
+        assert!(is_invalid(&parse_error(&["--lookat", "1,2"]), "--lookat"));
+        assert!(is_invalid(&parse_error(&["--scene", "moon"]), "--scene"));
+        assert!(is_invalid(&parse_error(&["--sampler", "poisson"]), "--sampler"));
+    }
+
+    #[test]
+    fn shutter_times_must_be_ordered_within_the_scene() {
+        assert!(is_invalid(&parse_error(&["--shutter", "0,2"]), "--shutter"));
+        assert!(is_invalid(&parse_error(&["--shutter", "-0.5,0.5"]), "--shutter"));
+        assert!(is_invalid(&parse_error(&["--shutter", "0.5,0.25"]), "--shutter"));
+        assert!(is_invalid(&parse_error(&["--shutter", "0.5"]), "--shutter"));
+        assert_eq!(parse_args(&["--shutter", "1,1"]).ok().unwrap().shutter, Some((1.0, 1.0)));
+    }
+
+    #[test]
+    fn options_must_suit_the_output_format() {
+        assert!(is_invalid(&parse_error(&["--output", "image.png", "--bit-depth", "32"]), "--bit-depth"));
+        assert!(is_invalid(&parse_error(&["--bit-depth", "12"]), "--bit-depth"));
+        assert!(is_invalid(&parse_error(&["--layers", "depth"]), "--layers"));
+    }
+
+    #[test]
+    fn image_settings_prefer_the_command_line_and_fit_the_framebuffer() {
+        let scene = RenderSettings { width: Some(640), samples: Some(16), ..RenderSettings::default() };
+        let image = parse_args(&["--samples", "4"]).ok().unwrap().image_settings(scene).ok().unwrap();
+        assert_eq!((image.width, image.height, image.samples), (640, 400, 4));
+
+        let options = parse_args(&["--width", "65536", "--height", "65536"]).ok().unwrap();
+        assert!(is_invalid(&options.image_settings(RenderSettings::default()).err().unwrap(), "--width"));
+        let options = parse_args(&["--width", "65536", "--height", "32767"]).ok().unwrap();
+        assert!(options.image_settings(RenderSettings::default()).is_ok());
+    }
+
+    #[test]
+    fn camera_overrides_are_checked_together() {
+        let same_point = parse_args(&["--lookat", "0,0,5"]).ok().unwrap();
+        assert!(is_invalid(&same_point.camera_settings(scene_camera()).err().unwrap(), "--lookat"));
+
+        let parallel_up = parse_args(&["--vup", "0,0,1"]).ok().unwrap();
+        assert!(is_invalid(&parallel_up.camera_settings(scene_camera()).err().unwrap(), "--vup"));
+
+        // 360 degrees only suits a fisheye.
+        let wide = parse_args(&["--vfov", "360"]).ok().unwrap();
+        assert!(is_invalid(&wide.camera_settings(scene_camera()).err().unwrap(), "--vfov"));
+        let fisheye = parse_args(&["--vfov", "360", "--projection", "fisheye-equidistant"]).ok().unwrap();
+        assert_eq!(fisheye.camera_settings(scene_camera()).ok().unwrap().vfov, 360.0);
+    }
+}
@@ -0,0 +1,409 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use crate::image_writer::ImageFormat;
+use crate::render::Framebuffer;
+use crate::tonemap::srgb_to_linear;
+use crate::vec3::Vec3;
+
+#[derive(Debug)]
+pub struct ReadError {
+    pub path: String,
+    pub message: String
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unable to read image '{}': {}", self.path, self.message)
+    }
+}
+
+/// Reads the image stored at `path` in the format its extension names.
+///
+/// # Parameters
+///
+/// - `path` : &str
+/// The file to read, a `.png`, `.ppm` or `.hdr` image
+///
+/// # Returns
+///
+/// The image as linear values with rows stored top to bottom. PNG and PPM files are
+/// taken to be sRGB encoded and are decoded, Radiance files are already linear.
+pub fn load(path: &str) -> Result<Framebuffer, ReadError> {
+    let error = |message: String| ReadError { path: path.to_string(), message };
+
+    match ImageFormat::from_path(path) {
+        Some(ImageFormat::Png) => {
+            let file = File::open(path).map_err(|e| error(e.to_string()))?;
+            return read_png(BufReader::new(file)).map_err(error);
+        },
+        Some(ImageFormat::Ppm) => {
+            let bytes = fs::read(path).map_err(|e| error(e.to_string()))?;
+            return read_ppm(&bytes).map_err(error);
+        },
+        Some(ImageFormat::Hdr) => {
+            let bytes = fs::read(path).map_err(|e| error(e.to_string()))?;
+            return read_hdr(&bytes).map_err(error);
+        },
+        _ => return Err(error(String::from("unsupported format, expected .png, .ppm or .hdr")))
+    }
+}
+
+fn read_png<R: std::io::Read>(reader: R) -> Result<Framebuffer, String> {
+    let mut decoder = png::Decoder::new(reader);
+    // Palettes, low bit depths and transparency keys all expand to plain 8 bit samples.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return Err(String::from("palette was not expanded"))
+    };
+    let sixteen_bit = info.bit_depth == png::BitDepth::Sixteen;
+
+    let sample = |row: &[u8], index: usize| -> f32 {
+        if sixteen_bit {
+            return u16::from_be_bytes([row[2 * index], row[2 * index + 1]]) as f32 / 65535.0;
+        }
+        return row[index] as f32 / 255.0;
+    };
+
+    let mut framebuffer = Framebuffer::new(info.width as i32, info.height as i32);
+    for y in 0..info.height as usize {
+        let row = &data[y * info.line_size..(y + 1) * info.line_size];
+        for x in 0..info.width as usize {
+            let base = x * channels;
+            // Alpha is ignored, gray replicates to all three channels.
+            let (r, g, b) = if channels < 3 {
+                let gray = sample(row, base);
+                (gray, gray, gray)
+            } else {
+                (sample(row, base), sample(row, base + 1), sample(row, base + 2))
+            };
+            framebuffer.set(x as i32, y as i32, Vec3::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)));
+        }
+    }
+    return Ok(framebuffer);
+}
+
+/// The most pixels an image header may claim, which keeps a corrupt header from asking
+/// for more memory than any texture needs.
+const MAX_PIXELS: usize = 1 << 28;
+
+/// Checks the size read from an image header.
+///
+/// # Returns
+///
+/// The number of pixels, or a description of the problem when either side is zero or
+/// the image is too large.
+fn check_size(width: usize, height: usize) -> Result<usize, String> {
+    return width.checked_mul(height)
+        .filter(|&pixels| width > 0 && height > 0 && pixels <= MAX_PIXELS)
+        .ok_or_else(|| format!("invalid size {}x{}", width, height));
+}
+
+/// Reads the whitespace separated header fields of a PNM file, skipping comments.
+struct PnmHeader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> PnmHeader<'a> {
+    fn next_token(&mut self) -> Option<&'a str> {
+        loop {
+            while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            }
+            if self.position < self.bytes.len() && self.bytes[self.position] == b'#' {
+                while self.position < self.bytes.len() && self.bytes[self.position] != b'\n' {
+                    self.position += 1;
+                }
+                continue;
+            }
+            break;
+        }
+
+        let start = self.position;
+        while self.position < self.bytes.len() && !self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        if start == self.position {
+            return None;
+        }
+        return std::str::from_utf8(&self.bytes[start..self.position]).ok();
+    }
+
+    fn next_number(&mut self, name: &str) -> Result<u32, String> {
+        let token = self.next_token().ok_or_else(|| format!("missing {}", name))?;
+        return token.parse().map_err(|_| format!("invalid {} '{}'", name, token));
+    }
+}
+
+/// Reads an ASCII (P3) or binary (P6) PPM with up to 16 bits per sample.
+fn read_ppm(bytes: &[u8]) -> Result<Framebuffer, String> {
+    let mut header = PnmHeader { bytes, position: 0 };
+    let binary = match header.next_token() {
+        Some("P6") => true,
+        Some("P3") => false,
+        _ => return Err(String::from("not a P3 or P6 PPM file"))
+    };
+    let width = header.next_number("width")?;
+    let height = header.next_number("height")?;
+    let max_value = header.next_number("maximum value")?;
+    let pixels = check_size(width as usize, height as usize)?;
+    if !(1..=65535).contains(&max_value) {
+        return Err(format!("invalid maximum value {}", max_value));
+    }
+
+    let count = pixels * 3;
+    // Every sample takes at least a byte, so a short file cannot reserve a huge buffer.
+    let mut samples = Vec::with_capacity(count.min(bytes.len()));
+    if binary {
+        // A single whitespace byte separates the header from the samples.
+        let start = header.position + 1;
+        let size = if max_value > 255 { 2 } else { 1 };
+        let data = bytes.get(start..start + count * size).ok_or("truncated pixel data")?;
+        for chunk in data.chunks_exact(size) {
+            samples.push(if size == 2 { u16::from_be_bytes([chunk[0], chunk[1]]) as u32 } else { chunk[0] as u32 });
+        }
+    } else {
+        for _ in 0..count {
+            samples.push(header.next_number("sample")?);
+        }
+    }
+
+    let mut framebuffer = Framebuffer::new(width as i32, height as i32);
+    for (pixel, rgb) in framebuffer.pixels.iter_mut().zip(samples.chunks_exact(3)) {
+        let decode = |value: u32| srgb_to_linear(value.min(max_value) as f32 / max_value as f32);
+        *pixel = Vec3::new(decode(rgb[0]), decode(rgb[1]), decode(rgb[2]));
+    }
+    return Ok(framebuffer);
+}
+
+/// Reads a Radiance RGBE file with the usual `-Y height +X width` orientation, in flat,
+/// old style run length or adaptive run length encoded scanlines.
+fn read_hdr(bytes: &[u8]) -> Result<Framebuffer, String> {
+    if !bytes.starts_with(b"#?") {
+        return Err(String::from("not a Radiance file"));
+    }
+
+    // The header ends at an empty line, the resolution line follows it.
+    let mut position = 0;
+    let mut lines = Vec::new();
+    while lines.last() != Some(&"") || lines.len() < 2 {
+        let end = bytes[position..].iter().position(|&b| b == b'\n')
+            .ok_or("truncated header")? + position;
+        let line = std::str::from_utf8(&bytes[position..end]).map_err(|_| "invalid header")?;
+        lines.push(line.trim_end_matches('\r'));
+        position = end + 1;
+    }
+    if let Some(format) = lines.iter().find_map(|line| line.strip_prefix("FORMAT=")) {
+        if format != "32-bit_rle_rgbe" {
+            return Err(format!("unsupported pixel format '{}'", format));
+        }
+    }
+
+    let end = bytes[position..].iter().position(|&b| b == b'\n').ok_or("missing resolution")? + position;
+    let resolution = std::str::from_utf8(&bytes[position..end]).map_err(|_| "invalid resolution")?;
+    position = end + 1;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match fields.as_slice() {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>().map_err(|_| format!("invalid height '{}'", h))?,
+            w.parse::<usize>().map_err(|_| format!("invalid width '{}'", w))?),
+        _ => return Err(format!("unsupported orientation '{}'", resolution))
+    };
+    check_size(width, height)?;
+
+    let mut framebuffer = Framebuffer::new(width as i32, height as i32);
+    let mut scanline = vec![[0u8; 4]; width];
+    let mut read = |count: usize| -> Result<&[u8], String> {
+        let data = bytes.get(position..position + count).ok_or("truncated pixel data")?;
+        position += count;
+        return Ok(data);
+    };
+
+    for y in 0..height {
+        let start = read(4)?;
+        let adaptive = (8..32768).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0;
+
+        if adaptive {
+            if ((start[2] as usize) << 8 | start[3] as usize) != width {
+                return Err(String::from("scanline length does not match the width"));
+            }
+            // Each channel is stored separately as runs and literal spans.
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let code = read(1)?[0] as usize;
+                    let (count, run) = if code > 128 { (code - 128, true) } else { (code, false) };
+                    if count == 0 || x + count > width {
+                        return Err(String::from("corrupt run length encoding"));
+                    }
+                    if run {
+                        let value = read(1)?[0];
+                        for pixel in &mut scanline[x..x + count] {
+                            pixel[channel] = value;
+                        }
+                    } else {
+                        for (pixel, &value) in scanline[x..x + count].iter_mut().zip(read(count)?) {
+                            pixel[channel] = value;
+                        }
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            let mut x = 0;
+            let mut next = [start[0], start[1], start[2], start[3]];
+            let mut shift = 0u32;
+            loop {
+                // Old style runs repeat the previous pixel, consecutive runs form
+                // higher digits of the count.
+                if next[0] == 1 && next[1] == 1 && next[2] == 1 {
+                    if x == 0 {
+                        return Err(String::from("run without a pixel to repeat"));
+                    }
+                    // Runs longer than the rest of the scanline are corrupt, which also
+                    // stops a chain of empty runs from shifting the count out of range.
+                    let count = match (next[3] as usize).checked_shl(shift) {
+                        Some(count) if shift < usize::BITS - 8 && x + count <= width => count,
+                        _ => return Err(String::from("corrupt run length encoding"))
+                    };
+                    let previous = scanline[x - 1];
+                    for pixel in &mut scanline[x..x + count] {
+                        *pixel = previous;
+                    }
+                    x += count;
+                    shift += 8;
+                } else {
+                    scanline[x] = next;
+                    x += 1;
+                    shift = 0;
+                }
+                if x == width {
+                    break;
+                }
+                let data = read(4)?;
+                next = [data[0], data[1], data[2], data[3]];
+            }
+        }
+
+        for (x, rgbe) in scanline.iter().enumerate() {
+            framebuffer.set(x as i32, y as i32, from_rgbe(*rgbe));
+        }
+    }
+    return Ok(framebuffer);
+}
+
+fn from_rgbe(rgbe: [u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::zero();
+    }
+    // Mantissas are stored as m / 256 with the shared exponent biased by 128.
+    let scale = 2f32.powi(rgbe[3] as i32 - 136);
+    return Vec3::new(rgbe[0] as f32 * scale, rgbe[1] as f32 * scale, rgbe[2] as f32 * scale);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_writer::{self, BitDepth};
+
+    /// The bytes `image_writer` saves for `framebuffer`, in the format of the extension.
+    fn saved(name: &str, framebuffer: &Framebuffer, depth: BitDepth) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("raytracing-image-{}-{}", std::process::id(), name));
+        let path = path.to_str().unwrap();
+        image_writer::save(path, framebuffer, depth, &[]).unwrap();
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        return bytes;
+    }
+
+    /// Values in [0, 1] with runs of equal pixels along the bottom half of the rows.
+    fn gradient(width: i32, height: i32) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let s = if x < width / 2 { 0.25 } else { x as f32 / width as f32 };
+                framebuffer.set(x, y, Vec3::new(s, y as f32 / height as f32, 0.5));
+            }
+        }
+        return framebuffer;
+    }
+
+    fn assert_pixels(read: &Framebuffer, expected: &Framebuffer, decode: impl Fn(f32) -> f32, tolerance: f32) {
+        assert_eq!((read.width, read.height), (expected.width, expected.height));
+        for (a, b) in read.pixels.iter().zip(expected.pixels.iter()) {
+            for axis in 0..3 {
+                assert!((a[axis] - decode(b[axis])).abs() <= tolerance, "{} against {}", a[axis], decode(b[axis]));
+            }
+        }
+    }
+
+    #[test]
+    fn binary_ppm_round_trips() {
+        let image = gradient(5, 3);
+        let read = read_ppm(&saved("8.ppm", &image, BitDepth::Eight)).unwrap();
+        assert_pixels(&read, &image, srgb_to_linear, 1e-2);
+
+        let read = read_ppm(&saved("16.ppm", &image, BitDepth::Sixteen)).unwrap();
+        assert_pixels(&read, &image, srgb_to_linear, 1e-4);
+    }
+
+    #[test]
+    fn ascii_ppm_with_comments() {
+        let read = read_ppm(b"P3\n# two pixels\n2 1\n255\n255 0 0\n0 128 255\n").unwrap();
+        assert_eq!((read.width, read.height), (2, 1));
+        assert_eq!([read.pixels[0].x, read.pixels[0].y, read.pixels[0].z], [1.0, 0.0, 0.0]);
+        assert_eq!(read.pixels[1].y, srgb_to_linear(128.0 / 255.0));
+        assert_eq!(read.pixels[1].z, 1.0);
+    }
+
+    #[test]
+    fn radiance_round_trips_run_length_encoded_and_flat() {
+        // Widths from 8 are run length encoded, narrower ones are stored flat.
+        for width in [16, 4] {
+            let image = gradient(width, 3);
+            let bytes = saved(&format!("{}.hdr", width), &image, BitDepth::ThirtyTwo);
+            let read = read_hdr(&bytes).unwrap();
+            // RGBE keeps 8 bits of mantissa for the largest component.
+            assert_pixels(&read, &image, |value| value, 1.0 / 128.0);
+        }
+    }
+
+    #[test]
+    fn radiance_old_style_runs_repeat_the_previous_pixel() {
+        let mut bytes = b"#?RADIANCE\n\n-Y 1 +X 5\n".to_vec();
+        bytes.extend_from_slice(&[128, 64, 32, 129, 1, 1, 1, 3, 0, 0, 0, 0]);
+        let read = read_hdr(&bytes).unwrap();
+        for pixel in &read.pixels[..4] {
+            assert_eq!([pixel.x, pixel.y, pixel.z], [1.0, 0.5, 0.25]);
+        }
+        assert_eq!(read.pixels[4].x, 0.0);
+    }
+
+    #[test]
+    fn malformed_headers_are_errors() {
+        assert!(read_ppm(b"P6\n4294967295 4294967295\n255\n").is_err());
+        assert!(read_ppm(b"P6\n0 4\n255\n").is_err());
+        assert!(read_ppm(b"P6\n2 2\n255\n\x00\x00\x00").is_err());
+        assert!(read_hdr(b"#?RADIANCE\n\n-Y 1 +X 0\n\x01\x02\x03\x04").is_err());
+        assert!(read_hdr(b"#?RADIANCE\n\n-Y 99999999999 +X 99999999999\n").is_err());
+
+        // Chains of empty runs keep shifting the count up.
+        let mut bytes = b"#?RADIANCE\n\n-Y 1 +X 4\n".to_vec();
+        bytes.extend_from_slice(&[128, 64, 32, 129]);
+        for _ in 0..10 {
+            bytes.extend_from_slice(&[1, 1, 1, 0]);
+        }
+        assert!(read_hdr(&bytes).is_err());
+    }
+}
@@ -0,0 +1,532 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::render::Framebuffer;
+use crate::vec3::Vec3;
+
+/// The file formats the renderer can write, chosen from the output file's extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// Binary PPM (P6).
+    Ppm,
+    Png,
+    /// Portable float map, the floating point sibling of PPM.
+    Pfm,
+    /// Radiance RGBE.
+    Hdr,
+    OpenExr
+}
+
+/// Bits per channel. The integer formats use 8 or 16, OpenEXR stores 16 bit half or
+/// 32 bit floats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+    ThirtyTwo
+}
+
+impl ImageFormat {
+    /// Picks the format matching the extension of `path`, ignoring case.
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => return Some(ImageFormat::Ppm),
+            "png" => return Some(ImageFormat::Png),
+            "pfm" => return Some(ImageFormat::Pfm),
+            "hdr" => return Some(ImageFormat::Hdr),
+            "exr" => return Some(ImageFormat::OpenExr),
+            _ => return None
+        }
+    }
+
+    pub fn supports(self, depth: BitDepth) -> bool {
+        match self {
+            ImageFormat::Ppm | ImageFormat::Png => return depth != BitDepth::ThirtyTwo,
+            ImageFormat::Pfm | ImageFormat::Hdr => return depth == BitDepth::ThirtyTwo,
+            ImageFormat::OpenExr => return depth != BitDepth::Eight
+        }
+    }
+
+    /// Whether the format stores floating point values, which are written linear
+    /// rather than tone mapped.
+    pub fn is_high_dynamic_range(self) -> bool {
+        return matches!(self, ImageFormat::Pfm | ImageFormat::Hdr | ImageFormat::OpenExr);
+    }
+
+    /// The depth used when none is asked for: 8 bits for the integer formats and half
+    /// floats for OpenEXR.
+    pub fn default_depth(self) -> BitDepth {
+        match self {
+            ImageFormat::Ppm | ImageFormat::Png => return BitDepth::Eight,
+            ImageFormat::Pfm | ImageFormat::Hdr => return BitDepth::ThirtyTwo,
+            ImageFormat::OpenExr => return BitDepth::Sixteen
+        }
+    }
+}
+
+impl BitDepth {
+    pub fn from_bits(bits: u32) -> Option<BitDepth> {
+        match bits {
+            8 => return Some(BitDepth::Eight),
+            16 => return Some(BitDepth::Sixteen),
+            32 => return Some(BitDepth::ThirtyTwo),
+            _ => return None
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            BitDepth::Eight => return 8,
+            BitDepth::Sixteen => return 16,
+            BitDepth::ThirtyTwo => return 32
+        }
+    }
+
+    fn max_value(self) -> f32 {
+        match self {
+            BitDepth::Eight => return 255.0,
+            _ => return 65535.0
+        }
+    }
+}
+
+/// An extra image stored next to the color in formats with layers, such as surface
+/// normals or depth. `channels` names the components of each pixel that are written,
+/// in x, y, z order.
+pub struct Layer<'a> {
+    pub name: &'a str,
+    pub channels: &'a [&'a str],
+    pub framebuffer: &'a Framebuffer
+}
+
+#[derive(Debug)]
+pub struct ImageError {
+    pub path: String,
+    pub message: String
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unable to write image '{}': {}", self.path, self.message)
+    }
+}
+
+/// Rounds an encoded value in [0, 1] to the nearest integer code of the given depth.
+fn quantize(value: f32, depth: BitDepth) -> u16 {
+    // NaN from a degenerate sample ends up black rather than poisoning the cast.
+    let clamped = if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+    return (clamped * depth.max_value() + 0.5) as u16;
+}
+
+fn quantize_pixel(pixel: Vec3, depth: BitDepth) -> [u16; 3] {
+    return [quantize(pixel.x, depth), quantize(pixel.y, depth), quantize(pixel.z, depth)];
+}
+
+/// Writes the framebuffer to `path` in the format its extension asks for.
+///
+/// # Parameters
+///
+/// - `path` : &str
+/// Where to write the image, its extension selects the format
+/// - `framebuffer` : &Framebuffer
+/// The pixel values, stored unchanged by the floating point formats. The integer
+/// formats expect values already tone mapped and encoded for display, see
+/// `DisplayTransform::display`.
+/// - `depth` : BitDepth
+/// Bits per channel, must be supported by the format
+/// - `layers` : &[Layer]
+/// Extra images, only OpenEXR can store them
+pub fn save(path: &str, framebuffer: &Framebuffer, depth: BitDepth, layers: &[Layer]) -> Result<(), ImageError> {
+    let error = |message: String| ImageError { path: path.to_string(), message };
+
+    let format = ImageFormat::from_path(path)
+        .ok_or_else(|| error(String::from("unknown file extension, expected .ppm, .png, .pfm, .hdr or .exr")))?;
+    if !format.supports(depth) {
+        return Err(error(format!("{} bits per channel are not supported by this format", depth.bits())));
+    }
+    if !layers.is_empty() && format != ImageFormat::OpenExr {
+        return Err(error(String::from("only OpenEXR images can hold extra layers")));
+    }
+
+    let file = File::create(path).map_err(|e| error(e.to_string()))?;
+    let mut writer = BufWriter::new(file);
+
+    let result = match format {
+        ImageFormat::Ppm => write_ppm(&mut writer, framebuffer, depth).map_err(|e| e.to_string()),
+        ImageFormat::Png => write_png(&mut writer, framebuffer, depth),
+        ImageFormat::Pfm => write_pfm(&mut writer, framebuffer).map_err(|e| e.to_string()),
+        ImageFormat::Hdr => write_hdr(&mut writer, framebuffer).map_err(|e| e.to_string()),
+        ImageFormat::OpenExr => write_exr(&mut writer, framebuffer, depth, layers).map_err(|e| e.to_string())
+    };
+    result.map_err(error)?;
+
+    return writer.flush().map_err(|e| error(e.to_string()));
+}
+
+/// Binary PPM (P6). 16 bit samples are stored big endian as the format requires.
+fn write_ppm<W: Write>(writer: &mut W, framebuffer: &Framebuffer, depth: BitDepth) -> std::io::Result<()> {
+    write!(writer, "P6\n{} {}\n{}\n", framebuffer.width, framebuffer.height, depth.max_value() as u32)?;
+    return writer.write_all(&integer_samples(framebuffer, depth));
+}
+
+/// RGB PNG.
+fn write_png<W: Write>(writer: &mut W, framebuffer: &Framebuffer, depth: BitDepth) -> Result<(), String> {
+    let mut encoder = png::Encoder::new(writer, framebuffer.width as u32, framebuffer.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(match depth {
+        BitDepth::Eight => png::BitDepth::Eight,
+        _ => png::BitDepth::Sixteen
+    });
+
+    let mut png_writer = encoder.write_header().map_err(|e| e.to_string())?;
+    png_writer.write_image_data(&integer_samples(framebuffer, depth)).map_err(|e| e.to_string())?;
+    return png_writer.finish().map_err(|e| e.to_string());
+}
+
+/// The framebuffer as interleaved RGB samples of one or two big endian bytes.
+fn integer_samples(framebuffer: &Framebuffer, depth: BitDepth) -> Vec<u8> {
+    let bytes = if depth == BitDepth::Eight { 1 } else { 2 };
+    let mut data = Vec::with_capacity(framebuffer.pixels.len() * 3 * bytes);
+    for pixel in &framebuffer.pixels {
+        for value in quantize_pixel(*pixel, depth) {
+            match depth {
+                BitDepth::Eight => data.push(value as u8),
+                _ => data.extend_from_slice(&value.to_be_bytes())
+            }
+        }
+    }
+    return data;
+}
+
+/// Portable float map: little endian floats, signalled by the negative scale, with the
+/// rows stored bottom to top.
+fn write_pfm<W: Write>(writer: &mut W, framebuffer: &Framebuffer) -> std::io::Result<()> {
+    write!(writer, "PF\n{} {}\n-1.0\n", framebuffer.width, framebuffer.height)?;
+
+    let width = framebuffer.width as usize;
+    for row in framebuffer.pixels.chunks(width).rev() {
+        for pixel in row {
+            for value in [pixel.x, pixel.y, pixel.z] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// Radiance HDR with run length encoded RGBE scanlines.
+fn write_hdr<W: Write>(writer: &mut W, framebuffer: &Framebuffer) -> std::io::Result<()> {
+    write!(writer, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", framebuffer.height, framebuffer.width)?;
+
+    let width = framebuffer.width as usize;
+    for row in framebuffer.pixels.chunks(width) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(|pixel| to_rgbe(*pixel)).collect();
+
+        // The run length encoding can only describe widths between 8 and 32767.
+        if !(8..=0x7fff).contains(&width) {
+            for pixel in &rgbe {
+                writer.write_all(pixel)?;
+            }
+            continue;
+        }
+
+        writer.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for channel in 0..4 {
+            let values: Vec<u8> = rgbe.iter().map(|pixel| pixel[channel]).collect();
+            write_rle_channel(writer, &values)?;
+        }
+    }
+    return Ok(());
+}
+
+/// Shared exponent encoding: the largest component sets the exponent and the three
+/// mantissas keep 8 bits each.
+fn to_rgbe(pixel: Vec3) -> [u8; 4] {
+    let r = pixel.x.max(0.0);
+    let g = pixel.y.max(0.0);
+    let b = pixel.z.max(0.0);
+    let v = r.max(g).max(b);
+
+    if v < 1e-32 || !v.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // v = mantissa * 2^exponent with the mantissa in [0.5, 1).
+    let exponent = v.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exponent);
+    return [
+        (r * scale).min(255.0) as u8,
+        (g * scale).min(255.0) as u8,
+        (b * scale).min(255.0) as u8,
+        (exponent + 128) as u8
+    ];
+}
+
+/// Encodes one channel of a scanline as runs of up to 127 equal bytes, marked by a
+/// count above 128, and literal dumps of up to 128 bytes otherwise.
+fn write_rle_channel<W: Write>(writer: &mut W, values: &[u8]) -> std::io::Result<()> {
+    const MIN_RUN: usize = 4;
+    let mut current = 0;
+
+    while current < values.len() {
+        // Find the next run long enough to be worth encoding.
+        let mut run_start = current;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = 1;
+            while run_length < 127 && run_start + run_length < values.len()
+                && values[run_start + run_length] == values[run_start] {
+                run_length += 1;
+            }
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+
+        // Everything before the run is dumped literally.
+        while current < run_start {
+            let count = (run_start - current).min(128);
+            writer.write_all(&[count as u8])?;
+            writer.write_all(&values[current..current + count])?;
+            current += count;
+        }
+
+        if run_length >= MIN_RUN {
+            writer.write_all(&[128 + run_length as u8, values[run_start]])?;
+            current = run_start + run_length;
+        }
+    }
+    return Ok(());
+}
+
+/// Converts to IEEE 754 half precision, rounding to nearest even. Values too large
+/// become infinity and values too small flush through the subnormals to zero.
+fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN keeps a set mantissa bit.
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        if half_exponent < -10 {
+            return sign;
+        }
+        // Subnormal: shift the mantissa, with its implicit leading one, into place.
+        let full = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half_mantissa = full >> shift;
+        let remainder = full & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = remainder > halfway || (remainder == halfway && half_mantissa & 1 == 1);
+        return sign | (half_mantissa + round_up as u32) as u16;
+    }
+
+    let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round_up = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    // A carry out of the mantissa correctly bumps the exponent, up to infinity.
+    return sign | (half + round_up as u32) as u16;
+}
+
+/// One channel of an OpenEXR file and where its values come from.
+struct ExrChannel<'a> {
+    name: String,
+    framebuffer: &'a Framebuffer,
+    component: usize
+}
+
+fn write_attribute<W: Write>(writer: &mut W, name: &str, kind: &str, value: &[u8]) -> std::io::Result<()> {
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(kind.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(&(value.len() as i32).to_le_bytes())?;
+    return writer.write_all(value);
+}
+
+/// Single part, uncompressed scanline OpenEXR. The color goes in the R, G and B channels
+/// and every layer adds `layer.channel` channels, stored in the same precision.
+fn write_exr<W: Write>(writer: &mut W, framebuffer: &Framebuffer, depth: BitDepth, layers: &[Layer]) -> std::io::Result<()> {
+    let mut channels: Vec<ExrChannel> = ["R", "G", "B"].iter().enumerate()
+        .map(|(component, name)| ExrChannel { name: name.to_string(), framebuffer, component })
+        .collect();
+    for layer in layers {
+        for (component, channel) in layer.channels.iter().enumerate() {
+            channels.push(ExrChannel {
+                name: format!("{}.{}", layer.name, channel),
+                framebuffer: layer.framebuffer,
+                component
+            });
+        }
+    }
+    // Readers expect the channel list, and the data in every scanline, sorted by name.
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let (pixel_type, sample_size): (i32, usize) = if depth == BitDepth::Sixteen { (1, 2) } else { (2, 4) };
+    let width = framebuffer.width as usize;
+    let height = framebuffer.height as usize;
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01];
+    // Version 2, single part scanline file. Names longer than 31 bytes need the long
+    // names flag.
+    let long_names = channels.iter().any(|c| c.name.len() > 31);
+    header.extend_from_slice(&(2u32 | if long_names { 0x400 } else { 0 }).to_le_bytes());
+
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&pixel_type.to_le_bytes());
+        // pLinear and three reserved bytes, then the x and y sampling rates.
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+
+    let mut window = Vec::new();
+    for value in [0, 0, framebuffer.width - 1, framebuffer.height - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    write_attribute(&mut header, "channels", "chlist", &channel_list)?;
+    write_attribute(&mut header, "compression", "compression", &[0])?;
+    write_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes())?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0u8; 8])?;
+    write_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes())?;
+    header.push(0);
+    writer.write_all(&header)?;
+
+    // The offset table points at every scanline, which all have the same size here:
+    // the y coordinate, the data size and the data.
+    let line_size = width * channels.len() * sample_size;
+    let first_line = header.len() + 8 * height;
+    for y in 0..height {
+        writer.write_all(&((first_line + y * (8 + line_size)) as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height {
+        line.clear();
+        for channel in &channels {
+            for pixel in &channel.framebuffer.pixels[y * width..(y + 1) * width] {
+                let value = pixel[channel.component];
+                if depth == BitDepth::Sixteen {
+                    line.extend_from_slice(&f32_to_half(value).to_le_bytes());
+                } else {
+                    line.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&(line_size as i32).to_le_bytes())?;
+        writer.write_all(&line)?;
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes a finite half back to f32, the inverse of `f32_to_half`.
+    fn half_to_f32(half: u16) -> f32 {
+        let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((half >> 10) & 0x1f) as i32;
+        let mantissa = (half & 0x3ff) as f32;
+        if exponent == 0 {
+            return sign * mantissa * 2f32.powi(-24);
+        }
+        return sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15);
+    }
+
+    fn encode_channel(values: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_rle_channel(&mut out, values).unwrap();
+        return out;
+    }
+
+    #[test]
+    fn every_finite_half_survives_a_round_trip() {
+        for half in 0..=0xffffu16 {
+            if half & 0x7c00 == 0x7c00 {
+                continue;
+            }
+            assert_eq!(f32_to_half(half_to_f32(half)), half, "{:#06x}", half);
+        }
+    }
+
+    #[test]
+    fn halves_round_to_nearest_even() {
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        // Halfway between 1 and the next half goes down to the even 1, the next
+        // halfway point up to the even 1 + 2^-9.
+        assert_eq!(f32_to_half(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        assert_eq!(f32_to_half(1.0 + 1.5 * 2f32.powi(-11)), 0x3c01);
+        // Rounding up out of the largest half overflows to infinity.
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NAN) & 0x7c00, 0x7c00);
+        assert_ne!(f32_to_half(f32::NAN) & 0x3ff, 0);
+        // Subnormals, with half the smallest one a tie rounding to zero.
+        assert_eq!(f32_to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(1.5 * 2f32.powi(-24)), 0x0002);
+        assert_eq!(f32_to_half(2f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_half(-1e-10), 0x8000);
+    }
+
+    #[test]
+    fn rgbe_shares_the_largest_components_exponent() {
+        assert_eq!(to_rgbe(Vec3::new(1.0, 0.5, 0.25)), [128, 64, 32, 129]);
+        assert_eq!(to_rgbe(Vec3::new(0.0, 3.0, 0.0)), [0, 192, 0, 130]);
+        assert_eq!(to_rgbe(Vec3::new(-1.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(Vec3::new(f32::INFINITY, 0.0, 0.0)), [0, 0, 0, 0]);
+
+        let [r, g, b, e] = to_rgbe(Vec3::new(0.3, 0.2, 0.1));
+        let scale = 2f32.powi(e as i32 - 128 - 8);
+        assert!((r as f32 * scale - 0.3).abs() < 0.3 / 128.0);
+        assert!((g as f32 * scale - 0.2).abs() < 0.3 / 128.0);
+        assert!((b as f32 * scale - 0.1).abs() < 0.3 / 128.0);
+    }
+
+    #[test]
+    fn rle_encodes_runs_and_dumps() {
+        assert_eq!(encode_channel(&[7; 10]), [138, 7]);
+        assert_eq!(encode_channel(&[1, 2, 3]), [3, 1, 2, 3]);
+        // Runs shorter than four are cheaper to dump.
+        assert_eq!(encode_channel(&[9, 9, 9]), [3, 9, 9, 9]);
+        assert_eq!(encode_channel(&[1, 2, 5, 5, 5, 5, 5, 3]), [2, 1, 2, 133, 5, 1, 3]);
+    }
+
+    #[test]
+    fn rle_splits_long_runs_and_dumps() {
+        assert_eq!(encode_channel(&[4; 200]), [255, 4, 201, 4]);
+
+        let values: Vec<u8> = (0..130).collect();
+        let encoded = encode_channel(&values);
+        assert_eq!(encoded.len(), 132);
+        assert_eq!(encoded[0], 128);
+        assert_eq!(&encoded[1..129], &values[..128]);
+        assert_eq!(&encoded[129..], [2, 128, 129]);
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::material::MaterialHandle;
+use crate::ray::Ray;
+use crate::shapes::{ Hitable, HitRecord, MaterialAccessor, Primitive, Sampleable, ShapeSample };
+use crate::transform::AnimatedTransform;
+use crate::vec3::Vec3;
+
+/// A shared group of primitives placed in the world with a transform.
+///
+/// The primitives and their BVH are stored once and referenced by every instance, so
+/// an object placed many times only costs one transform per copy. Rays are taken into
+/// the object's space at their own time rather than moving the geometry.
+#[derive(Clone)]
+pub struct Instance {
+    object: Arc<Bvh<Primitive>>,
+    object_to_world: Box<AnimatedTransform>,
+    bounds: Aabb,
+    /// Replaces the materials of the object's primitives when assigned.
+    material: MaterialHandle
+}
+
+impl Instance {
+    /// Places `object` in the world with `object_to_world`, keeping the materials of
+    /// its primitives. The bounds cover the whole motion of a changing transform.
+    pub fn new(object: Arc<Bvh<Primitive>>, object_to_world: AnimatedTransform) -> Instance {
+        let object_bounds = object.bounding_box();
+        let bounds = if object.primitives().is_empty() {
+            Aabb::empty()
+        } else {
+            object_to_world.bounding_box(object_bounds)
+        };
+
+        return Instance {
+            object,
+            object_to_world: Box::new(object_to_world),
+            bounds,
+            material: MaterialHandle::UNASSIGNED
+        }
+    }
+
+    /// Draws every primitive of the object with `material` instead of its own.
+    pub fn with_material(mut self, material: MaterialHandle) -> Instance {
+        self.material = material;
+        return self;
+    }
+
+    pub fn object(&self) -> &Bvh<Primitive> {
+        return &self.object;
+    }
+}
+
+impl Hitable for Instance {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let object_to_world = self.object_to_world.at(r.time);
+        let local_ray = object_to_world.inverse().ray(r);
+        if !self.object.hit(&local_ray, t_min, t_max, rec) {
+            return false;
+        }
+
+        rec.p = object_to_world.point(rec.p);
+        rec.normal = object_to_world.normal(rec.normal).unit_vector();
+        return true;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bounds;
+    }
+}
+
+impl Sampleable for Instance {
+    /// Instances are not sampled as lights, a transform changes the area and solid
+    /// angle of every part differently. Paths hitting an emissive instance still pick
+    /// up its emission.
+    #[allow(unused_variables)]
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample> {
+        return None;
+    }
+
+    #[allow(unused_variables)]
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        return 0.0;
+    }
+
+    fn is_sampleable(&self) -> bool {
+        return false;
+    }
+}
+
+impl MaterialAccessor for Instance {
+    /// The replacement material, unassigned when the object's primitives keep theirs.
+    fn material(&self) -> MaterialHandle {
+        return self.material;
+    }
+}
@@ -0,0 +1,65 @@
+use crate::material::{MaterialHandle, MaterialRegistry};
+use crate::shapes::{MaterialAccessor, Sampleable};
+
+/// A light direct lighting can pick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    /// An emissive primitive, by its index in `Bvh::primitives`.
+    Primitive(usize),
+    /// The environment map surrounding the scene.
+    Environment
+}
+
+/// The emissive primitives of the world, plus the environment map when there is one,
+/// which direct lighting samples explicitly.
+///
+/// Lights are referred to by their index in `Bvh::primitives` and are picked with
+/// equal probability. Emissive shapes which cannot be sampled, like infinite planes,
+/// are left out and only contribute when paths hit them.
+pub struct LightList {
+    primitives: Vec<usize>,
+    environment: bool
+}
+
+impl LightList {
+    /// Collects every sampleable primitive whose material is emissive, adding the
+    /// environment when `environment` is set.
+    pub fn from_primitives<T: MaterialAccessor + Sampleable>(primitives: &[T], materials: &MaterialRegistry, environment: bool) -> LightList {
+        let lights = primitives.iter()
+            .enumerate()
+            .filter(|(_, primitive)| primitive.material() != MaterialHandle::UNASSIGNED
+                && primitive.is_sampleable()
+                && materials.get(primitive.material()).is_emissive())
+            .map(|(index, _)| index)
+            .collect();
+
+        return LightList { primitives: lights, environment };
+    }
+
+    fn len(&self) -> usize {
+        return self.primitives.len() + self.environment as usize;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Whether the environment map is one of the lights.
+    pub fn has_environment(&self) -> bool {
+        return self.environment;
+    }
+
+    /// The probability of `pick` choosing any given light.
+    pub fn selection_pdf(&self) -> f32 {
+        return 1.0 / self.len() as f32;
+    }
+
+    /// Chooses a light with the uniform number `u`.
+    pub fn pick(&self, u: f32) -> Light {
+        let index = ((u * self.len() as f32) as usize).min(self.len() - 1);
+        if index == self.primitives.len() {
+            return Light::Environment;
+        }
+        return Light::Primitive(self.primitives[index]);
+    }
+}
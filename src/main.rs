@@ -1,205 +1,404 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::clone_on_copy, clippy::doc_lazy_continuation)]
+
+mod aabb;
+mod background;
+mod bvh;
 mod camera;
+mod cli;
 mod hitable_list;
+mod image_reader;
+mod image_writer;
+mod instance;
+mod light;
 mod material;
 mod math;
+mod medium;
+mod microfacet;
+mod mesh;
+mod obj;
 mod random_utils;
 mod ray;
+mod render;
+mod sampler;
+mod scene_file;
+mod scenes;
 mod shapes;
+mod texture;
+mod tonemap;
+mod transform;
 mod vec3;
 
-use std::{fs::File, io::Write};
-use random_utils::generate_normalized_ran;
+use random_utils::{mix, RandomContext};
 
+use crate::background::Background;
+use crate::bvh::Bvh;
 use crate::camera::Camera;
+use crate::cli::{CliError, ImageSettings, OutputLayer};
+use crate::image_writer::Layer;
 use crate::hitable_list::HitableList;
-use crate::material::{Lambertian, Metal, Physics, Dielectric};
+use crate::light::{Light, LightList};
+use crate::material::{Lobe, MaterialRegistry, Physics, ScatterRecord};
+use crate::medium::Medium;
 use crate::ray::Ray;
-use crate::shapes::{HitRecord, Sphere, MaterialAccessor};
+use crate::render::Framebuffer;
+use crate::sampler::Sampler;
+use crate::shapes::{Hitable, HitRecord, MaterialAccessor, Sampleable};
 use crate::vec3::Vec3;
 
-fn color<T: shapes::Hitable + MaterialAccessor + Copy> (
-    lambertians: &Vec<Lambertian>,
-    metals: &Vec<Metal>,
-    dielectics: &Vec<Dielectric>,
-    ray: &Ray, 
-    world: &HitableList<T>,
-    depth: i32) -> Vec3 {
-
-    let mut rec = HitRecord::default();
-
-    if hitable_list::hit(&world.list, &ray, 0.001, f32::MAX, &mut rec) {
-        let mut scattered = Ray::default();
-        let mut attenuation = Vec3::from_uniform_value(0.0);
-
-        if rec.material_type == 0 {
-            let lamb_mat : Lambertian = lambertians[rec.material_index as usize];
-            if lamb_mat.scatter(&ray, &rec, &mut attenuation, &mut scattered) && depth < 50 {
-                return attenuation * color(&lambertians, &metals, &dielectics, &scattered, &world, depth + 1);
-            } else {
-                return Vec3::zero();
+/// Everything `color` needs to look up while following a path.
+struct SceneContext<'a, T: Hitable + MaterialAccessor + Sampleable> {
+    world: &'a Bvh<T>,
+    media: &'a [Medium],
+    materials: &'a MaterialRegistry,
+    lights: &'a LightList,
+    settings: ImageSettings,
+    background: &'a Background
+}
+
+impl<'a, T: Hitable + MaterialAccessor + Sampleable> SceneContext<'a, T> {
+    /// The density light sampling would have produced `ray` with, given that it hit the
+    /// light recorded in `rec`.
+    fn light_pdf(&self, ray: &Ray, rec: &HitRecord) -> f32 {
+        let light = &self.world.primitives()[rec.primitive];
+        return self.lights.selection_pdf() * light.pdf_value(ray.origin, ray.direction, ray.time);
+    }
+
+    /// The density light sampling would have produced `ray` with, given that it left
+    /// the scene.
+    fn environment_pdf(&self, ray: &Ray) -> f32 {
+        match self.background.environment() {
+            Some(map) if self.lights.has_environment() => return self.lights.selection_pdf() * map.pdf(ray.direction),
+            _ => return 0.0
+        }
+    }
+
+    /// Where the media scatter `ray` before it reaches `t_max`, if they do. Each medium
+    /// proposes a distance and the closest wins, which for independent media is the
+    /// first collision with all of them together.
+    fn sample_media(&self, ray: &Ray, t_max: f32, rng: &mut RandomContext) -> Option<HitRecord> {
+        let mut closest: Option<(f32, &Medium)> = None;
+        for medium in self.media {
+            let limit = closest.map_or(t_max, |(t, _)| t);
+            if let Some(t) = medium.sample_distance(ray, 0.001, limit, rng) {
+                closest = Some((t, medium));
             }
-        } else if rec.material_type == 1 {
-            let metal_mat : Metal = metals[rec.material_index as usize];
-            if metal_mat.scatter(&ray, &rec, &mut attenuation, &mut scattered) && depth < 50 {
-                return attenuation * color(&lambertians, &metals, &dielectics, &scattered, &world, depth + 1);
-            } else {
-                return Vec3::zero();
+        }
+
+        let (t, medium) = closest?;
+        let mut rec = HitRecord::default();
+        rec.t = t;
+        rec.p = ray.point_at_parameter(t);
+        rec.normal = -ray.direction.unit_vector();
+        rec.material = medium.material();
+        return Some(rec);
+    }
+
+    /// The fraction of light the media let through along `ray` up to `t_max`.
+    fn transmittance(&self, ray: &Ray, t_max: f32, rng: &mut RandomContext) -> f32 {
+        let mut transmittance = 1.0;
+        for medium in self.media {
+            transmittance *= medium.transmittance(ray, 0.001, t_max, rng);
+            if transmittance <= 0.0 {
+                break;
             }
-        } else {
-            let dialectic_metal : Dielectric = dielectics[rec.material_index as usize];
-            if dialectic_metal.scatter(&ray, &rec, &mut attenuation, &mut scattered) && depth < 50 {
-                return attenuation * color(&lambertians, &metals, &dielectics, &scattered, &world, depth + 1);
-            } else {
-                return Vec3::zero();
+        }
+        return transmittance;
+    }
+
+    /// Direct lighting at `rec` from one light picked at random, weighted against the
+    /// chance of the material's own sampling finding the same light.
+    fn sample_light(&self, ray: &Ray, rec: &HitRecord, material: &dyn Physics, sampler: &mut dyn Sampler, rng: &mut RandomContext) -> Vec3 {
+        let pick = sampler.get_1d();
+        let u = sampler.get_2d();
+        if self.lights.is_empty() {
+            return Vec3::zero();
+        }
+
+        let (direction, dist, emitted, pdf) = match self.lights.pick(pick) {
+            Light::Primitive(index) => {
+                let light = &self.world.primitives()[index];
+                let sample = match light.sample(rec.p, ray.time, u) {
+                    Some(sample) if sample.pdf > 0.0 => sample,
+                    _ => return Vec3::zero()
+                };
+
+                let to_light = sample.p - rec.p;
+                let dist = to_light.length();
+                let direction = to_light / dist;
+
+                let mut light_rec = HitRecord::default();
+                light_rec.p = sample.p;
+                light_rec.normal = sample.normal;
+                light_rec.t = dist;
+                let emitted = self.materials.get(light.material()).emitted(&Ray::new(&rec.p, &direction).with_time(ray.time), &light_rec);
+                // Stop just short of the light so it does not shadow itself.
+                (direction, dist * (1.0 - 1e-4), emitted, sample.pdf)
+            },
+            Light::Environment => {
+                let map = self.background.environment().expect("Environment light without a map");
+                let (direction, pdf) = match map.sample(u) {
+                    Some(sample) => sample,
+                    None => return Vec3::zero()
+                };
+                (direction, f32::MAX, map.radiance(direction), pdf)
             }
+        };
+
+        let f = material.eval(ray, rec, direction);
+        if f.x <= 0.0 && f.y <= 0.0 && f.z <= 0.0 {
+            return Vec3::zero();
         }
 
-        // Material materials
-        // let target = rec.p + rec.normal + random_utils::random_in_unit_sphere();
-        // let new_ray = Ray::new(&rec.p, &(target - rec.p));
-        // return 0.5 * color(lambertians, metals, &new_ray, &world);
-    } else {
-        let unit_dir = ray.direction.unit_vector();
-        let t = (unit_dir.y + 1.0) * 0.5;
-        return (1.0 - t) * Vec3::from_uniform_value(1.0) + t * Vec3::new(0.5, 0.7, 1.0);
+        let shadow_ray = Ray::new(&rec.p, &direction).with_time(ray.time);
+        let mut blocker = HitRecord::default();
+        if self.world.hit(&shadow_ray, 0.001, dist, &mut blocker) {
+            return Vec3::zero();
+        }
+        let transmittance = self.transmittance(&shadow_ray, dist, rng);
+
+        let light_pdf = self.lights.selection_pdf() * pdf;
+        let weight = math::power_heuristic(light_pdf, material.pdf(ray, rec, direction));
+        return f * emitted * (transmittance * weight / light_pdf);
     }
 }
 
-fn random_world() -> (Vec<Sphere>, Vec<Lambertian>, Vec<Metal>, Vec<Dielectric>) {
-    let n = 500;
-    let mut spheres : Vec<Sphere> = Vec::with_capacity(500);
-
-    let mut lambertians : Vec<Lambertian> = Vec::with_capacity(500);
-    let mut metals : Vec<Metal> = Vec::with_capacity(500);
-    let glasses : Vec<Dielectric> = vec![ Dielectric::new(1.5) ];
-    lambertians.push(Lambertian::with_albedo(Vec3::from_uniform_value(0.5)));
-    spheres.push(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 100.0).with_material(0, 0));
-
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = generate_normalized_ran(100);
-            let center = Vec3::new(a as f32 + 0.9 * generate_normalized_ran(100), 0.2, b as f32 + 0.9 * generate_normalized_ran(100));
-
-            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                if choose_mat < 0.8 {
-                    let lamb = Lambertian::with_albedo(
-                        Vec3::new(
-                            generate_normalized_ran(100) * generate_normalized_ran(100), 
-                            generate_normalized_ran(100) * generate_normalized_ran(100), 
-                            generate_normalized_ran(100) * generate_normalized_ran(100))
-                    );
-                    lambertians.push(lamb);
-                    spheres.push(Sphere::new(center, 0.2).with_material(0, lambertians.len() as i32 - 1))
-                } else if choose_mat < 0.95 {
-                    let metal = Metal::with_properties(
-                        Vec3::new(
-                            0.5 * (1.0 + generate_normalized_ran(100)),
-                            0.5 * (1.0 + generate_normalized_ran(100)),
-                            0.5 * (1.0 + generate_normalized_ran(100))
-                        ), 
-                        0.5 * generate_normalized_ran(100));
-                    metals.push(metal);
-                    spheres.push(Sphere::new(center, 0.2).with_material(1, metals.len() as i32 - 1));
-                } else {
-                    spheres.push(Sphere::new(center, 0.2).with_material(2, glasses.len() as i32 - 1));
-                }
+/// Radiance arriving along `ray`, following the path one bounce at a time.
+///
+/// `throughput` is the product of the attenuations along the path so far, the factor
+/// anything found further along is scaled by. Before reaching the next surface a path
+/// may be scattered by the media in between, and carries on through them otherwise.
+/// Lights hit by a sampled bounce are weighted against light sampling, which already
+/// accounted for part of them; after the camera ray or a specular bounce nothing
+/// sampled them, so they count in full. Paths end on a miss, an absorbing surface, a
+/// depth limit or Russian roulette, which once past `roulette_depth` ends dim paths
+/// early and boosts the survivors to keep the estimate unbiased. Media draw their
+/// varying number of random numbers from `rng`, keeping the sampler's dimensions fixed.
+fn color<T: Hitable + MaterialAccessor + Sampleable> (
+    ctx: &SceneContext<T>,
+    camera_ray: &Ray, 
+    sampler: &mut dyn Sampler,
+    rng: &mut RandomContext) -> Vec3 {
+
+    let settings = &ctx.settings;
+    let mut radiance = Vec3::zero();
+    let mut throughput = Vec3::from_uniform_value(1.0);
+    let mut ray = *camera_ray;
+    let mut bsdf_pdf: Option<f32> = None;
+
+    let mut depth = 0;
+    let mut diffuse_depth = 0;
+    let mut specular_depth = 0;
+    let mut transmission_depth = 0;
+
+    loop {
+        let mut rec = HitRecord::default();
+        let hit_surface = ctx.world.hit(&ray, 0.001, f32::MAX, &mut rec);
+        let t_surface = if hit_surface { rec.t } else { f32::MAX };
+
+        if let Some(scattering) = ctx.sample_media(&ray, t_surface, rng) {
+            rec = scattering;
+        } else if !hit_surface {
+            let mut background = ctx.background.radiance(ray.direction);
+            if let Some(pdf) = bsdf_pdf {
+                background = background * math::power_heuristic(pdf, ctx.environment_pdf(&ray));
             }
+            radiance = radiance + throughput * background;
+            break;
         }
-    }
 
-    metals.push(Metal::with_properties(Vec3::new(0.7, 0.6, 0.5), 0.0));
-    lambertians.push(Lambertian::with_albedo(Vec3::new(0.4, 0.4, 0.1)));
+        let material = ctx.materials.get(rec.material);
+
+        let mut emitted = material.emitted(&ray, &rec);
+        if let Some(pdf) = bsdf_pdf {
+            if emitted.x > 0.0 || emitted.y > 0.0 || emitted.z > 0.0 {
+                emitted = emitted * math::power_heuristic(pdf, ctx.light_pdf(&ray, &rec));
+            }
+        }
+        radiance = radiance + throughput * emitted;
+
+        let mut srec = ScatterRecord::default();
+        if !material.scatter(&ray, &rec, &mut srec, sampler) || depth >= settings.max_depth {
+            break;
+        }
 
-    spheres.push(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0).with_material(2, 0));
-    // list[i++] = new sphere(vec3(-4, 1, 0), 1.0, new lambertian(vec3(0.4, 0.2, 0.1)));
-    // list[i++] = new sphere(vec3(4, 1, 0), 1.0, new metal(vec3(0.7, 0.6, 0.5), 0.0));
-    spheres.push(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0).with_material(0, lambertians.len() as i32 - 1));
-    spheres.push(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0).with_material(1, metals.len() as i32 - 1));
+        let (lobe_depth, lobe_limit) = match srec.lobe {
+            Lobe::Diffuse => (&mut diffuse_depth, settings.diffuse_depth),
+            Lobe::Specular => (&mut specular_depth, settings.specular_depth),
+            Lobe::Transmission => (&mut transmission_depth, settings.transmission_depth)
+        };
+        if *lobe_depth >= lobe_limit {
+            break;
+        }
+        *lobe_depth += 1;
+        depth += 1;
+
+        if srec.is_specular {
+            bsdf_pdf = None;
+        } else {
+            radiance = radiance + throughput * ctx.sample_light(&ray, &rec, material, sampler, rng);
+            bsdf_pdf = Some(srec.pdf);
+        }
+
+        throughput = throughput * srec.attenuation;
+        ray = srec.scattered;
+
+        if depth >= settings.roulette_depth {
+            let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+            if sampler.get_1d() >= survival {
+                break;
+            }
+            throughput = throughput / survival;
+        }
+    }
 
-    return (spheres, lambertians, metals, glasses);
+    return radiance;
 }
 
 fn main() {
-    let nx : i32 = 800; // width
-    let ny : i32 = 400; // height
-    let ns : i32 = 100; // AA sampling
-
-    // let spheres = vec![
-    //     Sphere::new(Vec3::new(0.0, 0.0, 1.0), 0.5).with_material(0, 0),
-    //     Sphere::new(Vec3::new(0.0, -100.5, 1.0), 100.0).with_material(0, 1),
-    //     Sphere::new(Vec3::new(1.0, 0.0, 1.5), 0.5).with_material(1, 0),
-    //     Sphere::new(Vec3::new(-1.0, 0.0, 0.5), 0.5).with_material(2, 0),
-    //     Sphere::new(Vec3::new(-1.0, 0.0, 0.5), -0.45).with_material(2, 0)
-    // ];
-
-    // let lambertians = vec![
-    //     Lambertian::with_albedo(Vec3::new(0.1, 0.2, 0.5)),
-    //     Lambertian::with_albedo(Vec3::new(0.8, 0.8, 0.0))
-    // ];
-
-    // let metals = vec![
-    //     Metal::with_properties(Vec3::new(0.8, 0.6, 0.2), 0.3),
-    // ];
-
-    // let dielectric = vec![
-    //     Dielectric::new(1.5)
-    // ];
-
-    let scene = random_world();
-
-    let world = HitableList::from_list(scene.0);
-
-    let mut output = File::create("image.ppm").expect("Unable to create file");
-    let ppm_header = format!("P3\n{} {}\n255\n", nx, ny);
-
-    output.write_all(ppm_header.as_bytes()).expect("Unable to write to file");
-
-    let lookfrom = Vec3::new(13.0, 2.0, 3.0);
-    let lookat = Vec3::new(0.0, 0.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-
-    let camera = Camera::perspective(
-        lookfrom,
-        lookat,
-        Vec3::new(0.0, 1.0, 0.0), 
-        20.0, 
-        nx as f32 / ny as f32,
-        aperture,
-        dist_to_focus
-    );
-
-    let mut j = ny - 1;
-    while j >= 0 {
-        let mut i = 0;
-        while i < nx {
-            let mut col = Vec3::from_uniform_value(0.0);
-
-            let mut s = 0;
-            while s < ns {
-                let normalized_rand = random_utils::generate_normalized_ran(100);
-
-                let u = ((i as f32) + normalized_rand) / nx as f32;
-                let v = ((j as f32) + normalized_rand) / ny as f32;
-
-                let r = camera.get_ray(u, v);
-                let _p = r.point_at_parameter(2.0); // Not really sure what I'm doing here
-                col = col + color(&(scene.1), &(scene.2), &(scene.3), &r, &world, 0);
-                s = s + 1;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match cli::parse(&args) {
+        Ok(options) => options,
+        Err(CliError::HelpRequested) => {
+            println!("{}", cli::USAGE);
+            return;
+        },
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let seed = options.seed;
+    let sampler_kind = options.sampler;
+
+    let scene = match scenes::builtin(&options.scene, &mut RandomContext::from_seed(seed)) {
+        Some(scene) => scene,
+        None => match scene_file::load(&options.scene) {
+            Ok(scene) => scene,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
             }
+        }
+    };
 
-            col = col / ns;
-            col = math::sqrt(col);
+    if let Err(err) = scene.check_materials() {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
+    }
 
-            let ir : i32 = (255.99 * col.x) as i32;
-            let ig : i32 = (255.99 * col.y) as i32;
-            let ib : i32 = (255.99 * col.z) as i32;
+    let camera_settings = match options.camera_settings(scene.camera) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
 
-            let color_row = format!("{} {} {}\n", ir, ig, ib);
-            output.write_all(color_row.as_bytes()).expect("Unable to write color");
-            i = i + 1;
+    let image = match options.image_settings(scene.render) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let nx = image.width;
+    let ny = image.height;
+    let ns = image.samples;
+
+    let world = Bvh::build(HitableList::from_list(scene.primitives));
+    let environment = scene.background.environment().is_some_and(|map| !map.is_dark());
+    let lights = LightList::from_primitives(world.primitives(), &scene.materials, environment);
+    let ctx = SceneContext {
+        world: &world,
+        media: &scene.media,
+        materials: &scene.materials,
+        lights: &lights,
+        settings: image,
+        background: &scene.background
+    };
+
+    let camera = Camera::new(
+        camera_settings.projection,
+        camera_settings.lookfrom,
+        camera_settings.lookat,
+        camera_settings.vup,
+        camera_settings.vfov,
+        nx as f32 / ny as f32,
+        camera_settings.aperture,
+        camera_settings.focus_dist
+    ).with_shutter(camera_settings.shutter_open, camera_settings.shutter_close);
+
+    // Generates the camera rays of pixel (i, j), one per sample, each with the sampler
+    // positioned for the rest of that sample's path and a generator for the media,
+    // seeded apart from the independent sampler's own.
+    let for_each_sample = |i: i32, j: i32, f: &mut dyn FnMut(&Ray, &mut dyn Sampler, &mut RandomContext)| {
+        let mut sampler = sampler::create_sampler(sampler_kind, seed, ns);
+
+        let mut s = 0;
+        while s < ns {
+            sampler.start_pixel_sample(i, j, s);
+            let (du, dv) = sampler.get_2d();
+
+            let u = ((i as f32) + du) / nx as f32;
+            let v = ((j as f32) + dv) / ny as f32;
+
+            // Samples falling outside the projection stay black.
+            if let Some(r) = camera.get_ray(u, v, sampler.as_mut()) {
+                f(&r, sampler.as_mut(), &mut RandomContext::for_sample(mix(seed), i, j, s));
+            }
+            s += 1;
         }
+    };
+
+    let framebuffer = render::render_tiles(nx, ny, options.threads, |i, j| {
+        let mut col = Vec3::from_uniform_value(0.0);
+        for_each_sample(i, j, &mut |r, sampler, rng| col = col + color(&ctx, r, sampler, rng));
+        return col / ns;
+    });
+
+    // Layers only look at the first hit of each camera ray, averaged over the samples
+    // which hit anything.
+    let layer_buffers: Vec<(OutputLayer, Framebuffer)> = options.layers.iter().map(|&layer| {
+        let buffer = render::render_tiles(nx, ny, options.threads, |i, j| {
+            let mut sum = Vec3::zero();
+            let mut hits = 0;
+            for_each_sample(i, j, &mut |r, _, _| {
+                let mut rec = HitRecord::default();
+                if world.hit(r, 0.001, f32::MAX, &mut rec) {
+                    sum = sum + match layer {
+                        OutputLayer::Normal => rec.normal,
+                        OutputLayer::Depth => Vec3::from_uniform_value(rec.t * r.direction.length())
+                    };
+                    hits += 1;
+                }
+            });
+
+            if hits == 0 {
+                return match layer {
+                    OutputLayer::Normal => Vec3::zero(),
+                    OutputLayer::Depth => Vec3::from_uniform_value(f32::INFINITY)
+                };
+            }
+            return sum / hits as f32;
+        });
+        return (layer, buffer);
+    }).collect();
+
+    let layers: Vec<Layer> = layer_buffers.iter().map(|(layer, buffer)| match layer {
+        OutputLayer::Normal => Layer { name: "normal", channels: &["X", "Y", "Z"], framebuffer: buffer },
+        OutputLayer::Depth => Layer { name: "depth", channels: &["Z"], framebuffer: buffer }
+    }).collect();
+
+    let display = options.display_transform();
+    let output = if options.output_format().is_high_dynamic_range() {
+        display.adjust(&framebuffer)
+    } else {
+        display.display(&framebuffer)
+    };
 
-        j = j - 1;
+    if let Err(err) = image_writer::save(&options.output, &output, options.output_depth(), &layers) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
     }
 }
@@ -1,136 +1,922 @@
-use crate::ray::Ray;
-use crate::shapes::HitRecord;
-use crate::vec3::Vec3;
-use crate::random_utils::{random_in_unit_sphere, generate_normalized_ran};
-use crate::math::{reflect, refract, dot, schlick};
-
-pub trait Physics {
-    fn scatter(
-        self, 
-        ray: &Ray, 
-        rec: &HitRecord, 
-        attenuation: &mut Vec3, 
-        scattered: &mut Ray) -> bool;
-}
-
-#[derive(Clone, Copy)]
-pub struct Lambertian {
-    albedo: Vec3
-}
-
-impl Lambertian {
-    pub fn with_albedo(albedo_value: Vec3) -> Lambertian {
-        return Lambertian {
-            albedo: albedo_value
-        }
-    }
-}
-
-impl Physics for Lambertian {
-    #[allow(unused_variables)]
-    fn scatter(
-        self, 
-        ray: &Ray, 
-        rec: &HitRecord, 
-        attenuation: &mut Vec3, 
-        scattered: &mut Ray) -> bool {
-
-        let target = rec.p + rec.normal + random_in_unit_sphere();
-        let dir = target - rec.p;
-
-        // println!("Target: {}, Dir: {}", target, dir);
-        *scattered = Ray::new(&rec.p, &dir);
-        *attenuation = self.albedo.clone();
-        return true;
-    }
-}
-
-#[derive(Clone, Copy)]
-pub struct Metal {
-    albedo: Vec3,
-    fuzz: f32
-}
-
-impl Metal {
-    pub fn with_properties(albedo_value: Vec3, f: f32) -> Metal {
-        return Metal {
-            albedo: albedo_value,
-            fuzz: f
-        }
-    }
-}
-
-impl Physics for Metal {
-    fn scatter(
-        self, 
-        ray: &Ray, 
-        rec: &HitRecord, 
-        attenuation: &mut Vec3, 
-        scattered: &mut Ray) -> bool {
-        
-        let reflected = reflect(ray.direction, rec.normal);
-        *scattered = Ray::new(&rec.p, &(reflected + self.fuzz * random_in_unit_sphere()));
-        *attenuation = self.albedo.clone();
-        return (dot(scattered.direction, rec.normal)) > 0.0;
-    }
-}
-
-#[derive(Clone, Copy)]
-pub struct Dielectric {
-    ref_idx : f32
-}
-
-#[allow(unused_assignments)]
-#[allow(unused_assignments)]
-impl Physics for Dielectric {
-    fn scatter(
-        self,
-        ray: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray) -> bool {
-
-        let mut outward_normal = Vec3::zero();
-        let mut ni_over_nt = 0.0;
-        let reflected = reflect(ray.direction, rec.normal);
-        
-        *attenuation = Vec3::from_uniform_value(1.0);
-        let mut refracted = Vec3::zero();
-
-        let mut reflected_prob : f32 = 0.0;
-        let mut cosine : f32 = 0.0;
-
-        if dot(ray.direction, rec.normal) > 0.0 {
-            outward_normal = -1.0 * rec.normal;
-            ni_over_nt = self.ref_idx;
-            cosine = self.ref_idx * dot(ray.direction, rec.normal) / ray.direction.length()
-        } else {
-            outward_normal = rec.normal;
-            ni_over_nt = 1.0 / self.ref_idx;
-            cosine = -1.0 * dot(ray.direction, rec.normal) / ray.direction.length()
-        }
-
-        if refract(ray.direction, outward_normal, ni_over_nt, &mut refracted) {
-            reflected_prob = schlick(cosine, self.ref_idx);
-            *scattered = Ray::new(&rec.p, &refracted);
-        } else {
-            *scattered = Ray::new(&rec.p, &reflected);
-            reflected_prob = 1.0;
-        }
-
-        if generate_normalized_ran(100) < reflected_prob {
-            *scattered = Ray::new(&rec.p, &reflected);
-        } else {
-            *scattered = Ray::new(&rec.p, &refracted);
-        }
-        return true;
-    }
-}
-
-impl Dielectric {
-    pub fn new(reference_index: f32) -> Dielectric {
-        return Dielectric {
-            ref_idx: reference_index
-        }
-    }
-}
+use crate::ray::Ray;
+use crate::shapes::HitRecord;
+use crate::vec3::Vec3;
+use crate::random_utils::{random_in_unit_sphere, random_unit_vector};
+use crate::sampler::Sampler;
+use crate::math::{reflect, refract, dot, schlick, orthonormal_basis};
+use crate::medium::PhaseFunction;
+use crate::microfacet::{dielectric_bsdf, fresnel_conductor, fresnel_dielectric, named_conductor, reflect_about, refract_about, Ggx, SMOOTH_ALPHA};
+use crate::texture::{SolidColor, Texture};
+use crate::tonemap::luminance;
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// The kind of scattering event, each one can be given its own bounce limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lobe {
+    /// Diffuse reflection, and scattering inside media.
+    Diffuse,
+    /// Mirror and glossy reflection.
+    Specular,
+    /// Refraction into or out of a dielectric.
+    Transmission
+}
+
+/// The outcome of sampling a material: the direction the path continues in and the
+/// throughput it is carried with.
+#[derive(Clone, Copy)]
+pub struct ScatterRecord {
+    pub scattered: Ray,
+    /// BSDF times cosine divided by `pdf`, the factor the incoming radiance is scaled by.
+    pub attenuation: Vec3,
+    /// Solid angle density the direction was sampled with, unused for specular lobes.
+    pub pdf: f32,
+    /// Perfectly specular directions cannot be reached by light sampling, so the
+    /// integrator skips it and counts lights hit after them in full.
+    pub is_specular: bool,
+    pub lobe: Lobe
+}
+
+impl ScatterRecord {
+    pub fn default() -> ScatterRecord {
+        return ScatterRecord {
+            scattered: Ray::default(),
+            attenuation: Vec3::zero(),
+            pdf: 0.0,
+            is_specular: false,
+            lobe: Lobe::Diffuse
+        }
+    }
+}
+
+/// Refers to a material stored in a `MaterialRegistry`.
+///
+/// Shapes are created before knowing which registry they will be rendered with, so a
+/// handle can be unassigned or out of range until `MaterialRegistry::check` confirms
+/// otherwise for the whole scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaterialHandle(usize);
+
+impl MaterialHandle {
+    /// The handle of shapes which were never given a material.
+    pub const UNASSIGNED: MaterialHandle = MaterialHandle(usize::MAX);
+}
+
+impl std::fmt::Display for MaterialHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if *self == MaterialHandle::UNASSIGNED {
+            return write!(f, "no material");
+        }
+        write!(f, "material #{}", self.0)
+    }
+}
+
+/// Owns every material of a scene. Any type implementing `Physics` can be added, the
+/// renderer only ever sees `&dyn Physics`.
+#[derive(Default)]
+pub struct MaterialRegistry {
+    materials: Vec<Box<dyn Physics>>
+}
+
+impl MaterialRegistry {
+    pub fn new() -> MaterialRegistry {
+        return MaterialRegistry { materials: Vec::new() };
+    }
+
+    pub fn add<M: Physics + 'static>(&mut self, material: M) -> MaterialHandle {
+        self.materials.push(Box::new(material));
+        return MaterialHandle(self.materials.len() - 1);
+    }
+
+    /// Looks up a material. The handle must have passed `check`.
+    pub fn get(&self, handle: MaterialHandle) -> &dyn Physics {
+        return self.materials[handle.0].as_ref();
+    }
+
+    /// Confirms that `handle` refers to a material of this registry.
+    pub fn check(&self, handle: MaterialHandle) -> Result<(), String> {
+        if handle.0 >= self.materials.len() {
+            return Err(format!("{} is not defined, the scene has {} materials", handle, self.materials.len()));
+        }
+        return Ok(());
+    }
+}
+
+/// Materials are shared by all render threads.
+pub trait Physics: Send + Sync {
+    fn scatter(
+        &self, 
+        ray: &Ray, 
+        rec: &HitRecord, 
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool;
+
+    /// The BSDF times the cosine with the normal for light arriving along `direction`,
+    /// used when a light is sampled explicitly. Specular materials return black.
+    #[allow(unused_variables)]
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        return Vec3::zero();
+    }
+
+    /// The solid angle density `scatter` picks `direction` with.
+    #[allow(unused_variables)]
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        return 0.0;
+    }
+
+    /// Radiance given off by the surface at the hit point, black for anything that is
+    /// not a light.
+    #[allow(unused_variables)]
+    fn emitted(&self, ray: &Ray, rec: &HitRecord) -> Vec3 {
+        return Vec3::zero();
+    }
+
+    /// Whether shapes using the material are lights and should be sampled directly.
+    fn is_emissive(&self) -> bool {
+        return false;
+    }
+}
+
+/// The surface normal flipped, if needed, to face against the incoming ray so that
+/// one sided geometry like triangles scatters correctly when hit from behind.
+fn facing_normal(ray: &Ray, rec: &HitRecord) -> Vec3 {
+    if dot(ray.direction, rec.normal) > 0.0 {
+        return -rec.normal;
+    }
+    return rec.normal;
+}
+
+#[derive(Clone)]
+pub struct Lambertian {
+    albedo: Arc<dyn Texture>
+}
+
+impl Lambertian {
+    pub fn with_albedo(albedo_value: Vec3) -> Lambertian {
+        return Lambertian::with_texture(Arc::new(SolidColor::new(albedo_value)));
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Lambertian {
+        return Lambertian {
+            albedo
+        }
+    }
+}
+
+impl Physics for Lambertian {
+    /// Cosine weighted sampling: the normal plus a uniformly distributed unit vector
+    /// lands on the tangent unit sphere with a density proportional to the cosine.
+    fn scatter(
+        &self, 
+        ray: &Ray, 
+        rec: &HitRecord, 
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool {
+
+        let normal = facing_normal(ray, rec);
+        let mut dir = normal + random_unit_vector(sampler);
+        if dir.length_sq() < 1e-12 {
+            dir = normal;
+        }
+        let dir = dir.unit_vector();
+
+        srec.scattered = Ray::new(&rec.p, &dir).with_time(ray.time);
+        srec.attenuation = self.albedo.value(rec.u, rec.v, rec.p);
+        srec.pdf = dot(dir, normal).max(0.0) / PI;
+        srec.is_specular = false;
+        srec.lobe = Lobe::Diffuse;
+        return true;
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let cos = dot(direction.unit_vector(), facing_normal(ray, rec));
+        if cos <= 0.0 {
+            return Vec3::zero();
+        }
+        return self.albedo.value(rec.u, rec.v, rec.p) * (cos / PI);
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        return dot(direction.unit_vector(), facing_normal(ray, rec)).max(0.0) / PI;
+    }
+}
+
+#[derive(Clone)]
+pub struct Metal {
+    albedo: Arc<dyn Texture>,
+    fuzz: f32
+}
+
+impl Metal {
+    pub fn with_properties(albedo_value: Vec3, f: f32) -> Metal {
+        return Metal::with_texture(Arc::new(SolidColor::new(albedo_value)), f);
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>, f: f32) -> Metal {
+        return Metal {
+            albedo,
+            fuzz: f
+        }
+    }
+
+    /// Density of `normalize(reflected + fuzz * p)` with `p` uniform in the unit ball.
+    ///
+    /// The directions form a cone around the unit mirror direction `reflected`, each
+    /// one with the probability of the ball section it crosses: integrating the ball's
+    /// constant density along the chord from t0 to t1 gives (t1^3 - t0^3) / (4 pi f^3).
+    fn fuzz_pdf(&self, reflected: Vec3, direction: Vec3) -> f32 {
+        let f = self.fuzz;
+        let c = dot(direction, reflected);
+        let disc = c * c - 1.0 + f * f;
+        if disc <= 0.0 {
+            return 0.0;
+        }
+
+        let root = disc.sqrt();
+        let t1 = c + root;
+        if t1 <= 0.0 {
+            return 0.0;
+        }
+        // With a fuzz above 1 the ball contains the origin and the chord starts there.
+        let t0 = (c - root).max(0.0);
+
+        // t1^3 - t0^3 factored to avoid cancellation when the fuzz is small.
+        return (t1 - t0) * (t1 * t1 + t1 * t0 + t0 * t0) / (4.0 * PI * f * f * f);
+    }
+}
+
+impl Physics for Metal {
+    fn scatter(
+        &self, 
+        ray: &Ray, 
+        rec: &HitRecord, 
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool {
+        
+        let normal = facing_normal(ray, rec);
+        let reflected = reflect(ray.direction.unit_vector(), normal);
+        let dir = (reflected + self.fuzz * random_in_unit_sphere(sampler)).unit_vector();
+
+        srec.scattered = Ray::new(&rec.p, &dir).with_time(ray.time);
+        srec.attenuation = self.albedo.value(rec.u, rec.v, rec.p);
+        srec.is_specular = self.fuzz == 0.0;
+        srec.lobe = Lobe::Specular;
+        srec.pdf = if srec.is_specular { 0.0 } else { self.fuzz_pdf(reflected, dir) };
+        return (dot(dir, normal)) > 0.0;
+    }
+
+    /// Directions below the surface are absorbed, so above it the BSDF times cosine is
+    /// exactly the albedo times the sampling density.
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        if self.fuzz == 0.0 {
+            return Vec3::zero();
+        }
+        let normal = facing_normal(ray, rec);
+        let direction = direction.unit_vector();
+        if dot(direction, normal) <= 0.0 {
+            return Vec3::zero();
+        }
+        let reflected = reflect(ray.direction.unit_vector(), normal);
+        return self.albedo.value(rec.u, rec.v, rec.p) * self.fuzz_pdf(reflected, direction);
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        if self.fuzz == 0.0 {
+            return 0.0;
+        }
+        let reflected = reflect(ray.direction.unit_vector(), facing_normal(ray, rec));
+        return self.fuzz_pdf(reflected, direction.unit_vector());
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Dielectric {
+    ref_idx : f32
+}
+
+#[allow(unused_assignments)]
+impl Physics for Dielectric {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool {
+
+        let mut outward_normal = Vec3::zero();
+        let mut ni_over_nt = 0.0;
+        let reflected = reflect(ray.direction, rec.normal);
+        
+        srec.attenuation = Vec3::from_uniform_value(1.0);
+        srec.is_specular = true;
+        srec.pdf = 0.0;
+        let mut refracted = Vec3::zero();
+
+        let mut reflected_prob : f32 = 0.0;
+        let mut cosine : f32 = 0.0;
+
+        if dot(ray.direction, rec.normal) > 0.0 {
+            outward_normal = -rec.normal;
+            ni_over_nt = self.ref_idx;
+            cosine = self.ref_idx * dot(ray.direction, rec.normal) / ray.direction.length()
+        } else {
+            outward_normal = rec.normal;
+            ni_over_nt = 1.0 / self.ref_idx;
+            cosine = -dot(ray.direction, rec.normal) / ray.direction.length()
+        }
+
+        if refract(ray.direction, outward_normal, ni_over_nt, &mut refracted) {
+            reflected_prob = schlick(cosine, self.ref_idx);
+        } else {
+            reflected_prob = 1.0;
+        }
+
+        if sampler.get_1d() < reflected_prob {
+            srec.scattered = Ray::new(&rec.p, &reflected).with_time(ray.time);
+            srec.lobe = Lobe::Specular;
+        } else {
+            srec.scattered = Ray::new(&rec.p, &refracted).with_time(ray.time);
+            srec.lobe = Lobe::Transmission;
+        }
+        return true;
+    }
+}
+
+impl Dielectric {
+    pub fn new(reference_index: f32) -> Dielectric {
+        return Dielectric {
+            ref_idx: reference_index
+        }
+    }
+}
+
+/// A surface which gives off light, turning the shape it is applied to into an area
+/// light. It emits from both sides and never scatters.
+#[derive(Clone, Copy)]
+pub struct DiffuseLight {
+    emit: Vec3
+}
+
+impl DiffuseLight {
+    pub fn with_emission(emit: Vec3) -> DiffuseLight {
+        return DiffuseLight {
+            emit
+        }
+    }
+}
+
+impl Physics for DiffuseLight {
+    #[allow(unused_variables)]
+    fn scatter(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool {
+
+        return false;
+    }
+
+    #[allow(unused_variables)]
+    fn emitted(&self, ray: &Ray, rec: &HitRecord) -> Vec3 {
+        return self.emit;
+    }
+
+    fn is_emissive(&self) -> bool {
+        return true;
+    }
+}
+
+/// Orthonormal shading frame with z along `n`, used by the microfacet materials.
+struct Frame {
+    t: Vec3,
+    b: Vec3,
+    n: Vec3
+}
+
+impl Frame {
+    fn new(n: Vec3) -> Frame {
+        let (t, b) = orthonormal_basis(n);
+        return Frame { t, b, n };
+    }
+
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        return Vec3::new(dot(v, self.t), dot(v, self.b), dot(v, self.n));
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        return v.x * self.t + v.y * self.b + v.z * self.n;
+    }
+}
+
+/// A metal described by its complex index of refraction, with GGX microfacet roughness.
+///
+/// Unlike `Metal` the reflectance follows from Fresnel's equations, so it brightens and
+/// loses its tint towards grazing angles, and rough surfaces stay energy conserving up
+/// to the light lost to masking.
+#[derive(Clone, Copy)]
+pub struct Conductor {
+    eta: Vec3,
+    k: Vec3,
+    distribution: Ggx
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f32) -> Conductor {
+        return Conductor {
+            eta,
+            k,
+            distribution: Ggx::from_roughness(roughness)
+        }
+    }
+
+    /// One of the metals known to `microfacet::named_conductor`.
+    pub fn named(name: &str, roughness: f32) -> Option<Conductor> {
+        let (eta, k) = named_conductor(name)?;
+        return Some(Conductor::new(eta, k, roughness));
+    }
+
+    /// The BSDF times cosine and the sampling density for the local pair of directions.
+    fn evaluate(&self, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (Vec3::zero(), 0.0);
+        }
+        let h = (wo + wi).unit_vector();
+        let cos_h = dot(wo, h);
+        let fresnel = fresnel_conductor(cos_h, self.eta, self.k);
+        let ggx = &self.distribution;
+
+        let f = fresnel * (ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z));
+        return (f, ggx.visible_pdf(wo, h) / (4.0 * cos_h));
+    }
+}
+
+impl Physics for Conductor {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool {
+
+        let frame = Frame::new(facing_normal(ray, rec));
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let u = sampler.get_2d();
+        if wo.z <= 0.0 {
+            return false;
+        }
+
+        srec.lobe = Lobe::Specular;
+        if self.distribution.is_smooth() {
+            srec.scattered = Ray::new(&rec.p, &frame.to_world(Vec3::new(-wo.x, -wo.y, wo.z))).with_time(ray.time);
+            srec.attenuation = fresnel_conductor(wo.z, self.eta, self.k);
+            srec.pdf = 0.0;
+            srec.is_specular = true;
+            return true;
+        }
+
+        let ggx = &self.distribution;
+        let h = ggx.sample_visible(wo, u);
+        let wi = reflect_about(wo, h);
+        if wi.z <= 0.0 {
+            return false;
+        }
+
+        // D and the cosines cancel against the visible normal density.
+        let cos_h = dot(wo, h);
+        srec.scattered = Ray::new(&rec.p, &frame.to_world(wi)).with_time(ray.time);
+        srec.attenuation = fresnel_conductor(cos_h, self.eta, self.k) * (ggx.g2(wo, wi) / ggx.g1(wo));
+        srec.pdf = ggx.visible_pdf(wo, h) / (4.0 * cos_h);
+        srec.is_specular = false;
+        return true;
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        if self.distribution.is_smooth() {
+            return Vec3::zero();
+        }
+        let frame = Frame::new(facing_normal(ray, rec));
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        return self.evaluate(wo, frame.to_local(direction.unit_vector())).0;
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let frame = Frame::new(facing_normal(ray, rec));
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        return self.evaluate(wo, frame.to_local(direction.unit_vector())).1;
+    }
+}
+
+/// Glass with a GGX microfacet surface, reflecting and refracting into a blurred lobe.
+///
+/// Reflection and refraction are chosen with the Fresnel reflectance of the sampled
+/// microfacet, following Walter et al. (2007). Very low roughness falls back to a
+/// perfectly smooth interface.
+#[derive(Clone, Copy)]
+pub struct RoughDielectric {
+    ior: f32,
+    distribution: Ggx
+}
+
+impl RoughDielectric {
+    pub fn new(ior: f32, roughness: f32) -> RoughDielectric {
+        return RoughDielectric {
+            ior,
+            distribution: Ggx::from_roughness(roughness)
+        }
+    }
+
+    /// The frame facing the incoming ray and the ratio of the index on the far side to
+    /// that on the near side.
+    fn orient(&self, ray: &Ray, rec: &HitRecord) -> (Frame, f32) {
+        if dot(ray.direction, rec.normal) > 0.0 {
+            return (Frame::new(-rec.normal), 1.0 / self.ior);
+        }
+        return (Frame::new(rec.normal), self.ior);
+    }
+}
+
+impl Physics for RoughDielectric {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool {
+
+        let (frame, eta) = self.orient(ray, rec);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let u = sampler.get_2d();
+        let choice = sampler.get_1d();
+        if wo.z <= 0.0 {
+            return false;
+        }
+
+        let smooth = self.distribution.is_smooth();
+        let h = if smooth { Vec3::new(0.0, 0.0, 1.0) } else { self.distribution.sample_visible(wo, u) };
+        let fresnel = fresnel_dielectric(dot(wo, h), eta);
+
+        let wi = if choice < fresnel {
+            srec.lobe = Lobe::Specular;
+            reflect_about(wo, h)
+        } else {
+            srec.lobe = Lobe::Transmission;
+            match refract_about(wo, h, eta) {
+                Some(wi) => wi,
+                None => return false
+            }
+        };
+
+        srec.scattered = Ray::new(&rec.p, &frame.to_world(wi)).with_time(ray.time);
+        srec.is_specular = smooth;
+        if smooth {
+            srec.attenuation = Vec3::from_uniform_value(1.0);
+            srec.pdf = 0.0;
+            return true;
+        }
+
+        // Reflections must stay above the surface and refractions below it.
+        if (srec.lobe == Lobe::Specular) != (wi.z > 0.0) {
+            return false;
+        }
+        // Fresnel, D and the Jacobians cancel against the sampling density.
+        let ggx = &self.distribution;
+        srec.attenuation = Vec3::from_uniform_value(ggx.g2(wo, wi) / ggx.g1(wo));
+        srec.pdf = dielectric_bsdf(ggx, wo, wi, eta).1;
+        return true;
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        if self.distribution.is_smooth() {
+            return Vec3::zero();
+        }
+        let (frame, eta) = self.orient(ray, rec);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        return Vec3::from_uniform_value(dielectric_bsdf(&self.distribution, wo, frame.to_local(direction.unit_vector()), eta).0);
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (frame, eta) = self.orient(ray, rec);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        return dielectric_bsdf(&self.distribution, wo, frame.to_local(direction.unit_vector()), eta).1;
+    }
+}
+
+/// Fraction of the way from a surface's reflectance at normal incidence to full
+/// reflection, Schlick's (1 - cos)^5.
+fn schlick_weight(cos: f32) -> f32 {
+    let m = (1.0 - cos).clamp(0.0, 1.0);
+    return (m * m) * (m * m) * m;
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    return (1.0 - t) * a + t * b;
+}
+
+/// A single material covering plastics, metals, glass and everything in between, after
+/// the Disney principled BSDF (Burley 2012, 2015).
+///
+/// It layers a retro-reflective diffuse base with sheen, a GGX specular reflection, a
+/// rough glass lobe and a clear coat. `metallic` blends the base towards a tinted
+/// mirror, `transmission` towards glass. Every parameter but `ior` is a texture; the
+/// scalar ones use the average of the three channels, and all of them are in [0, 1].
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    /// Dielectric reflectance, 0.5 being exactly what `ior` predicts.
+    pub specular: Arc<dyn Texture>,
+    /// Tints dielectric reflections towards the base color.
+    pub specular_tint: Arc<dyn Texture>,
+    /// Extra grazing retro-reflection for cloth.
+    pub sheen: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_roughness: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: f32
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        let value = |v: f32| -> Arc<dyn Texture> { Arc::new(SolidColor::new(Vec3::from_uniform_value(v))) };
+        return Principled {
+            base_color: value(0.8),
+            metallic: value(0.0),
+            roughness: value(0.5),
+            specular: value(0.5),
+            specular_tint: value(0.0),
+            sheen: value(0.0),
+            clearcoat: value(0.0),
+            clearcoat_roughness: value(0.1),
+            transmission: value(0.0),
+            ior: 1.5
+        }
+    }
+}
+
+/// The parameters of a `Principled` material at one hit point, turned into lobe
+/// weights and the probabilities of sampling each lobe.
+struct PrincipledLobes {
+    base_color: Vec3,
+    sheen_color: Vec3,
+    /// Specular reflectance at normal incidence.
+    f0: Vec3,
+    roughness: f32,
+    specular: Ggx,
+    clearcoat: Ggx,
+    /// Index of refraction of the far side over the near one.
+    eta: f32,
+    /// Weights of the diffuse, specular, glass and clear coat lobes.
+    weights: [f32; 4],
+    probabilities: [f32; 4]
+}
+
+const DIFFUSE_LOBE: usize = 0;
+const SPECULAR_LOBE: usize = 1;
+const GLASS_LOBE: usize = 2;
+const CLEARCOAT_LOBE: usize = 3;
+
+impl Principled {
+    fn lobes(&self, rec: &HitRecord, eta: f32) -> PrincipledLobes {
+        let scalar = |texture: &Arc<dyn Texture>| -> f32 {
+            let v = texture.value(rec.u, rec.v, rec.p);
+            return ((v.x + v.y + v.z) / 3.0).clamp(0.0, 1.0);
+        };
+
+        let base_color = self.base_color.value(rec.u, rec.v, rec.p);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = scalar(&self.transmission);
+        let clearcoat = scalar(&self.clearcoat);
+
+        // The hue of the base color at unit luminance.
+        let base_luminance = luminance(base_color);
+        let tint = if base_luminance > 0.0 { base_color / base_luminance } else { Vec3::from_uniform_value(1.0) };
+        let white = Vec3::from_uniform_value(1.0);
+
+        let ior_f0 = ((self.ior - 1.0) / (self.ior + 1.0)).powi(2);
+        let dielectric_f0 = 2.0 * scalar(&self.specular) * ior_f0 * lerp(white, tint, scalar(&self.specular_tint));
+        let f0 = lerp(dielectric_f0, base_color, metallic);
+
+        let glass = (1.0 - metallic) * transmission;
+        let weights = [(1.0 - metallic) * (1.0 - transmission), 1.0 - glass, glass, 0.25 * clearcoat];
+
+        // Dim lobes are sampled less often, but the specular highlight of plastics keeps
+        // a floor so it is still found.
+        let mut probabilities = [
+            weights[DIFFUSE_LOBE] * base_luminance,
+            weights[SPECULAR_LOBE] * luminance(f0).max(0.2),
+            weights[GLASS_LOBE],
+            weights[CLEARCOAT_LOBE]
+        ];
+        let total: f32 = probabilities.iter().sum();
+        for p in probabilities.iter_mut() {
+            *p /= total;
+        }
+
+        let alpha = |r: f32| Ggx { alpha: (r * r).max(SMOOTH_ALPHA) };
+        return PrincipledLobes {
+            base_color,
+            sheen_color: scalar(&self.sheen) * lerp(white, tint, 0.5),
+            f0,
+            roughness,
+            specular: alpha(roughness),
+            clearcoat: alpha(scalar(&self.clearcoat_roughness)),
+            eta,
+            weights,
+            probabilities
+        }
+    }
+
+    fn orient(&self, ray: &Ray, rec: &HitRecord) -> (Frame, f32) {
+        if dot(ray.direction, rec.normal) > 0.0 {
+            return (Frame::new(-rec.normal), 1.0 / self.ior);
+        }
+        return (Frame::new(rec.normal), self.ior);
+    }
+}
+
+impl PrincipledLobes {
+    /// The BSDF times cosine summed over the lobes, and the density of picking `wi`
+    /// by first choosing a lobe and then sampling it.
+    fn evaluate(&self, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+        let mut f = Vec3::zero();
+        let mut pdf = 0.0;
+        let w = &self.weights;
+        let p = &self.probabilities;
+
+        if wo.z > 0.0 && wi.z > 0.0 {
+            let h = (wo + wi).unit_vector();
+            let cos_d = dot(wi, h);
+
+            if w[DIFFUSE_LOBE] > 0.0 {
+                // Burley's diffuse darkens grazing angles on smooth surfaces and adds
+                // retro-reflection on rough ones.
+                let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+                let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z)) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+                let diffuse = self.base_color * (retro / PI) + self.sheen_color * schlick_weight(cos_d);
+                f = f + diffuse * (w[DIFFUSE_LOBE] * wi.z);
+                pdf += p[DIFFUSE_LOBE] * wi.z / PI;
+            }
+
+            let reflection = self.specular.d(h) * self.specular.g2(wo, wi) / (4.0 * wo.z);
+            let fresnel = self.f0 + (Vec3::from_uniform_value(1.0) - self.f0) * schlick_weight(cos_d);
+            f = f + fresnel * (w[SPECULAR_LOBE] * reflection);
+            pdf += p[SPECULAR_LOBE] * self.specular.visible_pdf(wo, h) / (4.0 * dot(wo, h));
+
+            if w[CLEARCOAT_LOBE] > 0.0 {
+                let coat = self.clearcoat.d(h) * self.clearcoat.g2(wo, wi) / (4.0 * wo.z);
+                let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+                f = f + Vec3::from_uniform_value(w[CLEARCOAT_LOBE] * fresnel * coat);
+                pdf += p[CLEARCOAT_LOBE] * self.clearcoat.visible_pdf(wo, h) / (4.0 * dot(wo, h));
+            }
+        }
+
+        if w[GLASS_LOBE] > 0.0 {
+            let (glass, glass_pdf) = dielectric_bsdf(&self.specular, wo, wi, self.eta);
+            // Light passing through the surface picks up the base color.
+            let tint = if wi.z < 0.0 { self.base_color } else { Vec3::from_uniform_value(1.0) };
+            f = f + tint * (w[GLASS_LOBE] * glass);
+            pdf += p[GLASS_LOBE] * glass_pdf;
+        }
+
+        return (f, pdf);
+    }
+}
+
+impl Physics for Principled {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool {
+
+        let (frame, eta) = self.orient(ray, rec);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let mut choice = sampler.get_1d();
+        let u = sampler.get_2d();
+        if wo.z <= 0.0 {
+            return false;
+        }
+
+        let lobes = self.lobes(rec, eta);
+        // Rounding may leave a sliver past the last lobe, which goes to the last one
+        // that can be sampled at all.
+        let mut lobe = lobes.probabilities.iter().rposition(|p| *p > 0.0).unwrap_or(SPECULAR_LOBE);
+        for (index, p) in lobes.probabilities.iter().enumerate() {
+            if choice < *p {
+                lobe = index;
+                // Rescaled, the rest of the number still serves the glass lobe's choice.
+                choice /= p;
+                break;
+            }
+            choice -= p;
+        }
+
+        let wi = match lobe {
+            DIFFUSE_LOBE => {
+                let r = u.0.sqrt();
+                let phi = 2.0 * PI * u.1;
+                Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u.0).max(0.0).sqrt())
+            },
+            SPECULAR_LOBE => reflect_about(wo, lobes.specular.sample_visible(wo, u)),
+            CLEARCOAT_LOBE => reflect_about(wo, lobes.clearcoat.sample_visible(wo, u)),
+            _ => {
+                let h = lobes.specular.sample_visible(wo, u);
+                if choice < fresnel_dielectric(dot(wo, h), eta) {
+                    reflect_about(wo, h)
+                } else {
+                    match refract_about(wo, h, eta) {
+                        Some(wi) => wi,
+                        None => return false
+                    }
+                }
+            }
+        };
+
+        let (f, pdf) = lobes.evaluate(wo, wi);
+        if pdf <= 0.0 || !pdf.is_finite() {
+            return false;
+        }
+
+        srec.scattered = Ray::new(&rec.p, &frame.to_world(wi)).with_time(ray.time);
+        srec.attenuation = f / pdf;
+        srec.pdf = pdf;
+        srec.is_specular = false;
+        srec.lobe = match lobe {
+            DIFFUSE_LOBE => Lobe::Diffuse,
+            _ if wi.z < 0.0 => Lobe::Transmission,
+            _ => Lobe::Specular
+        };
+        return true;
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let (frame, eta) = self.orient(ray, rec);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        return self.lobes(rec, eta).evaluate(wo, frame.to_local(direction.unit_vector())).0;
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let (frame, eta) = self.orient(ray, rec);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        return self.lobes(rec, eta).evaluate(wo, frame.to_local(direction.unit_vector())).1;
+    }
+}
+
+/// Scattering inside a participating medium, handed out at the points a `Medium`
+/// scatters rays. There is no surface, the phase function alone decides the direction
+/// and `albedo` the share of light not absorbed.
+#[derive(Clone, Copy)]
+pub struct VolumeScatter {
+    albedo: Vec3,
+    phase: PhaseFunction
+}
+
+impl VolumeScatter {
+    pub fn new(albedo: Vec3, phase: PhaseFunction) -> VolumeScatter {
+        return VolumeScatter {
+            albedo,
+            phase
+        }
+    }
+}
+
+impl Physics for VolumeScatter {
+    /// Samples the phase function exactly, so only the albedo is left over.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord,
+        sampler: &mut dyn Sampler) -> bool {
+
+        let wo = ray.direction.unit_vector();
+        let dir = self.phase.sample(wo, sampler.get_2d());
+
+        srec.scattered = Ray::new(&rec.p, &dir).with_time(ray.time);
+        srec.attenuation = self.albedo;
+        srec.pdf = self.phase.eval(wo, dir);
+        srec.is_specular = false;
+        srec.lobe = Lobe::Diffuse;
+        return true;
+    }
+
+    #[allow(unused_variables)]
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        return self.albedo * self.phase.eval(ray.direction.unit_vector(), direction.unit_vector());
+    }
+
+    #[allow(unused_variables)]
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        return self.phase.eval(ray.direction.unit_vector(), direction.unit_vector());
+    }
+}
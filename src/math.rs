@@ -1,32 +1,51 @@
-use crate::vec3::Vec3;
-
-pub fn dot(lhs: Vec3, rhs: Vec3) -> f32 {
-    return lhs.x * rhs.x + lhs.y * rhs.y + lhs.z * rhs.z
-}
-
-pub fn sqrt(value: Vec3) -> Vec3 {
-    return Vec3::new(value.x.sqrt(), value.y.sqrt(), value.z.sqrt());
-}
-
-pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
-    return v - 2.0 * dot(v, n) * n;
-}
-
-pub fn refract(v: Vec3, n: Vec3, ni_over_t: f32, refracted: &mut Vec3) -> bool {
-    let uv = v.unit_vector();
-    let dt = dot(uv, n);
-
-    let discriminant = 1.0 - ni_over_t * ni_over_t * (1.0 - dt * dt);
-    if discriminant > 0.0 {
-        *refracted = ni_over_t * (uv - n * dt) - n * discriminant.sqrt();
-        return true;
-    }
-
-    return false;
-}
-
-pub fn schlick(cos: f32, ref_idx: f32) -> f32 {
-    let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-    r0 = r0 * r0;
-    return r0 + (1.0 - r0) * (1.0 - cos).powf(5.0);
-}
+use crate::vec3::Vec3;
+
+pub fn dot(lhs: Vec3, rhs: Vec3) -> f32 {
+    return lhs.x * rhs.x + lhs.y * rhs.y + lhs.z * rhs.z
+}
+
+pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
+    return v - 2.0 * dot(v, n) * n;
+}
+
+pub fn refract(v: Vec3, n: Vec3, ni_over_t: f32, refracted: &mut Vec3) -> bool {
+    let uv = v.unit_vector();
+    let dt = dot(uv, n);
+
+    let discriminant = 1.0 - ni_over_t * ni_over_t * (1.0 - dt * dt);
+    if discriminant > 0.0 {
+        *refracted = ni_over_t * (uv - n * dt) - n * discriminant.sqrt();
+        return true;
+    }
+
+    return false;
+}
+
+pub fn schlick(cos: f32, ref_idx: f32) -> f32 {
+    let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
+    r0 = r0 * r0;
+    return r0 + (1.0 - r0) * (1.0 - cos).powf(5.0);
+}
+
+/// Two unit vectors completing `n` into an orthonormal basis, without branching on
+/// which axis `n` is closest to (Duff et al. 2017).
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let sign = 1.0f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+
+    let t = Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    let bt = Vec3::new(b, sign + n.y * n.y * a, -n.y);
+    return (t, bt);
+}
+
+/// Weight of a sample drawn from a strategy with density `pdf_f` when combined with a
+/// second strategy of density `pdf_g`, using Veach's power heuristic (beta = 2).
+pub fn power_heuristic(pdf_f: f32, pdf_g: f32) -> f32 {
+    let f = pdf_f * pdf_f;
+    let g = pdf_g * pdf_g;
+    if f + g == 0.0 {
+        return 0.0;
+    }
+    return f / (f + g);
+}
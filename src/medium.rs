@@ -0,0 +1,271 @@
+use std::f32::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::material::MaterialHandle;
+use crate::math::{dot, orthonormal_basis};
+use crate::random_utils::RandomContext;
+use crate::ray::Ray;
+use crate::shapes::{Hitable, HitRecord, Primitive};
+use crate::vec3::Vec3;
+
+/// How a medium spreads the light it scatters over directions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhaseFunction {
+    /// Every direction equally likely.
+    Isotropic,
+    /// Henyey-Greenstein with asymmetry `g` in (-1, 1): positive values favour carrying
+    /// on forwards, negative ones scattering back, 0 is isotropic.
+    HenyeyGreenstein(f32)
+}
+
+impl PhaseFunction {
+    /// The density of scattering into `wi` for light travelling along `wo`, both unit
+    /// vectors. Doubles as the solid angle pdf `sample` picks `wi` with.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> f32 {
+        match *self {
+            PhaseFunction::Isotropic => return 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein(g) => {
+                let denom = 1.0 + g * g - 2.0 * g * dot(wo, wi);
+                return (1.0 - g * g) / (4.0 * PI * denom * denom.max(1e-12).sqrt());
+            }
+        }
+    }
+
+    /// Picks the direction light travelling along the unit vector `wo` carries on in
+    /// with the uniform sample `u`, exactly in proportion to `eval`.
+    pub fn sample(&self, wo: Vec3, u: (f32, f32)) -> Vec3 {
+        let cos_theta = match *self {
+            PhaseFunction::HenyeyGreenstein(g) if g.abs() >= 1e-3 => {
+                let sq = (1.0 - g * g) / (1.0 + g - 2.0 * g * u.0);
+                ((1.0 + g * g - sq * sq) / (2.0 * g)).clamp(-1.0, 1.0)
+            },
+            _ => 1.0 - 2.0 * u.0
+        };
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let (t, b) = orthonormal_basis(wo);
+        return (sin_theta * phi.cos()) * t + (sin_theta * phi.sin()) * b + cos_theta * wo;
+    }
+}
+
+/// Densities sampled on a regular grid stretched over `bounds`, one value per cell
+/// centre, blended trilinearly in between and zero outside.
+#[derive(Clone)]
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    /// x varies fastest, then y, then z.
+    values: Vec<f32>,
+    bounds: Aabb,
+    max: f32
+}
+
+impl DensityGrid {
+    /// # Returns
+    ///
+    /// The grid, or a description of the problem when the number of values does not
+    /// match the resolution or a value is negative.
+    pub fn new(resolution: [usize; 3], values: Vec<f32>, bounds: Aabb) -> Result<DensityGrid, String> {
+        if resolution.contains(&0) {
+            return Err(String::from("grid resolution must be at least 1 along every axis"));
+        }
+        let expected = resolution[0] * resolution[1] * resolution[2];
+        if values.len() != expected {
+            return Err(format!("a {}x{}x{} grid needs {} values, found {}",
+                resolution[0], resolution[1], resolution[2], expected, values.len()));
+        }
+        if values.iter().any(|&v| v < 0.0 || !v.is_finite()) {
+            return Err(String::from("grid values must be finite and not negative"));
+        }
+
+        let max = values.iter().cloned().fold(0.0, f32::max);
+        return Ok(DensityGrid { resolution, values, bounds, max });
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f32 {
+        return self.values[(z * self.resolution[1] + y) * self.resolution[0] + x];
+    }
+
+    pub fn density(&self, p: Vec3) -> f32 {
+        let extent = self.bounds.extent();
+        let mut cell = [0usize; 3];
+        let mut frac = [0.0f32; 3];
+        for axis in 0..3 {
+            let local = (p[axis] - self.bounds.min[axis]) / extent[axis];
+            if !(0.0..=1.0).contains(&local) {
+                return 0.0;
+            }
+            // Cell centres sit at half steps, values are held flat past the outer ones.
+            let n = self.resolution[axis];
+            let x = (local * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+            cell[axis] = (x as usize).min(n.saturating_sub(2));
+            frac[axis] = x - cell[axis] as f32;
+        }
+
+        let next = |axis: usize| (cell[axis] + 1).min(self.resolution[axis] - 1);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let (x0, y0, z0) = (cell[0], cell[1], cell[2]);
+        let (x1, y1, z1) = (next(0), next(1), next(2));
+
+        let c00 = lerp(self.value(x0, y0, z0), self.value(x1, y0, z0), frac[0]);
+        let c10 = lerp(self.value(x0, y1, z0), self.value(x1, y1, z0), frac[0]);
+        let c01 = lerp(self.value(x0, y0, z1), self.value(x1, y0, z1), frac[0]);
+        let c11 = lerp(self.value(x0, y1, z1), self.value(x1, y1, z1), frac[0]);
+        return lerp(lerp(c00, c10, frac[1]), lerp(c01, c11, frac[1]), frac[2]);
+    }
+}
+
+/// How much a medium scatters per unit of distance.
+#[derive(Clone)]
+pub enum Density {
+    Constant(f32),
+    /// The grid's values times the scale.
+    Grid(DensityGrid, f32)
+}
+
+impl Density {
+    fn at(&self, p: Vec3) -> f32 {
+        match self {
+            Density::Constant(density) => return *density,
+            Density::Grid(grid, scale) => return grid.density(p) * scale
+        }
+    }
+
+    /// An upper bound of the density anywhere, which distances are first sampled with.
+    fn majorant(&self) -> f32 {
+        match self {
+            Density::Constant(density) => return *density,
+            Density::Grid(grid, scale) => return grid.max * scale
+        }
+    }
+}
+
+/// The part of space a medium fills.
+#[derive(Clone)]
+pub enum MediumBounds {
+    /// The inside of a closed convex shape, such as a sphere or a box.
+    Shape(Primitive),
+    /// Everything below the height, a layer of ground fog.
+    Below(f32),
+    Everywhere
+}
+
+/// Smoke, fog or anything else which scatters light inside a volume rather than at a
+/// surface. Light is only scattered and absorbed, with the chance of carrying on
+/// through the medium after scattering given by the albedo of `material`.
+#[derive(Clone)]
+pub struct Medium {
+    bounds: MediumBounds,
+    density: Density,
+    /// Describes the scattering, such as a `VolumeScatter`.
+    material: MaterialHandle
+}
+
+impl Medium {
+    pub fn new(bounds: MediumBounds, density: Density, material: MaterialHandle) -> Medium {
+        return Medium { bounds, density, material };
+    }
+
+    pub fn material(&self) -> MaterialHandle {
+        return self.material;
+    }
+
+    /// The stretch of `r` between `t_min` and `t_max` inside the medium, assuming the
+    /// ray enters and leaves it at most once.
+    fn segment(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let (enter, exit) = match &self.bounds {
+            MediumBounds::Everywhere => (t_min, t_max),
+            MediumBounds::Below(height) => {
+                if r.direction.y == 0.0 {
+                    if r.origin.y >= *height {
+                        return None;
+                    }
+                    (t_min, t_max)
+                } else {
+                    let crossing = (height - r.origin.y) / r.direction.y;
+                    if r.direction.y > 0.0 { (t_min, crossing) } else { (crossing, t_max) }
+                }
+            },
+            MediumBounds::Shape(shape) => {
+                let mut rec = HitRecord::default();
+                if !shape.hit(r, f32::MIN, f32::MAX, &mut rec) {
+                    return None;
+                }
+                let enter = rec.t;
+                if !shape.hit(r, enter + 1e-4, f32::MAX, &mut rec) {
+                    return None;
+                }
+                (enter, rec.t)
+            }
+        };
+
+        let (enter, exit) = (enter.max(t_min), exit.min(t_max));
+        if enter >= exit {
+            return None;
+        }
+        return Some((enter, exit));
+    }
+
+    /// Follows `r` from `t_min` towards `t_max` through the medium with delta tracking.
+    /// The tracking takes as many random numbers as it has collisions, so they come from
+    /// `rng` rather than a `Sampler`, whose dimensions must not vary between samples.
+    ///
+    /// # Returns
+    ///
+    /// Where the ray is scattered, or `None` when it passes through. Passing through
+    /// happens as often as the transmittance, which paths are therefore not scaled by.
+    pub fn sample_distance(&self, r: &Ray, t_min: f32, t_max: f32, rng: &mut RandomContext) -> Option<f32> {
+        let (enter, exit) = self.segment(r, t_min, t_max)?;
+        let majorant = self.density.majorant() * r.direction.length();
+        if majorant <= 0.0 {
+            return None;
+        }
+
+        let mut t = enter;
+        loop {
+            t -= (1.0 - rng.next_f32()).ln() / majorant;
+            if t >= exit {
+                return None;
+            }
+            // Below the majorant some collisions are fictitious and the ray carries on.
+            match self.density {
+                Density::Constant(_) => return Some(t),
+                Density::Grid(..) => {
+                    if rng.next_f32() * self.density.majorant() < self.density.at(r.point_at_parameter(t)) {
+                        return Some(t);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The fraction of light making it along `r` from `t_min` to `t_max`. Constant
+    /// densities are exact, grids are estimated without bias by ratio tracking, drawing
+    /// from `rng` like `sample_distance`.
+    pub fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32, rng: &mut RandomContext) -> f32 {
+        let (enter, exit) = match self.segment(r, t_min, t_max) {
+            Some(segment) => segment,
+            None => return 1.0
+        };
+        let length = r.direction.length();
+
+        match &self.density {
+            Density::Constant(density) => return (-density * (exit - enter) * length).exp(),
+            Density::Grid(..) => {
+                let majorant = self.density.majorant();
+                if majorant <= 0.0 {
+                    return 1.0;
+                }
+                let mut transmittance = 1.0;
+                let mut t = enter;
+                loop {
+                    t -= (1.0 - rng.next_f32()).ln() / (majorant * length);
+                    if t >= exit {
+                        return transmittance;
+                    }
+                    transmittance *= 1.0 - self.density.at(r.point_at_parameter(t)) / majorant;
+                }
+            }
+        }
+    }
+}
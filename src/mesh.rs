@@ -0,0 +1,334 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::MaterialHandle;
+use crate::ray::Ray;
+use crate::math::dot;
+use crate::shapes::{ Hitable, HitRecord, MaterialAccessor, Sampleable, ShapeSample };
+use crate::vec3::Vec3;
+
+/// Shared vertex storage for a set of triangles.
+///
+/// Every triangle stores three indices into the vertex arrays. Normals and texture
+/// coordinates are optional, but when present there must be one per position.
+pub struct TriangleMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<(f32, f32)>>,
+    pub indices: Vec<[usize; 3]>,
+    material: MaterialHandle
+}
+
+impl TriangleMesh {
+    /// Creates a mesh, checking that every index refers to an existing vertex.
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Option<Vec<Vec3>>,
+        uvs: Option<Vec<(f32, f32)>>,
+        indices: Vec<[usize; 3]>) -> Result<TriangleMesh, String> {
+
+        if let Some(n) = &normals {
+            if n.len() != positions.len() {
+                return Err(format!("mesh has {} positions but {} normals", positions.len(), n.len()));
+            }
+        }
+        if let Some(uv) = &uvs {
+            if uv.len() != positions.len() {
+                return Err(format!("mesh has {} positions but {} uvs", positions.len(), uv.len()));
+            }
+        }
+        for (face, triangle) in indices.iter().enumerate() {
+            for index in triangle {
+                if *index >= positions.len() {
+                    return Err(format!("triangle {} references vertex {}, the mesh only has {}", face, index, positions.len()));
+                }
+            }
+        }
+
+        return Ok(TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material: MaterialHandle::UNASSIGNED
+        });
+    }
+
+    pub fn with_material(mut self, material: MaterialHandle) -> TriangleMesh {
+        self.material = material;
+        return self;
+    }
+
+    /// Splits the mesh into individually hitable triangles, all sharing its storage.
+    pub fn triangles(mesh: &Arc<TriangleMesh>) -> Vec<Triangle> {
+        return (0..mesh.indices.len())
+            .map(|face| Triangle { mesh: Arc::clone(mesh), face })
+            .collect();
+    }
+}
+
+/// A single triangle referencing its vertices in a shared `TriangleMesh`.
+#[derive(Clone)]
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    face: usize
+}
+
+impl Triangle {
+    /// Builds a standalone triangle from three points, with its own one triangle mesh.
+    pub fn from_points(p0: Vec3, p1: Vec3, p2: Vec3, material: MaterialHandle) -> Triangle {
+        let mesh = TriangleMesh::new(vec![p0, p1, p2], None, None, vec![[0, 1, 2]])
+            .expect("A single triangle always has valid indices")
+            .with_material(material);
+        return Triangle { mesh: Arc::new(mesh), face: 0 };
+    }
+
+    fn vertices(&self) -> (Vec3, Vec3, Vec3) {
+        let [i0, i1, i2] = self.mesh.indices[self.face];
+        return (self.mesh.positions[i0], self.mesh.positions[i1], self.mesh.positions[i2]);
+    }
+}
+
+fn max_dimension(v: Vec3) -> usize {
+    if v.x > v.y {
+        return if v.x > v.z { 0 } else { 2 };
+    }
+    return if v.y > v.z { 1 } else { 2 };
+}
+
+fn permute(v: Vec3, x: usize, y: usize, z: usize) -> Vec3 {
+    return Vec3::new(v[x], v[y], v[z]);
+}
+
+impl Hitable for Triangle {
+    /// Watertight ray/triangle intersection (Woop, Benthin and Wald 2013).
+    ///
+    /// The vertices are moved into a space where the ray starts at the origin and points
+    /// down +z, so the edge tests are exact for rays passing through shared edges and
+    /// vertices and neighbouring triangles never leave cracks between them, nor both
+    /// claim a ray through the edge they share.
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let (p0, p1, p2) = self.vertices();
+
+        let abs_dir = Vec3::new(r.direction.x.abs(), r.direction.y.abs(), r.direction.z.abs());
+        let kz = max_dimension(abs_dir);
+        let kx = if kz == 2 { 0 } else { kz + 1 };
+        let ky = if kx == 2 { 0 } else { kx + 1 };
+
+        let d = permute(r.direction, kx, ky, kz);
+        if d.z == 0.0 {
+            return false;
+        }
+
+        let mut p0t = permute(p0 - r.origin, kx, ky, kz);
+        let mut p1t = permute(p1 - r.origin, kx, ky, kz);
+        let mut p2t = permute(p2 - r.origin, kx, ky, kz);
+
+        let sx = -d.x / d.z;
+        let sy = -d.y / d.z;
+        let sz = 1.0 / d.z;
+        p0t.x += sx * p0t.z;
+        p0t.y += sy * p0t.z;
+        p1t.x += sx * p1t.z;
+        p1t.y += sy * p1t.z;
+        p2t.x += sx * p2t.z;
+        p2t.y += sy * p2t.z;
+
+        let mut e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+        let mut e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+        let mut e2 = p0t.x * p1t.y - p0t.y * p1t.x;
+
+        // Fall back to double precision when an edge function lands exactly on zero.
+        if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
+            e0 = (p1t.x as f64 * p2t.y as f64 - p1t.y as f64 * p2t.x as f64) as f32;
+            e1 = (p2t.x as f64 * p0t.y as f64 - p2t.y as f64 * p0t.x as f64) as f32;
+            e2 = (p0t.x as f64 * p1t.y as f64 - p0t.y as f64 * p1t.x as f64) as f32;
+        }
+
+        let det = e0 + e1 + e2;
+        if det == 0.0 {
+            return false;
+        }
+
+        // Seen as counterclockwise, the ray must not pass to the right of any edge. A ray
+        // exactly on an edge only counts if it is a top or left edge. The neighbour
+        // sharing the edge runs along it the other way, so exactly one of them is hit.
+        let orientation = det.signum();
+        for (e, edge) in [(e0, p2t - p1t), (e1, p0t - p2t), (e2, p1t - p0t)] {
+            let (e, dx, dy) = (e * orientation, edge.x * orientation, edge.y * orientation);
+            if e < 0.0 || (e == 0.0 && !(dy < 0.0 || (dy == 0.0 && dx < 0.0))) {
+                return false;
+            }
+        }
+
+        p0t.z *= sz;
+        p1t.z *= sz;
+        p2t.z *= sz;
+        let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+
+        let inv_det = 1.0 / det;
+        let t = t_scaled * inv_det;
+        if !(t > t_min && t < t_max) {
+            return false;
+        }
+
+        let b0 = e0 * inv_det;
+        let b1 = e1 * inv_det;
+        let b2 = e2 * inv_det;
+
+        let [i0, i1, i2] = self.mesh.indices[self.face];
+
+        let geometric_normal = (p1 - p0).cross(p2 - p0).unit_vector();
+        let normal = match &self.mesh.normals {
+            Some(normals) => {
+                let n = b0 * normals[i0] + b1 * normals[i1] + b2 * normals[i2];
+                if n.length_sq() > 0.0 { n.unit_vector() } else { geometric_normal }
+            },
+            None => geometric_normal
+        };
+
+        let (uv0, uv1, uv2) = match &self.mesh.uvs {
+            Some(uvs) => (uvs[i0], uvs[i1], uvs[i2]),
+            None => ((0.0, 0.0), (1.0, 0.0), (1.0, 1.0))
+        };
+
+        rec.t = t;
+        rec.p = b0 * p0 + b1 * p1 + b2 * p2;
+        rec.normal = normal;
+        rec.u = b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0;
+        rec.v = b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1;
+        return true;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let (p0, p1, p2) = self.vertices();
+        let bounds = Aabb::new(p0, p0).grow(p1).grow(p2);
+
+        // Axis aligned triangles have a flat box, pad it so the slab test stays reliable.
+        let pad = Vec3::from_uniform_value(1e-4);
+        return Aabb::new(bounds.min - pad, bounds.max + pad);
+    }
+}
+
+impl Sampleable for Triangle {
+    /// Samples the triangle uniformly by area and converts the density to solid angle.
+    #[allow(unused_variables)]
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample> {
+        let (p0, p1, p2) = self.vertices();
+        let cross = (p1 - p0).cross(p2 - p0);
+        let area = 0.5 * cross.length();
+        if area == 0.0 {
+            return None;
+        }
+
+        let su0 = u.0.sqrt();
+        let b0 = 1.0 - su0;
+        let b1 = u.1 * su0;
+        let p = b0 * p0 + b1 * p1 + (1.0 - b0 - b1) * p2;
+        let normal = cross.unit_vector();
+
+        let to_point = p - origin;
+        let dist_sq = to_point.length_sq();
+        let cos = dot(normal, to_point).abs() / dist_sq.sqrt();
+        if cos == 0.0 {
+            return None;
+        }
+
+        return Some(ShapeSample { p, normal, pdf: dist_sq / (cos * area) });
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        let mut rec = HitRecord::default();
+        if !self.hit(&Ray::new(&origin, &direction).with_time(time), 0.001, f32::MAX, &mut rec) {
+            return 0.0;
+        }
+
+        let (p0, p1, p2) = self.vertices();
+        let cross = (p1 - p0).cross(p2 - p0);
+        let area = 0.5 * cross.length();
+        let dist_sq = (rec.p - origin).length_sq();
+        let cos = dot(cross.unit_vector(), direction).abs() / direction.length();
+        if area == 0.0 || cos == 0.0 {
+            return 0.0;
+        }
+        return dist_sq / (cos * area);
+    }
+}
+
+impl MaterialAccessor for Triangle {
+    fn material(&self) -> MaterialHandle {
+        return self.mesh.material;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(triangle: &Triangle, origin: Vec3, direction: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        if triangle.hit(&Ray::new(&origin, &direction), 0.0, f32::MAX, &mut rec) {
+            return Some(rec);
+        }
+        return None;
+    }
+
+    /// The unit square in the z = 0 plane, split along its diagonal from (0, 0) to (1, 1).
+    fn split_square() -> Vec<Triangle> {
+        let positions = vec![
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)
+        ];
+        let mesh = TriangleMesh::new(positions, None, None, vec![[0, 1, 2], [0, 2, 3]]).unwrap();
+        return TriangleMesh::triangles(&Arc::new(mesh));
+    }
+
+    #[test]
+    fn hits_a_known_triangle() {
+        let triangle = Triangle::from_points(
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), MaterialHandle::UNASSIGNED);
+
+        let rec = hit(&triangle, Vec3::new(0.25, 0.25, 2.0), Vec3::new(0.0, 0.0, -2.0)).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert_eq!([rec.p.x, rec.p.y, rec.p.z], [0.25, 0.25, 0.0]);
+        assert_eq!([rec.normal.x, rec.normal.y, rec.normal.z], [0.0, 0.0, 1.0]);
+        assert_eq!((rec.u, rec.v), (0.5, 0.25));
+
+        // Triangles are hit from behind too, but not outside their edges or the range.
+        assert!(hit(&triangle, Vec3::new(0.25, 0.25, -1.0), Vec3::new(0.0, 0.0, 1.0)).is_some());
+        assert!(hit(&triangle, Vec3::new(0.6, 0.6, 1.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(hit(&triangle, Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, 1.0)).is_none());
+        assert!(hit(&triangle, Vec3::new(0.25, 0.25, 1.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(&Vec3::new(0.25, 0.25, 1.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(!triangle.hit(&ray, 0.0, 0.5, &mut rec));
+    }
+
+    #[test]
+    fn rays_through_a_shared_edge_hit_exactly_one_triangle() {
+        let square = split_square();
+        let directions = [
+            Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.3, -0.2, -1.0),
+            Vec3::new(-0.7, 0.1, -0.4), Vec3::new(0.0, 0.0, 1.0)
+        ];
+        for i in 1..64 {
+            let s = i as f32 / 64.0;
+            let on_edge = Vec3::new(s, s, 0.0);
+            for &direction in &directions {
+                let hits = square.iter().filter(|t| hit(t, on_edge - direction, direction).is_some()).count();
+                assert_eq!(hits, 1, "through ({}, {}) along {}", s, s, direction);
+            }
+        }
+    }
+
+    #[test]
+    fn mesh_indices_and_attributes_must_match_the_positions() {
+        let positions = vec![Vec3::zero(), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+
+        let err = TriangleMesh::new(positions.clone(), None, None, vec![[0, 1, 2], [0, 2, 3]]).err().unwrap();
+        assert!(err.contains("triangle 1 references vertex 3"), "{}", err);
+        assert!(TriangleMesh::new(positions.clone(), Some(vec![Vec3::zero(); 2]), None, vec![[0, 1, 2]]).is_err());
+        assert!(TriangleMesh::new(positions.clone(), None, Some(vec![(0.0, 0.0); 4]), vec![[0, 1, 2]]).is_err());
+        assert!(TriangleMesh::new(positions, None, None, vec![[0, 1, 2]]).is_ok());
+    }
+}
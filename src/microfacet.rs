@@ -0,0 +1,214 @@
+use std::f32::consts::PI;
+
+use crate::vec3::Vec3;
+
+/// Below this roughness the GGX lobe is numerically a delta, so materials switch to
+/// perfect specular reflection and refraction instead.
+pub const SMOOTH_ALPHA: f32 = 1e-3;
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals.
+///
+/// Everything works in the local shading frame, where the macro surface normal is +z.
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub alpha: f32
+}
+
+impl Ggx {
+    /// Maps the artist facing roughness in [0, 1] to the distribution's width, squaring
+    /// it so the perceived blur grows about linearly.
+    pub fn from_roughness(roughness: f32) -> Ggx {
+        let roughness = roughness.clamp(0.0, 1.0);
+        return Ggx { alpha: roughness * roughness };
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        return self.alpha < SMOOTH_ALPHA;
+    }
+
+    /// Density of microfacet normals `h` per unit projected area.
+    pub fn d(&self, h: Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let t = h.z * h.z * (a2 - 1.0) + 1.0;
+        return a2 / (PI * t * t);
+    }
+
+    /// Smith's auxiliary function, the masked fraction of microfacets seen from `w`.
+    fn lambda(&self, w: Vec3) -> f32 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return f32::INFINITY;
+        }
+        let tan2 = (w.x * w.x + w.y * w.y) / cos2;
+        return 0.5 * (-1.0 + (1.0 + self.alpha * self.alpha * tan2).sqrt());
+    }
+
+    /// Fraction of the microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> f32 {
+        return 1.0 / (1.0 + self.lambda(w));
+    }
+
+    /// Height correlated masking-shadowing for the pair of directions.
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        return 1.0 / (1.0 + self.lambda(wo) + self.lambda(wi));
+    }
+
+    /// Density of `sample_visible` returning `h` as seen from `wo`, G1(wo) D(h) |wo.h| / wo.z.
+    pub fn visible_pdf(&self, wo: Vec3, h: Vec3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        return self.g1(wo) * self.d(h) * wo.dot(h).abs() / wo.z;
+    }
+
+    /// Samples a microfacet normal among those visible from `wo` (Heitz 2018), which
+    /// never produces facets facing away from the viewer.
+    pub fn sample_visible(&self, wo: Vec3, u: (f32, f32)) -> Vec3 {
+        // Stretch to the hemisphere configuration, where visible normals are a
+        // projected disk.
+        let vh = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).unit_vector();
+        let len_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_sq > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        return Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).unit_vector();
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface.
+///
+/// # Parameters
+///
+/// - `cos_i` : f32
+/// Cosine between the incident direction and the normal, on the incident side
+/// - `eta` : f32
+/// Index of refraction of the far side over that of the incident side
+///
+/// # Returns
+///
+/// The reflected fraction, 1 under total internal reflection.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    return 0.5 * (parallel * parallel + perpendicular * perpendicular);
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with complex index of refraction
+/// `eta + i k` seen from air, one channel at a time.
+pub fn fresnel_conductor(cos_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f32, k: f32| -> f32 {
+        let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let (eta2, k2) = (eta * eta, k * k);
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i.clamp(0.0, 1.0) * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        return 0.5 * (rp + rs);
+    };
+    return Vec3::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z));
+}
+
+/// Complex refractive indices `(eta, k)` of common metals, sampled at the red, green
+/// and blue wavelengths of 650, 550 and 450 nm.
+pub fn named_conductor(name: &str) -> Option<(Vec3, Vec3)> {
+    match name {
+        "gold" => return Some((Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.385, 1.603))),
+        "copper" => return Some((Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142))),
+        "aluminium" | "aluminum" => return Some((Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.269, 4.837))),
+        "silver" => return Some((Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147))),
+        _ => return None
+    }
+}
+
+/// Mirrors `w` around `h`, both pointing away from the surface.
+pub fn reflect_about(w: Vec3, h: Vec3) -> Vec3 {
+    return 2.0 * w.dot(h) * h - w;
+}
+
+/// Refracts `w`, pointing away from the surface on the side `h` faces, through the
+/// microfacet `h` into a medium `eta` times denser.
+///
+/// # Returns
+///
+/// The transmitted direction pointing away on the far side, or `None` under total
+/// internal reflection.
+pub fn refract_about(w: Vec3, h: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = w.dot(h);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    return Some(-w / eta + (cos_i / eta - cos_t) * h);
+}
+
+/// A rough dielectric interface in the local frame, with `wo` on the side the normal
+/// faces and `wi` below the surface meaning refraction.
+///
+/// # Parameters
+///
+/// - `eta` : f32
+/// Index of refraction of the far side over that of the side of `wo`
+///
+/// # Returns
+///
+/// The BSDF times the cosine with the normal, and the density of picking `wi` by
+/// sampling a visible microfacet and then choosing reflection with its Fresnel
+/// reflectance.
+pub fn dielectric_bsdf(ggx: &Ggx, wo: Vec3, wi: Vec3, eta: f32) -> (f32, f32) {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    if wi.z > 0.0 {
+        let h = (wo + wi).unit_vector();
+        let cos_h = wo.dot(h);
+        let fresnel = fresnel_dielectric(cos_h, eta);
+        let f = fresnel * ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z);
+        return (f, fresnel * ggx.visible_pdf(wo, h) / (4.0 * cos_h));
+    }
+
+    // The generalized half vector, pointing to the side of wo.
+    let mut h = -(wo + eta * wi).unit_vector();
+    if h.z < 0.0 {
+        h = -h;
+    }
+    let (cos_o, cos_i) = (wo.dot(h), wi.dot(h));
+    if cos_o <= 0.0 || cos_i >= 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let fresnel = fresnel_dielectric(cos_o, eta);
+    let denom = cos_o + eta * cos_i;
+    let jacobian = eta * eta * cos_i.abs() / (denom * denom);
+    let f = (1.0 - fresnel) * ggx.d(h) * ggx.g2(wo, wi) * cos_o * jacobian / wo.z;
+    return (f, (1.0 - fresnel) * ggx.visible_pdf(wo, h) * jacobian);
+}
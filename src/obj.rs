@@ -0,0 +1,557 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::mesh::TriangleMesh;
+use crate::vec3::Vec3;
+
+/// A problem found while reading an OBJ or MTL file, pointing at the offending line.
+#[derive(Debug)]
+pub struct ObjError {
+    pub path: String,
+    /// 1 based line number, 0 when the file could not be read at all.
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: {}", self.path, self.message);
+        }
+        write!(f, "{}:{}: {}", self.path, self.line, self.message)
+    }
+}
+
+/// The subset of an MTL material the renderer understands.
+#[derive(Clone)]
+pub struct MtlMaterial {
+    /// Diffuse color.
+    pub kd: Vec3,
+    /// Specular color.
+    pub ks: Vec3,
+    /// Emitted radiance.
+    pub ke: Vec3,
+    /// Specular exponent, 0 to 1000.
+    pub ns: f32,
+    /// Index of refraction.
+    pub ni: f32,
+    /// Opacity, 1 is fully opaque.
+    pub d: f32,
+    pub illum: i32
+}
+
+impl Default for MtlMaterial {
+    fn default() -> MtlMaterial {
+        return MtlMaterial {
+            kd: Vec3::from_uniform_value(0.8),
+            ks: Vec3::zero(),
+            ke: Vec3::zero(),
+            ns: 0.0,
+            ni: 1.0,
+            d: 1.0,
+            illum: 2
+        }
+    }
+}
+
+/// The closest of the renderer's materials to an MTL description.
+pub enum MaterialMapping {
+    Lambertian { albedo: Vec3 },
+    Metal { albedo: Vec3, fuzz: f32 },
+    Dielectric { ref_idx: f32 },
+    DiffuseLight { emit: Vec3 }
+}
+
+impl MtlMaterial {
+    /// Picks the renderer material matching the MTL illumination model.
+    ///
+    /// Materials with an emission color `Ke` become lights, whatever their model.
+    /// Transparent models (or `d` below 1) become glass, reflective models or materials
+    /// with a specular but no diffuse color become metal, everything else is diffuse.
+    /// The Phong exponent `Ns` is turned into a fuzz factor, sharper highlights giving
+    /// a smaller fuzz.
+    pub fn mapping(&self) -> MaterialMapping {
+        if self.ke.x.max(self.ke.y).max(self.ke.z) > 0.0 {
+            return MaterialMapping::DiffuseLight { emit: self.ke };
+        }
+
+        let transparent = matches!(self.illum, 4 | 6 | 7 | 9) || self.d < 1.0;
+        if transparent {
+            let ref_idx = if self.ni > 1.0 { self.ni } else { 1.5 };
+            return MaterialMapping::Dielectric { ref_idx };
+        }
+
+        let has_specular = self.ks.x.max(self.ks.y).max(self.ks.z) > 0.0;
+        let has_diffuse = self.kd.x.max(self.kd.y).max(self.kd.z) > 0.0;
+        if matches!(self.illum, 3 | 5 | 8) || (has_specular && !has_diffuse) {
+            let fuzz = (2.0 / (self.ns.max(0.0) + 2.0)).sqrt().min(1.0);
+            return MaterialMapping::Metal { albedo: self.ks, fuzz };
+        }
+
+        return MaterialMapping::Lambertian { albedo: self.kd };
+    }
+}
+
+/// One group of faces sharing an object/group name and a material.
+pub struct ObjMesh {
+    pub name: String,
+    pub material: Option<String>,
+    pub mesh: TriangleMesh
+}
+
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: BTreeMap<String, MtlMaterial>
+}
+
+/// A face corner, indices into the position, texcoord and normal arrays.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Faces collected for one (group, material) pair before being turned into a mesh.
+#[derive(Default)]
+struct MeshBuilder {
+    name: String,
+    material: Option<String>,
+    /// The line of the `usemtl` statement naming the material, for errors.
+    material_line: usize,
+    vertex_ids: HashMap<Corner, usize>,
+    corners: Vec<Corner>,
+    indices: Vec<[usize; 3]>
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, corner: Corner) -> usize {
+        if let Some(id) = self.vertex_ids.get(&corner) {
+            return *id;
+        }
+        let id = self.corners.len();
+        self.corners.push(corner);
+        self.vertex_ids.insert(corner, id);
+        return id;
+    }
+
+    fn build(self, positions: &[Vec3], texcoords: &[(f32, f32)], normals: &[Vec3]) -> ObjMesh {
+        // Normals and UVs are only kept when every vertex of the mesh has them.
+        let mesh_normals = if self.corners.iter().all(|c| c.2.is_some()) {
+            Some(self.corners.iter().map(|c| normals[c.2.unwrap()]).collect())
+        } else {
+            None
+        };
+        let mesh_uvs = if self.corners.iter().all(|c| c.1.is_some()) {
+            Some(self.corners.iter().map(|c| texcoords[c.1.unwrap()]).collect())
+        } else {
+            None
+        };
+
+        let mesh = TriangleMesh::new(
+            self.corners.iter().map(|c| positions[c.0]).collect(),
+            mesh_normals,
+            mesh_uvs,
+            self.indices)
+            .expect("Face indices are validated while parsing");
+
+        return ObjMesh { name: self.name, material: self.material, mesh };
+    }
+}
+
+fn parse_floats(path: &str, line: usize, parts: &[&str], min: usize, max: usize) -> Result<Vec<f32>, ObjError> {
+    if parts.len() < min || parts.len() > max {
+        return Err(ObjError {
+            path: path.to_string(),
+            line,
+            message: format!("expected {} to {} numbers, found {}", min, max, parts.len())
+        });
+    }
+
+    let mut values = Vec::with_capacity(parts.len());
+    for part in parts {
+        match part.parse::<f32>() {
+            Ok(v) if v.is_finite() => values.push(v),
+            _ => return Err(ObjError { path: path.to_string(), line, message: format!("invalid number '{}'", part) })
+        }
+    }
+    return Ok(values);
+}
+
+/// An MTL color, either three components or a single value used for all of them.
+fn parse_color(path: &str, line: usize, parts: &[&str]) -> Result<Vec3, ObjError> {
+    let v = parse_floats(path, line, parts, 1, 3)?;
+    match v.as_slice() {
+        [value] => return Ok(Vec3::from_uniform_value(*value)),
+        [r, g, b] => return Ok(Vec3::new(*r, *g, *b)),
+        _ => return Err(ObjError { path: path.to_string(), line, message: format!("expected 1 or 3 numbers, found {}", v.len()) })
+    }
+}
+
+/// Resolves a 1 based, possibly negative (relative to the end) OBJ index.
+fn resolve_index(path: &str, line: usize, text: &str, count: usize, kind: &str) -> Result<usize, ObjError> {
+    let index: i64 = text.parse().map_err(|_| ObjError {
+        path: path.to_string(),
+        line,
+        message: format!("invalid {} index '{}'", kind, text)
+    })?;
+
+    let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(ObjError {
+            path: path.to_string(),
+            line,
+            message: format!("{} index {} is out of range, {} defined so far", kind, index, count)
+        });
+    }
+    return Ok(resolved as usize);
+}
+
+fn parse_corner(
+    path: &str,
+    line: usize,
+    text: &str,
+    counts: (usize, usize, usize)) -> Result<Corner, ObjError> {
+
+    let mut fields = text.split('/');
+    let v = resolve_index(path, line, fields.next().unwrap_or(""), counts.0, "vertex")?;
+
+    let vt = match fields.next() {
+        Some(t) if !t.is_empty() => Some(resolve_index(path, line, t, counts.1, "texture coordinate")?),
+        _ => None
+    };
+    let vn = match fields.next() {
+        Some(n) if !n.is_empty() => Some(resolve_index(path, line, n, counts.2, "normal")?),
+        _ => None
+    };
+
+    if fields.next().is_some() {
+        return Err(ObjError { path: path.to_string(), line, message: format!("malformed face vertex '{}'", text) });
+    }
+    return Ok((v, vt, vn));
+}
+
+/// Loads an OBJ file and any MTL libraries it references.
+///
+/// # Parameters
+///
+/// - `path` : &Path
+/// The .obj file, `mtllib` paths are resolved relative to its directory
+///
+/// # Returns
+///
+/// One mesh per group/object and material combination, polygons fan triangulated,
+/// along with the materials found in the MTL libraries.
+pub fn load(path: &Path) -> Result<ObjModel, ObjError> {
+    let display = path.display().to_string();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ObjError { path: display.clone(), line: 0, message: e.to_string() })?;
+
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    return parse(&display, &contents, base_dir);
+}
+
+fn parse(path: &str, contents: &str, base_dir: &Path) -> Result<ObjModel, ObjError> {
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut texcoords: Vec<(f32, f32)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut materials = BTreeMap::new();
+
+    let mut finished: Vec<MeshBuilder> = Vec::new();
+    let mut current = MeshBuilder { name: String::from("default"), ..MeshBuilder::default() };
+    let mut skipped: BTreeSet<String> = BTreeSet::new();
+
+    for (number, raw_line) in contents.lines().enumerate() {
+        let line = number + 1;
+        let text = match raw_line.find('#') {
+            Some(comment) => &raw_line[..comment],
+            None => raw_line
+        };
+        let parts: Vec<&str> = text.split_whitespace().collect();
+        if parts.is_empty() {
+            continue;
+        }
+
+        match parts[0] {
+            "v" => {
+                // An optional w, or the r g b of vertex colors, follow the position.
+                let v = parse_floats(path, line, &parts[1..], 3, 7)?;
+                positions.push(Vec3::new(v[0], v[1], v[2]));
+            },
+            "vt" => {
+                let v = parse_floats(path, line, &parts[1..], 1, 3)?;
+                texcoords.push((v[0], *v.get(1).unwrap_or(&0.0)));
+            },
+            "vn" => {
+                let v = parse_floats(path, line, &parts[1..], 3, 3)?;
+                normals.push(Vec3::new(v[0], v[1], v[2]));
+            },
+            "f" => {
+                if parts.len() < 4 {
+                    return Err(ObjError {
+                        path: path.to_string(),
+                        line,
+                        message: format!("a face needs at least 3 vertices, found {}", parts.len() - 1)
+                    });
+                }
+
+                let counts = (positions.len(), texcoords.len(), normals.len());
+                let mut ids = Vec::with_capacity(parts.len() - 1);
+                for corner in &parts[1..] {
+                    let corner = parse_corner(path, line, corner, counts)?;
+                    ids.push(current.vertex(corner));
+                }
+
+                for i in 1..ids.len() - 1 {
+                    current.indices.push([ids[0], ids[i], ids[i + 1]]);
+                }
+            },
+            "o" | "g" | "usemtl" => {
+                let value = parts[1..].join(" ");
+                if value.is_empty() && parts[0] == "usemtl" {
+                    return Err(ObjError { path: path.to_string(), line, message: String::from("usemtl needs a material name") });
+                }
+
+                let mut next = MeshBuilder {
+                    name: current.name.clone(),
+                    material: current.material.clone(),
+                    material_line: current.material_line,
+                    ..MeshBuilder::default()
+                };
+                if parts[0] == "usemtl" {
+                    next.material = Some(value);
+                    next.material_line = line;
+                } else if !value.is_empty() {
+                    next.name = value;
+                }
+
+                let previous = std::mem::replace(&mut current, next);
+                if !previous.indices.is_empty() {
+                    finished.push(previous);
+                }
+            },
+            "mtllib" => {
+                for library in &parts[1..] {
+                    let library_path = base_dir.join(library);
+                    let library_display = library_path.display().to_string();
+                    let library_contents = fs::read_to_string(&library_path).map_err(|e| ObjError {
+                        path: path.to_string(),
+                        line,
+                        message: format!("unable to read material library '{}': {}", library_display, e)
+                    })?;
+                    parse_mtl(&library_display, &library_contents, &mut materials)?;
+                }
+            },
+            // Smoothing groups, lines, points and free-form geometry are not rendered.
+            "s" | "l" | "p" | "vp" | "cstype" | "deg" | "curv" | "curv2" | "surf" | "parm" | "end" => {},
+            // Anything else, such as merging groups or texture maps, is skipped with a
+            // warning the first time it is seen rather than failing the whole model.
+            other => {
+                if skipped.insert(other.to_string()) {
+                    eprintln!("warning: {}:{}: skipping unsupported statement '{}'", path, line, other);
+                }
+            }
+        }
+    }
+
+    if !current.indices.is_empty() {
+        finished.push(current);
+    }
+
+    for builder in &finished {
+        if let Some(name) = &builder.material {
+            if !materials.contains_key(name) {
+                return Err(ObjError {
+                    path: path.to_string(),
+                    line: builder.material_line,
+                    message: format!("material '{}' is used but not defined in any mtllib", name)
+                });
+            }
+        }
+    }
+
+    let meshes = finished.into_iter()
+        .map(|builder| builder.build(&positions, &texcoords, &normals))
+        .collect();
+
+    return Ok(ObjModel { meshes, materials });
+}
+
+fn parse_mtl(path: &str, contents: &str, materials: &mut BTreeMap<String, MtlMaterial>) -> Result<(), ObjError> {
+    let mut current: Option<String> = None;
+
+    for (number, raw_line) in contents.lines().enumerate() {
+        let line = number + 1;
+        let text = match raw_line.find('#') {
+            Some(comment) => &raw_line[..comment],
+            None => raw_line
+        };
+        let parts: Vec<&str> = text.split_whitespace().collect();
+        if parts.is_empty() {
+            continue;
+        }
+
+        if parts[0] == "newmtl" {
+            let name = parts[1..].join(" ");
+            if name.is_empty() {
+                return Err(ObjError { path: path.to_string(), line, message: String::from("newmtl needs a material name") });
+            }
+            materials.insert(name.clone(), MtlMaterial::default());
+            current = Some(name);
+            continue;
+        }
+
+        let material = match &current {
+            Some(name) => materials.get_mut(name).unwrap(),
+            None => return Err(ObjError {
+                path: path.to_string(),
+                line,
+                message: format!("'{}' appears before any newmtl", parts[0])
+            })
+        };
+
+        match parts[0] {
+            "Kd" => material.kd = parse_color(path, line, &parts[1..])?,
+            "Ks" => material.ks = parse_color(path, line, &parts[1..])?,
+            "Ke" => material.ke = parse_color(path, line, &parts[1..])?,
+            "Ns" => material.ns = parse_floats(path, line, &parts[1..], 1, 1)?[0],
+            "Ni" => material.ni = parse_floats(path, line, &parts[1..], 1, 1)?[0],
+            "d" => material.d = parse_floats(path, line, &parts[1..], 1, 1)?[0],
+            "Tr" => material.d = 1.0 - parse_floats(path, line, &parts[1..], 1, 1)?[0],
+            "illum" => {
+                material.illum = parts.get(1).and_then(|v| v.parse().ok()).ok_or_else(|| ObjError {
+                    path: path.to_string(),
+                    line,
+                    message: String::from("illum needs an integer illumination model")
+                })?;
+            },
+            // Ambient, transmission filter and texture maps are not supported.
+            _ => {}
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn parse_str(contents: &str) -> Result<ObjModel, ObjError> {
+        return parse("test.obj", contents, Path::new(""));
+    }
+
+    /// A fresh directory holding the given files, for models referencing MTL libraries.
+    fn directory_with(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracing-obj-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        return dir;
+    }
+
+    const SQUARE: &str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+";
+
+    #[test]
+    fn faces_with_texcoords_and_normals() {
+        let model = parse_str(&format!("{}
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 4/4/1
+", SQUARE)).unwrap();
+
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.uvs.as_ref().unwrap()[2], (1.0, 1.0));
+        assert!(mesh.normals.as_ref().unwrap().iter().all(|n| n.z == 1.0));
+    }
+
+    #[test]
+    fn vertex_only_and_normal_only_corners() {
+        let model = parse_str(&format!("{}vn 0 0 1\nf 1//1 2//1 3//1\nf 1 3 4\n", SQUARE)).unwrap();
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.indices.len(), 2);
+        // The second face has no normals, so the mesh drops them altogether.
+        assert!(mesh.normals.is_none());
+        assert!(mesh.uvs.is_none());
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_end() {
+        let model = parse_str(&format!("{}f -4 -3 -2\n", SQUARE)).unwrap();
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.positions[mesh.indices[0][0]].x, 0.0);
+        assert_eq!(mesh.positions[mesh.indices[0][1]].x, 1.0);
+        assert_eq!(mesh.positions[mesh.indices[0][2]].y, 1.0);
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let model = parse_str("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4 5\n").unwrap();
+        assert_eq!(model.meshes[0].mesh.indices, vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn vertex_colors_and_unsupported_statements_are_ignored() {
+        let model = parse_str("v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 0 1 0 0 0 1\nmg 1\nusemap off\nf 1 2 3\n").unwrap();
+        assert_eq!(model.meshes[0].mesh.positions.len(), 3);
+        assert_eq!(model.meshes[0].mesh.positions[1].x, 1.0);
+    }
+
+    #[test]
+    fn usemtl_resolves_against_mtllib() {
+        let dir = directory_with("usemtl", &[
+            ("scene.mtl", "newmtl red\nKd 0.8 0.1 0.1\n\nnewmtl grey\nKd 0.5\nKs 0\n"),
+            ("scene.obj", "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\nusemtl grey\nf 3 2 1\n")
+        ]);
+        let model = load(&dir.join("scene.obj")).unwrap();
+
+        assert_eq!(model.meshes.len(), 2);
+        assert_eq!(model.meshes[0].material.as_deref(), Some("red"));
+        assert_eq!(model.meshes[1].material.as_deref(), Some("grey"));
+        assert_eq!(model.materials["red"].kd.x, 0.8);
+        assert_eq!(model.materials["grey"].kd.y, 0.5);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn undefined_material_is_an_error() {
+        let err = parse_str("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl missing\nf 1 2 3\n").err().unwrap();
+        assert!(err.message.contains("'missing'"), "{}", err);
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_line() {
+        let err = parse_str("v 0 0 0\nv 1 0 0\n\nf 1 2 3\n").err().unwrap();
+        assert_eq!(err.line, 4);
+        assert!(err.message.contains("vertex index 3"), "{}", err);
+
+        let err = parse_str("# comment\nv 0 0\n").err().unwrap();
+        assert_eq!(err.line, 2);
+
+        let err = parse_str("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 0\n").err().unwrap();
+        assert_eq!(err.line, 4);
+
+        // An undefined material is only found at the end, but blamed on its usemtl.
+        let err = parse_str("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl missing\ng part\nf 1 2 3\n").err().unwrap();
+        assert_eq!(err.line, 4);
+
+        let dir = directory_with("mtl-error", &[
+            ("bad.mtl", "newmtl a\nKd 1 1 1\nKd 1 1\n"),
+            ("bad.obj", "mtllib bad.mtl\n")
+        ]);
+        let err = load(&dir.join("bad.obj")).err().unwrap();
+        assert!(err.path.ends_with("bad.mtl"), "{}", err);
+        assert_eq!(err.line, 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,47 +1,117 @@
-use rand::prelude::*;
-use crate::{math::dot, vec3::Vec3};
-
-/// Helper function to generate a random value between 0 and 1.
-///
-/// # Parameters
-///
-/// - `range` : i32
-/// The maximum exclusive value that rand will generate from
-///
-/// # Returns
-///
-/// A normalized random value between [0..1)
-pub fn generate_normalized_ran(range: i32) -> f32 {
-    let random_value = rand::thread_rng().gen_range(0..range);
-    return random_value as f32 / range as f32;
-}
-
-/// Picks a random point inside a unit sphere
-///
-/// # Returns
-///
-/// A random unit inside a unit sphere where all x, y, z
-/// values are between [0..1).
-pub fn random_in_unit_sphere() -> Vec3 {
-    let mut p = 2.0 * Vec3::new(
-        generate_normalized_ran(100), 
-        generate_normalized_ran(100), 
-        generate_normalized_ran(100)) - Vec3::from_uniform_value(1.0);
-
-    while p.length_sq() >= 1.0 {
-        p = 2.0 * Vec3::new(
-            generate_normalized_ran(100), 
-            generate_normalized_ran(100), 
-            generate_normalized_ran(100)) - Vec3::from_uniform_value(1.0);
-    }
-
-    return p;
-}
-
-pub fn random_in_unit_disk() -> Vec3 {
-    let mut p = 2.0 * Vec3::new(generate_normalized_ran(100), generate_normalized_ran(100), 0.0) - Vec3::new(1.0, 1.0, 0.0);
-    while dot(p, p) >= 1.0 {
-        p = 2.0 * Vec3::new(generate_normalized_ran(100), generate_normalized_ran(100), 0.0) - Vec3::new(1.0, 1.0, 0.0);
-    }
-    return p;
-}
\ No newline at end of file
+use rand::prelude::*;
+use rand_pcg::Pcg32;
+use crate::{sampler::Sampler, vec3::Vec3};
+
+/// A deterministic random number generator handed to everything that needs randomness
+/// while tracing a single sample, so the same seed always reproduces the same image.
+pub struct RandomContext {
+    rng: Pcg32
+}
+
+/// SplitMix64 finalizer, used to scramble seeds so neighbouring pixels get unrelated streams.
+pub fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+impl RandomContext {
+    pub fn from_seed(seed: u64) -> RandomContext {
+        return RandomContext {
+            rng: Pcg32::new(mix(seed), 0xa02bdbf7bb3c0a7)
+        }
+    }
+
+    /// Creates the generator for one sample of one pixel.
+    ///
+    /// # Parameters
+    ///
+    /// - `seed` : u64
+    /// The global, user provided seed
+    /// - `x`, `y` : i32
+    /// The pixel being sampled
+    /// - `sample` : i32
+    /// The index of the sample within the pixel
+    ///
+    /// # Returns
+    ///
+    /// A generator that only depends on its inputs, never on thread or tile order.
+    pub fn for_sample(seed: u64, x: i32, y: i32, sample: i32) -> RandomContext {
+        let pixel = ((y as u32 as u64) << 32) | (x as u32 as u64);
+        let state = mix(seed ^ mix(pixel));
+        return RandomContext {
+            rng: Pcg32::new(state, sample as u32 as u64)
+        }
+    }
+
+    /// A uniformly distributed, full precision value in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        return self.rng.gen::<f32>();
+    }
+}
+
+/// Helper function to generate a random value between 0 and 1.
+///
+/// # Parameters
+///
+/// - `rng` : &mut RandomContext
+/// The generator to draw from
+///
+/// # Returns
+///
+/// A continuous, normalized random value between [0..1)
+pub fn generate_normalized_ran(rng: &mut RandomContext) -> f32 {
+    return rng.next_f32();
+}
+
+/// Picks a random point inside a unit sphere
+///
+/// Maps three sample dimensions straight onto the ball instead of rejection sampling,
+/// so the sampler always hands out a fixed number of dimensions per call.
+///
+/// # Returns
+///
+/// A random point inside a unit sphere where all x, y, z
+/// values are between (-1..1).
+pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let direction = uniform_sphere(sampler.get_2d());
+    let radius = sampler.get_1d().cbrt();
+
+    return radius * direction;
+}
+
+/// Picks a random direction, uniformly distributed over the unit sphere.
+pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+    return uniform_sphere(sampler.get_2d());
+}
+
+/// Maps a 2D sample in [0, 1)^2 to a point on the unit sphere with uniform density.
+pub fn uniform_sphere(u: (f32, f32)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.1;
+
+    return Vec3::new(r * phi.cos(), r * phi.sin(), z);
+}
+
+/// Picks a random point inside the unit disk on the xy plane using the concentric
+/// mapping, which keeps the stratification of the 2D sample intact.
+pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u, v) = sampler.get_2d();
+    let ox = 2.0 * u - 1.0;
+    let oy = 2.0 * v - 1.0;
+
+    if ox == 0.0 && oy == 0.0 {
+        return Vec3::zero();
+    }
+
+    let quarter_pi = std::f32::consts::FRAC_PI_4;
+    let (r, theta) = if ox.abs() > oy.abs() {
+        (ox, quarter_pi * (oy / ox))
+    } else {
+        (oy, 2.0 * quarter_pi - quarter_pi * (ox / oy))
+    };
+
+    return Vec3::new(r * theta.cos(), r * theta.sin(), 0.0);
+}
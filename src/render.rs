@@ -0,0 +1,201 @@
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::mpsc;
+use std::thread;
+
+use crate::vec3::Vec3;
+
+/// Width and height in pixels of the square tiles the image plane is split into.
+pub const TILE_SIZE: i32 = 16;
+
+/// An in-memory image of linear radiance values.
+///
+/// Rows are stored top to bottom, so `y = 0` is the first row written to the file.
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<Vec3>
+}
+
+impl Framebuffer {
+    pub fn new(width: i32, height: i32) -> Framebuffer {
+        return Framebuffer {
+            width,
+            height,
+            pixels: vec![Vec3::zero(); (width * height) as usize]
+        }
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, value: Vec3) {
+        self.pixels[(y * self.width + x) as usize] = value;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Tile {
+    x0: i32,
+    y0: i32,
+    x1: i32,
+    y1: i32
+}
+
+fn split_into_tiles(width: i32, height: i32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    let mut y0 = 0;
+    while y0 < height {
+        let mut x0 = 0;
+        while x0 < width {
+            tiles.push(Tile {
+                x0,
+                y0,
+                x1: (x0 + TILE_SIZE).min(width),
+                y1: (y0 + TILE_SIZE).min(height)
+            });
+            x0 += TILE_SIZE;
+        }
+        y0 += TILE_SIZE;
+    }
+    return tiles;
+}
+
+/// The number of threads to use when none is requested explicitly.
+pub fn default_thread_count() -> usize {
+    return thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+}
+
+/// Renders the image plane in parallel, tile by tile.
+///
+/// # Parameters
+///
+/// - `width`, `height` : i32
+/// The resolution of the image
+/// - `threads` : usize
+/// How many worker threads pull tiles off the shared queue
+/// - `shade` : Fn(i32, i32) -> Vec3
+/// Computes the final linear color of pixel (i, j), where j = 0 is the bottom row
+/// like the camera's (s, t) convention. It must only depend on its arguments for the
+/// output to be independent of the thread count.
+///
+/// # Returns
+///
+/// The fully rendered framebuffer.
+pub fn render_tiles<F>(width: i32, height: i32, threads: usize, shade: F) -> Framebuffer
+    where F: Fn(i32, i32) -> Vec3 + Sync {
+
+    let tiles = split_into_tiles(width, height);
+    let next_tile = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<(Tile, Vec<Vec3>)>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let sender = sender.clone();
+            let tiles = &tiles;
+            let next_tile = &next_tile;
+            let shade = &shade;
+
+            scope.spawn(move || {
+                loop {
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() {
+                        break;
+                    }
+
+                    let tile = tiles[index];
+                    let mut pixels = Vec::with_capacity(((tile.x1 - tile.x0) * (tile.y1 - tile.y0)) as usize);
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            pixels.push(shade(x, height - 1 - y));
+                        }
+                    }
+
+                    sender.send((tile, pixels)).expect("Framebuffer receiver dropped");
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    let mut framebuffer = Framebuffer::new(width, height);
+    for (tile, pixels) in receiver {
+        let mut values = pixels.into_iter();
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                framebuffer.set(x, y, values.next().unwrap());
+            }
+        }
+    }
+
+    return framebuffer;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+    use crate::camera::Camera;
+    use crate::cli::ImageSettings;
+    use crate::hitable_list::HitableList;
+    use crate::light::LightList;
+    use crate::random_utils::RandomContext;
+    use crate::sampler::{self, SamplerKind};
+    use crate::scenes;
+    use crate::{color, SceneContext};
+
+    #[test]
+    fn tiles_cover_the_image_once() {
+        let tiles = split_into_tiles(40, 20);
+        assert_eq!(tiles.len(), 6);
+        let covered: i32 = tiles.iter().map(|t| (t.x1 - t.x0) * (t.y1 - t.y0)).sum();
+        assert_eq!(covered, 40 * 20);
+    }
+
+    #[test]
+    fn output_does_not_depend_on_the_thread_count() {
+        let scene = scenes::cornell_box();
+        let world = Bvh::build(HitableList::from_list(scene.primitives));
+        let lights = LightList::from_primitives(world.primitives(), &scene.materials, false);
+        let (width, height, samples) = (37, 23, 4);
+        let ctx = SceneContext {
+            world: &world,
+            media: &scene.media,
+            materials: &scene.materials,
+            lights: &lights,
+            settings: ImageSettings {
+                width,
+                height,
+                samples,
+                max_depth: 8,
+                diffuse_depth: 8,
+                specular_depth: 8,
+                transmission_depth: 8,
+                roulette_depth: 2
+            },
+            background: &scene.background
+        };
+        let settings = scene.camera;
+        let camera = Camera::new(settings.projection, settings.lookfrom, settings.lookat, settings.vup,
+            settings.vfov, width as f32 / height as f32, settings.aperture, settings.focus_dist);
+
+        let render = |threads: usize| render_tiles(width, height, threads, |i, j| {
+            let mut sampler = sampler::create_sampler(SamplerKind::Sobol, 7, samples);
+            let mut sum = Vec3::zero();
+            for s in 0..samples {
+                sampler.start_pixel_sample(i, j, s);
+                let (du, dv) = sampler.get_2d();
+                let u = (i as f32 + du) / width as f32;
+                let v = (j as f32 + dv) / height as f32;
+                if let Some(r) = camera.get_ray(u, v, sampler.as_mut()) {
+                    sum = sum + color(&ctx, &r, sampler.as_mut(), &mut RandomContext::for_sample(7, i, j, s));
+                }
+            }
+            return sum / samples as f32;
+        });
+
+        let single = render(1);
+        let parallel = render(4);
+        assert!(single.pixels.iter().any(|p| p.x > 0.0));
+        for (a, b) in single.pixels.iter().zip(parallel.pixels.iter()) {
+            assert_eq!([a.x.to_bits(), a.y.to_bits(), a.z.to_bits()], [b.x.to_bits(), b.y.to_bits(), b.z.to_bits()]);
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use crate::random_utils::{mix, RandomContext};
+
+/// Source of the sample values consumed while tracing one path.
+///
+/// Every value handed out is a continuous number in [0, 1). Consumers ask for one
+/// dimension at a time, or two at once for things like pixel jitter and lens positions
+/// which should be distributed well as a pair. Samplers keep count of the dimensions
+/// handed out since `start_pixel_sample`, so consumers must always draw the same number
+/// of dimensions for a given decision for the samples to stay correlated across a pixel.
+pub trait Sampler {
+    /// Resets the sampler for sample `index` of pixel (x, y).
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: i32);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+/// The samplers which can be picked when rendering.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" | "random" => return Some(SamplerKind::Independent),
+            "stratified" => return Some(SamplerKind::Stratified),
+            "halton" => return Some(SamplerKind::Halton),
+            "sobol" => return Some(SamplerKind::Sobol),
+            _ => return None
+        }
+    }
+}
+
+/// Creates a sampler of the given kind.
+///
+/// # Parameters
+///
+/// - `kind` : SamplerKind
+/// Which sampler to create
+/// - `seed` : u64
+/// The global seed, every sampler is deterministic given it
+/// - `samples_per_pixel` : i32
+/// How many samples each pixel takes, the stratified sampler divides its strata by it
+pub fn create_sampler(kind: SamplerKind, seed: u64, samples_per_pixel: i32) -> Box<dyn Sampler> {
+    match kind {
+        SamplerKind::Independent => return Box::new(IndependentSampler::new(seed)),
+        SamplerKind::Stratified => return Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+        SamplerKind::Halton => return Box::new(HaltonSampler::new(seed)),
+        SamplerKind::Sobol => return Box::new(SobolSampler::new(seed))
+    }
+}
+
+/// The largest float below 1, samples are clamped to it to stay inside [0, 1).
+const ONE_MINUS_EPSILON: f32 = 0.99999994;
+
+fn pixel_hash(seed: u64, x: i32, y: i32) -> u64 {
+    let pixel = ((y as u32 as u64) << 32) | (x as u32 as u64);
+    return mix(seed ^ mix(pixel));
+}
+
+/// Converts the top 24 bits of a hash into a float in [0, 1).
+fn to_unit_float(bits: u32) -> f32 {
+    return (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32);
+}
+
+/// Uniform, independent random samples for every dimension.
+pub struct IndependentSampler {
+    seed: u64,
+    rng: RandomContext
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        return IndependentSampler {
+            seed,
+            rng: RandomContext::from_seed(seed)
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.rng = RandomContext::for_sample(self.seed, x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        return self.rng.next_f32();
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let u = self.rng.next_f32();
+        let v = self.rng.next_f32();
+        return (u, v);
+    }
+}
+
+/// Returns element `i` of a random permutation of [0, l) selected by `p`.
+///
+/// Kensler's cycle walking hash, lets every dimension visit the strata in a different
+/// order without storing the permutations.
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < l {
+            break;
+        }
+    }
+
+    return i.wrapping_add(p) % l;
+}
+
+/// Jittered grid sampling, each dimension (or pair of dimensions) is split into
+/// `samples_per_pixel` strata which are visited in a per pixel, per dimension
+/// shuffled order.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    x_strata: u32,
+    y_strata: u32,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: i32) -> StratifiedSampler {
+        let spp = samples_per_pixel.max(1) as u32;
+
+        // Pick the most square grid whose cell count is exactly the sample count.
+        let mut x_strata = (spp as f32).sqrt() as u32;
+        while !spp.is_multiple_of(x_strata) {
+            x_strata -= 1;
+        }
+
+        return StratifiedSampler {
+            seed,
+            samples_per_pixel: spp,
+            x_strata,
+            y_strata: spp / x_strata,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0
+        }
+    }
+
+    fn hash(&self, salt: u64) -> u64 {
+        return mix(self.pixel_seed ^ mix(((self.dimension as u64) << 32) ^ salt));
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel_seed = pixel_hash(self.seed, x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let permutation = self.hash(0) as u32;
+        let jitter = to_unit_float(self.hash(1 + self.index as u64) as u32);
+        self.dimension += 1;
+
+        // Samples past the requested count wrap around onto the same strata.
+        let stratum = permutation_element(self.index % self.samples_per_pixel, self.samples_per_pixel, permutation);
+        return ((stratum as f32 + jitter) / self.samples_per_pixel as f32).min(ONE_MINUS_EPSILON);
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let permutation = self.hash(0) as u32;
+        let jitter = self.hash(1 + self.index as u64);
+        self.dimension += 2;
+
+        let stratum = permutation_element(self.index % self.samples_per_pixel, self.samples_per_pixel, permutation);
+        let sx = stratum % self.x_strata;
+        let sy = stratum / self.x_strata;
+
+        let u = (sx as f32 + to_unit_float(jitter as u32)) / self.x_strata as f32;
+        let v = (sy as f32 + to_unit_float((jitter >> 32) as u32)) / self.y_strata as f32;
+        return (u.min(ONE_MINUS_EPSILON), v.min(ONE_MINUS_EPSILON));
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311
+];
+
+/// Digit reversal of `index` in the given base, the building block of the Halton sequence.
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed: u64 = 0;
+
+    while index > 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * base as u64 + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+
+    return ((reversed as f64 * inv_base_n) as f32).min(ONE_MINUS_EPSILON);
+}
+
+/// The Halton sequence, dimension `d` uses the `d`th prime as its base. Each pixel
+/// applies its own Cranley-Patterson rotation so neighbouring pixels decorrelate.
+/// Dimensions past the prime table fall back to hashed random values.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        return HaltonSampler {
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0
+        }
+    }
+
+    fn sample_dimension(&self, dimension: u32) -> f32 {
+        let offset = to_unit_float(mix(self.pixel_seed ^ mix(dimension as u64)) as u32);
+
+        if (dimension as usize) >= PRIMES.len() {
+            let random = mix(self.pixel_seed ^ mix(((dimension as u64) << 32) | self.index as u64));
+            return to_unit_float(random as u32);
+        }
+
+        let value = radical_inverse(PRIMES[dimension as usize], self.index) + offset;
+        return if value >= 1.0 { (value - 1.0).min(ONE_MINUS_EPSILON) } else { value };
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel_seed = pixel_hash(self.seed, x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let value = self.sample_dimension(self.dimension);
+        self.dimension += 1;
+        return value;
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let u = self.sample_dimension(self.dimension);
+        let v = self.sample_dimension(self.dimension + 1);
+        self.dimension += 2;
+        return (u, v);
+    }
+}
+
+/// Laine-Karras style hash which only lets bits affect the bits above them, the core of
+/// hash based Owen scrambling.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    return x;
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    return laine_karras_permutation(x.reverse_bits(), seed).reverse_bits();
+}
+
+/// Second dimension of the Sobol sequence, the first is the bit reversed index.
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut v: u32 = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    return result;
+}
+
+/// Owen scrambled Sobol samples, following Burley's "Practical Hash-based Owen
+/// Scrambling". Every pair of dimensions uses the first two Sobol dimensions with its
+/// own scramble and a shuffled sample index, so pairs stay well stratified without
+/// needing a large table of direction numbers.
+pub struct SobolSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        return SobolSampler {
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0
+        }
+    }
+
+    fn dimension_hash(&self, salt: u64) -> u32 {
+        return mix(self.pixel_seed ^ mix(((self.dimension as u64) << 8) | salt)) as u32;
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel_seed = pixel_hash(self.seed, x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let index = nested_uniform_scramble(self.index, self.dimension_hash(0));
+        let value = nested_uniform_scramble(index.reverse_bits(), self.dimension_hash(1));
+        self.dimension += 1;
+        return to_unit_float(value);
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let index = nested_uniform_scramble(self.index, self.dimension_hash(0));
+        let u = nested_uniform_scramble(index.reverse_bits(), self.dimension_hash(1));
+        let v = nested_uniform_scramble(sobol_second_dimension(index), self.dimension_hash(2));
+        self.dimension += 2;
+        return (to_unit_float(u), to_unit_float(v));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The 1D strata, out of `count`, hit by the first `count` samples of each of the
+    /// first few dimensions of pixel (x, y).
+    fn strata_1d(sampler: &mut dyn Sampler, count: u32, dimensions: usize) -> Vec<Vec<u32>> {
+        let mut strata = vec![Vec::new(); dimensions];
+        for index in 0..count {
+            sampler.start_pixel_sample(3, 7, index as i32);
+            for stratum in strata.iter_mut() {
+                stratum.push((sampler.get_1d() * count as f32) as u32);
+            }
+        }
+        return strata;
+    }
+
+    fn is_permutation(mut values: Vec<u32>) -> bool {
+        values.sort_unstable();
+        return values.iter().enumerate().all(|(i, &v)| v == i as u32);
+    }
+
+    #[test]
+    fn samples_stay_in_the_unit_interval() {
+        let kinds = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+        for kind in kinds {
+            let mut sampler = create_sampler(kind, 42, 16);
+            for (x, y) in [(0, 0), (-1, 5), (1023, 767)] {
+                // Past the sample count and the Halton prime table as well.
+                for index in 0..40 {
+                    sampler.start_pixel_sample(x, y, index);
+                    for _ in 0..40 {
+                        let value = sampler.get_1d();
+                        let (u, v) = sampler.get_2d();
+                        for value in [value, u, v] {
+                            assert!((0.0..1.0).contains(&value), "{:?} gave {}", kind, value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_stratum_once() {
+        for count in [16, 64, 12] {
+            let mut sampler = StratifiedSampler::new(9, count as i32);
+            for dimension in strata_1d(&mut sampler, count, 5) {
+                assert!(is_permutation(dimension), "{} samples", count);
+            }
+        }
+
+        // Pairs are spread over a 4x4 grid.
+        let mut sampler = StratifiedSampler::new(9, 16);
+        for pair in 0..3 {
+            let cells = (0..16).map(|index| {
+                sampler.start_pixel_sample(3, 7, index);
+                for _ in 0..pair {
+                    sampler.get_2d();
+                }
+                let (u, v) = sampler.get_2d();
+                return (v * 4.0) as u32 * 4 + (u * 4.0) as u32;
+            }).collect();
+            assert!(is_permutation(cells));
+        }
+    }
+
+    #[test]
+    fn sobol_samples_fill_every_elementary_interval_once() {
+        for count in [4, 16, 256] {
+            let mut sampler = SobolSampler::new(9);
+            for dimension in strata_1d(&mut sampler, count, 5) {
+                assert!(is_permutation(dimension), "{} samples", count);
+            }
+        }
+
+        // Sixteen pairs put one point in each cell of every 16 cell grid, 1x16 to 16x1.
+        let mut sampler = SobolSampler::new(9);
+        let points: Vec<(f32, f32)> = (0..16).map(|index| {
+            sampler.start_pixel_sample(3, 7, index);
+            sampler.get_1d();
+            return sampler.get_2d();
+        }).collect();
+        for x_cells in [1u32, 2, 4, 8, 16] {
+            let y_cells = 16 / x_cells;
+            let cells = points.iter()
+                .map(|&(u, v)| (v * y_cells as f32) as u32 * x_cells + (u * x_cells as f32) as u32)
+                .collect();
+            assert!(is_permutation(cells), "{}x{}", x_cells, y_cells);
+        }
+    }
+
+    #[test]
+    fn radical_inverse_reverses_the_digits() {
+        assert_eq!(radical_inverse(2, 0), 0.0);
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 2), 0.25);
+        assert_eq!(radical_inverse(2, 3), 0.75);
+        assert_eq!(radical_inverse(2, 6), 0.375);
+        assert!((radical_inverse(3, 1) - 1.0 / 3.0).abs() < 1e-7);
+        assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 1e-7);
+        // 7 is 12 in base 5, reversed to 0.21.
+        assert!((radical_inverse(5, 7) - 0.44).abs() < 1e-7);
+    }
+
+    #[test]
+    fn halton_samples_are_rotated_radical_inverses() {
+        let mut sampler = HaltonSampler::new(9);
+        let samples: Vec<(f32, f32)> = (0..27).map(|index| {
+            sampler.start_pixel_sample(3, 7, index);
+            return (sampler.get_1d(), sampler.get_1d());
+        }).collect();
+
+        // Sample 0 is the pixel's rotation itself.
+        let (offset_x, offset_y) = samples[0];
+        for (index, &(x, y)) in samples.iter().enumerate() {
+            let unrotated_x = (x - offset_x).rem_euclid(1.0);
+            let unrotated_y = (y - offset_y).rem_euclid(1.0);
+            assert!((unrotated_x - radical_inverse(2, index as u32)).abs() < 1e-5, "{}", index);
+            assert!((unrotated_y - radical_inverse(3, index as u32)).abs() < 1e-5, "{}", index);
+        }
+    }
+}
@@ -0,0 +1,1100 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::background::{Background, EnvironmentMap};
+use crate::bvh::Bvh;
+use crate::camera::{FisheyeMapping, Projection};
+use crate::image_reader::{self, ReadError};
+use crate::instance::Instance;
+use crate::material::{Conductor, Lambertian, Metal, Dielectric, DiffuseLight, MaterialHandle, MaterialRegistry, Principled, RoughDielectric, VolumeScatter};
+use crate::medium::{Density, DensityGrid, Medium, MediumBounds, PhaseFunction};
+use crate::mesh::{Triangle, TriangleMesh};
+use crate::obj::{self, MaterialMapping, MtlMaterial, ObjError, ObjModel};
+use crate::random_utils::RandomContext;
+use crate::scenes::{CameraSettings, RenderSettings, Scene};
+use crate::shapes::{Cuboid, Disk, Hitable, Plane, Primitive, Quad, Sphere};
+use crate::texture::{Checker, ImageTexture, NoisePattern, NoiseTexture, SolidColor, Texture, WrapMode};
+use crate::transform::{AnimatedTransform, Matrix4, Transform, TransformParts};
+use crate::vec3::Vec3;
+
+/// Everything that can go wrong turning a scene file into a `Scene`.
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: String, message: String },
+    Parse { path: String, message: String },
+    /// A shape refers to a material name which is not defined under `[materials]`.
+    UnknownMaterial { shape: String, name: String },
+    InvalidValue { context: String, message: String },
+    Obj(ObjError),
+    Image(ReadError)
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, message } => write!(f, "Unable to read scene '{}': {}", path, message),
+            SceneError::Parse { path, message } => write!(f, "Unable to parse scene '{}': {}", path, message),
+            SceneError::UnknownMaterial { shape, name } =>
+                write!(f, "Material '{}' used by {} is not defined under [materials]", name, shape),
+            SceneError::InvalidValue { context, message } => write!(f, "Invalid {}: {}", context, message),
+            SceneError::Obj(err) => write!(f, "Unable to load model {}", err),
+            SceneError::Image(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    camera: CameraDescription,
+    #[serde(default)]
+    render: RenderDescription,
+    /// What rays leaving the scene see, the sky gradient when left out.
+    background: Option<BackgroundDescription>,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDescription>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    shapes: Vec<ShapeDescription>,
+    /// Named groups of shapes which are only drawn through instances.
+    #[serde(default)]
+    objects: BTreeMap<String, ObjectDescription>,
+    #[serde(default)]
+    media: Vec<MediumDescription>,
+    fog: Option<FogDescription>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDescription {
+    shapes: Vec<ShapeDescription>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDescription {
+    #[serde(default)]
+    projection: ProjectionDescription,
+    lookfrom: [f32; 3],
+    lookat: [f32; 3],
+    #[serde(default = "default_vup")]
+    vup: [f32; 3],
+    vfov: f32,
+    #[serde(default)]
+    aperture: f32,
+    focus_dist: Option<f32>,
+    /// Moving shapes are at their start at time 0 and their end at time 1, which the
+    /// shutter times must lie between.
+    #[serde(default)]
+    shutter_open: f32,
+    #[serde(default = "default_one")]
+    shutter_close: f32
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum ProjectionDescription {
+    #[default]
+    Perspective,
+    Orthographic,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+    Equirectangular
+}
+
+fn default_vup() -> [f32; 3] {
+    return [0.0, 1.0, 0.0];
+}
+
+fn default_one() -> f32 {
+    return 1.0;
+}
+
+fn default_white() -> [f32; 3] {
+    return [1.0, 1.0, 1.0];
+}
+
+fn default_wrap() -> String {
+    return String::from("repeat");
+}
+
+fn default_octaves() -> u32 {
+    return 7;
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RenderDescription {
+    width: Option<i32>,
+    height: Option<i32>,
+    samples: Option<i32>,
+    max_depth: Option<i32>,
+    diffuse_depth: Option<i32>,
+    specular_depth: Option<i32>,
+    transmission_depth: Option<i32>,
+    roulette_depth: Option<i32>
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDescription {
+    Constant { color: [f32; 3] },
+    Gradient { bottom: [f32; 3], top: [f32; 3] },
+    /// A latitude/longitude image, rotated by `rotation` degrees around the y axis.
+    Environment {
+        path: String,
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default = "default_one")]
+        intensity: f32
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDescription {
+    Solid { color: [f32; 3] },
+    /// Cubes of `scale` units alternating between the two colors.
+    Checker { even: [f32; 3], odd: [f32; 3], #[serde(default = "default_one")] scale: f32 },
+    /// A PNG, PPM or Radiance image, `wrap` being repeat, mirror or clamp.
+    Image { path: String, #[serde(default = "default_wrap")] wrap: String },
+    /// A perlin, turbulence or marble pattern. The seed picks the noise tables.
+    Noise {
+        pattern: String,
+        #[serde(default = "default_white")]
+        color: [f32; 3],
+        #[serde(default = "default_one")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default)]
+        seed: u64
+    }
+}
+
+/// A color written inline or the name of a texture defined under `[textures]`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorSource {
+    Color([f32; 3]),
+    Texture(String)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDescription {
+    Lambertian { albedo: ColorSource },
+    Metal { albedo: ColorSource, #[serde(default)] fuzz: f32 },
+    Dielectric { ref_idx: f32 },
+    DiffuseLight { emit: [f32; 3] },
+    /// A GGX metal, either one of the named metals or a complex index `eta + i k`.
+    Conductor {
+        metal: Option<String>,
+        eta: Option<[f32; 3]>,
+        k: Option<[f32; 3]>,
+        #[serde(default)]
+        roughness: f32
+    },
+    RoughDielectric { ior: f32, #[serde(default)] roughness: f32 },
+    /// The principled uber material, every parameter left out keeping its default.
+    Principled {
+        base_color: Option<ColorSource>,
+        metallic: Option<ScalarSource>,
+        roughness: Option<ScalarSource>,
+        specular: Option<ScalarSource>,
+        specular_tint: Option<ScalarSource>,
+        sheen: Option<ScalarSource>,
+        clearcoat: Option<ScalarSource>,
+        clearcoat_roughness: Option<ScalarSource>,
+        transmission: Option<ScalarSource>,
+        ior: Option<f32>
+    }
+}
+
+/// A number in [0, 1] written inline or the name of a texture defined under `[textures]`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScalarSource {
+    Value(f32),
+    Texture(String)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDescription {
+    /// A sphere moving in a straight line to `end_center` while the shutter is open.
+    Sphere { center: [f32; 3], end_center: Option<[f32; 3]>, radius: f32, material: String },
+    Triangle { vertices: [[f32; 3]; 3], material: String },
+    /// An infinite plane through `point`.
+    Plane { point: [f32; 3], normal: [f32; 3], material: String },
+    /// The parallelogram spanned by the edges `u` and `v` from `corner`.
+    Quad { corner: [f32; 3], u: [f32; 3], v: [f32; 3], material: String },
+    /// An axis aligned rectangle between two opposite corners sharing one coordinate.
+    Rectangle { min: [f32; 3], max: [f32; 3], material: String },
+    Disk { center: [f32; 3], normal: [f32; 3], radius: f32, material: String },
+    /// An axis aligned box between two opposite corners.
+    Box { min: [f32; 3], max: [f32; 3], material: String },
+    Mesh {
+        positions: Vec<[f32; 3]>,
+        /// Three vertex indices per triangle.
+        indices: Vec<[usize; 3]>,
+        normals: Option<Vec<[f32; 3]>>,
+        uvs: Option<Vec<[f32; 2]>>,
+        material: String
+    },
+    /// A copy of the named object, scaled, then rotated by `rotate` degrees around x, y
+    /// and z in turn, then moved by `translate`, and finally transformed by the row
+    /// major `matrix`, whose last row must be `[0, 0, 0, 1]`. The `end_` parts, when given, are blended towards while the
+    /// shutter is open. A material replaces the object's own.
+    Instance {
+        object: String,
+        #[serde(default)]
+        translate: [f32; 3],
+        #[serde(default)]
+        rotate: [f32; 3],
+        #[serde(default = "default_scale")]
+        scale: ScaleDescription,
+        end_translate: Option<[f32; 3]>,
+        end_rotate: Option<[f32; 3]>,
+        end_scale: Option<ScaleDescription>,
+        matrix: Option<[[f32; 4]; 4]>,
+        material: Option<String>
+    },
+    /// A Wavefront OBJ model. Without a material the model's MTL materials are used,
+    /// `groups` restricts it to the listed object/group names.
+    Obj { path: String, material: Option<String>, groups: Option<Vec<String>> }
+}
+
+impl ShapeDescription {
+    fn material(&self) -> Option<&str> {
+        match self {
+            ShapeDescription::Sphere { material, .. } => return Some(material),
+            ShapeDescription::Triangle { material, .. } => return Some(material),
+            ShapeDescription::Plane { material, .. } => return Some(material),
+            ShapeDescription::Quad { material, .. } => return Some(material),
+            ShapeDescription::Rectangle { material, .. } => return Some(material),
+            ShapeDescription::Disk { material, .. } => return Some(material),
+            ShapeDescription::Box { material, .. } => return Some(material),
+            ShapeDescription::Mesh { material, .. } => return Some(material),
+            ShapeDescription::Instance { material, .. } => return material.as_deref(),
+            ShapeDescription::Obj { material, .. } => return material.as_deref()
+        }
+    }
+}
+
+/// A volume filling the inside of `boundary`, which itself is not drawn. With a grid
+/// the density is the grid's values, stretched over the boundary's box, times `density`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediumDescription {
+    boundary: BoundaryDescription,
+    density: f32,
+    #[serde(default = "default_white")]
+    albedo: [f32; 3],
+    #[serde(default)]
+    phase: PhaseDescription,
+    grid: Option<GridDescription>
+}
+
+/// A constant density medium filling the whole scene, or only what lies below `height`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDescription {
+    density: f32,
+    #[serde(default = "default_white")]
+    albedo: [f32; 3],
+    #[serde(default)]
+    phase: PhaseDescription,
+    height: Option<f32>
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BoundaryDescription {
+    Sphere { center: [f32; 3], radius: f32 },
+    Box { min: [f32; 3], max: [f32; 3] }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PhaseDescription {
+    #[default]
+    Isotropic,
+    HenyeyGreenstein { g: f32 }
+}
+
+/// Densities for `resolution` cells, x varying fastest, then y, then z.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GridDescription {
+    resolution: [usize; 3],
+    values: Vec<f32>
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDescription {
+    Uniform(f32),
+    PerAxis([f32; 3])
+}
+
+fn default_scale() -> ScaleDescription {
+    return ScaleDescription::Uniform(1.0);
+}
+
+fn to_vec3(v: [f32; 3]) -> Vec3 {
+    return Vec3::new(v[0], v[1], v[2]);
+}
+
+fn check_direction(shape: &str, name: &str, v: [f32; 3]) -> Result<(), SceneError> {
+    if to_vec3(v).length_sq() == 0.0 {
+        return Err(SceneError::InvalidValue {
+            context: shape.to_string(),
+            message: format!("{} must not be zero", name)
+        });
+    }
+    return Ok(());
+}
+
+/// Negative radii are allowed, they turn a sphere inside out for hollow glass.
+fn check_radius(context: &str, radius: f32) -> Result<f32, SceneError> {
+    if radius == 0.0 || !radius.is_finite() {
+        return Err(SceneError::InvalidValue {
+            context: context.to_string(),
+            message: format!("radius must be finite and not zero, found {}", radius)
+        });
+    }
+    return Ok(radius);
+}
+
+fn check_density(context: &str, density: f32) -> Result<f32, SceneError> {
+    if density < 0.0 || !density.is_finite() {
+        return Err(SceneError::InvalidValue {
+            context: context.to_string(),
+            message: format!("density must be finite and not negative, found {}", density)
+        });
+    }
+    return Ok(density);
+}
+
+/// Adds the scattering of a medium to the materials.
+fn volume_material(
+    materials: &mut MaterialRegistry,
+    context: &str,
+    albedo: [f32; 3],
+    phase: &PhaseDescription) -> Result<MaterialHandle, SceneError> {
+
+    let invalid = |message: String| SceneError::InvalidValue { context: context.to_string(), message };
+
+    if albedo.iter().any(|c| !(0.0..=1.0).contains(c)) {
+        return Err(invalid(format!("albedo must be between 0 and 1, found {:?}", albedo)));
+    }
+    let phase = match phase {
+        PhaseDescription::Isotropic => PhaseFunction::Isotropic,
+        PhaseDescription::HenyeyGreenstein { g } => {
+            if !(*g > -1.0 && *g < 1.0) {
+                return Err(invalid(format!("g must be between -1 and 1 exclusive, found {}", g)));
+            }
+            PhaseFunction::HenyeyGreenstein(*g)
+        }
+    };
+    return Ok(materials.add(VolumeScatter::new(to_vec3(albedo), phase)));
+}
+
+fn check_positive(context: &str, value: Option<i32>) -> Result<Option<i32>, SceneError> {
+    if let Some(v) = value {
+        if v <= 0 {
+            return Err(SceneError::InvalidValue {
+                context: context.to_string(),
+                message: format!("expected a positive integer, found {}", v)
+            });
+        }
+    }
+    return Ok(value);
+}
+
+fn check_non_negative(context: &str, value: Option<i32>) -> Result<Option<i32>, SceneError> {
+    if let Some(v) = value {
+        if v < 0 {
+            return Err(SceneError::InvalidValue {
+                context: context.to_string(),
+                message: format!("expected a non-negative integer, found {}", v)
+            });
+        }
+    }
+    return Ok(value);
+}
+
+/// Creates the texture a scene file description asks for, loading image files relative
+/// to `base_dir`.
+fn build_texture(name: &str, texture: &TextureDescription, base_dir: &Path) -> Result<Arc<dyn Texture>, SceneError> {
+    let invalid = |message: String| SceneError::InvalidValue { context: format!("texture '{}'", name), message };
+
+    match texture {
+        TextureDescription::Solid { color } => return Ok(Arc::new(SolidColor::new(to_vec3(*color)))),
+        TextureDescription::Checker { even, odd, scale } => {
+            if *scale <= 0.0 {
+                return Err(invalid(format!("scale must be positive, found {}", scale)));
+            }
+            return Ok(Arc::new(Checker::new(
+                Arc::new(SolidColor::new(to_vec3(*even))),
+                Arc::new(SolidColor::new(to_vec3(*odd))),
+                *scale)));
+        },
+        TextureDescription::Image { path, wrap } => {
+            let wrap = WrapMode::from_name(wrap)
+                .ok_or_else(|| invalid(format!("unknown wrap mode '{}', expected repeat, mirror or clamp", wrap)))?;
+            let image = image_reader::load(&base_dir.join(path).to_string_lossy()).map_err(SceneError::Image)?;
+            return Ok(Arc::new(ImageTexture::new(image, wrap)));
+        },
+        TextureDescription::Noise { pattern, color, scale, octaves, seed } => {
+            let pattern = NoisePattern::from_name(pattern)
+                .ok_or_else(|| invalid(format!("unknown pattern '{}', expected perlin, turbulence or marble", pattern)))?;
+            let mut rng = RandomContext::from_seed(*seed);
+            return Ok(Arc::new(NoiseTexture::new(pattern, to_vec3(*color), *scale, *octaves, &mut rng)));
+        }
+    }
+}
+
+/// Adds the material an MTL or scene file description maps to.
+fn add_material(materials: &mut MaterialRegistry, mapping: MaterialMapping) -> MaterialHandle {
+    match mapping {
+        MaterialMapping::Lambertian { albedo } => return materials.add(Lambertian::with_albedo(albedo)),
+        MaterialMapping::Metal { albedo, fuzz } => return materials.add(Metal::with_properties(albedo, fuzz)),
+        MaterialMapping::Dielectric { ref_idx } => return materials.add(Dielectric::new(ref_idx)),
+        MaterialMapping::DiffuseLight { emit } => return materials.add(DiffuseLight::with_emission(emit))
+    }
+}
+
+/// Turns lists of shape descriptions into primitives, building each named object the
+/// first time an instance refers to it.
+struct ShapeBuilder<'a> {
+    base_dir: &'a Path,
+    material_ids: &'a BTreeMap<&'a str, MaterialHandle>,
+    /// OBJ models add the materials of their MTL files here.
+    materials: &'a mut MaterialRegistry,
+    objects: &'a BTreeMap<String, ObjectDescription>,
+    built: BTreeMap<String, Arc<Bvh<Primitive>>>,
+    /// The objects currently being built, to catch ones which contain themselves.
+    building: Vec<String>
+}
+
+impl<'a> ShapeBuilder<'a> {
+    /// Builds the primitives of `shapes`, which belong to the named object or to the
+    /// scene itself when `object` is `None`.
+    fn build(&mut self, shapes: &[ShapeDescription], object: Option<&str>) -> Result<Vec<Primitive>, SceneError> {
+        let mut primitives = Vec::with_capacity(shapes.len());
+        for (index, shape) in shapes.iter().enumerate() {
+            let label = match object {
+                Some(name) => format!("shape #{} of object '{}'", index, name),
+                None => format!("shape #{}", index)
+            };
+
+            let material = match shape.material() {
+                Some(name) => Some(*self.material_ids.get(name)
+                    .ok_or_else(|| SceneError::UnknownMaterial { shape: label.clone(), name: name.to_string() })?),
+                None => None
+            };
+            // Only OBJ models may leave the material out, they fall back on their MTL files.
+            let handle = material.unwrap_or(MaterialHandle::UNASSIGNED);
+
+            match shape {
+                ShapeDescription::Sphere { center, end_center, radius, .. } => {
+                    let radius = check_radius(&label, *radius)?;
+                    let mut sphere = Sphere::new(to_vec3(*center), radius).with_material(handle);
+                    if let Some(end) = end_center {
+                        sphere = sphere.with_motion(to_vec3(*end));
+                    }
+                    primitives.push(Primitive::Sphere(sphere));
+                },
+                ShapeDescription::Triangle { vertices, .. } => {
+                    let [p0, p1, p2] = *vertices;
+                    primitives.push(Primitive::Triangle(
+                        Triangle::from_points(to_vec3(p0), to_vec3(p1), to_vec3(p2), handle)));
+                },
+                ShapeDescription::Plane { point, normal, .. } => {
+                    check_direction(&label, "normal", *normal)?;
+                    primitives.push(Primitive::Plane(Plane::new(to_vec3(*point), to_vec3(*normal)).with_material(handle)));
+                },
+                ShapeDescription::Quad { corner, u, v, .. } => {
+                    if to_vec3(*u).cross(to_vec3(*v)).length_sq() == 0.0 {
+                        return Err(SceneError::InvalidValue {
+                            context: label.clone(),
+                            message: String::from("the edges u and v must not be parallel or zero")
+                        });
+                    }
+                    primitives.push(Primitive::Quad(Quad::new(to_vec3(*corner), to_vec3(*u), to_vec3(*v)).with_material(handle)));
+                },
+                ShapeDescription::Rectangle { min, max, .. } => {
+                    let quad = Quad::axis_aligned(to_vec3(*min), to_vec3(*max)).ok_or_else(|| SceneError::InvalidValue {
+                        context: label.clone(),
+                        message: String::from("the corners must share exactly one coordinate")
+                    })?;
+                    primitives.push(Primitive::Quad(quad.with_material(handle)));
+                },
+                ShapeDescription::Disk { center, normal, radius, .. } => {
+                    check_direction(&label, "normal", *normal)?;
+                    if !(*radius > 0.0 && radius.is_finite()) {
+                        return Err(SceneError::InvalidValue {
+                            context: label.clone(),
+                            message: format!("radius must be positive and finite, found {}", radius)
+                        });
+                    }
+                    primitives.push(Primitive::Disk(Disk::new(to_vec3(*center), to_vec3(*normal), *radius).with_material(handle)));
+                },
+                ShapeDescription::Box { min, max, .. } => {
+                    if (0..3).any(|axis| min[axis] == max[axis]) {
+                        return Err(SceneError::InvalidValue {
+                            context: label.clone(),
+                            message: String::from("the corners must differ along every axis")
+                        });
+                    }
+                    primitives.push(Primitive::Cuboid(Cuboid::new(to_vec3(*min), to_vec3(*max)).with_material(handle)));
+                },
+                ShapeDescription::Mesh { positions, indices, normals, uvs, .. } => {
+                    let mesh = TriangleMesh::new(
+                        positions.iter().map(|p| to_vec3(*p)).collect(),
+                        normals.as_ref().map(|n| n.iter().map(|v| to_vec3(*v)).collect()),
+                        uvs.as_ref().map(|uv| uv.iter().map(|t| (t[0], t[1])).collect()),
+                        indices.clone())
+                        .map_err(|message| SceneError::InvalidValue { context: label.clone(), message })?;
+
+                    let mesh = Arc::new(mesh.with_material(handle));
+                    primitives.extend(TriangleMesh::triangles(&mesh).into_iter().map(Primitive::Triangle));
+                },
+                ShapeDescription::Instance { object, translate, rotate, scale, end_translate, end_rotate, end_scale, matrix, .. } => {
+                    let start = TransformParts {
+                        scale: scale_factors(scale),
+                        rotate: to_vec3(*rotate),
+                        translate: to_vec3(*translate)
+                    };
+                    let end = TransformParts {
+                        scale: end_scale.as_ref().map_or(start.scale, scale_factors),
+                        rotate: end_rotate.map_or(start.rotate, to_vec3),
+                        translate: end_translate.map_or(start.translate, to_vec3)
+                    };
+                    let transform = instance_transform(&label, start, end, *matrix)?;
+                    let object = self.object(object, &label)?;
+                    primitives.push(Primitive::Instance(Instance::new(object, transform).with_material(handle)));
+                },
+                ShapeDescription::Obj { path, groups, .. } => {
+                    let ObjModel { mut meshes, materials: obj_materials } = obj::load(&self.base_dir.join(path)).map_err(SceneError::Obj)?;
+
+                    if let Some(groups) = groups {
+                        for group in groups {
+                            if !meshes.iter().any(|m| &m.name == group) {
+                                return Err(SceneError::InvalidValue {
+                                    context: label.clone(),
+                                    message: format!("'{}' has no object or group named '{}'", path, group)
+                                });
+                            }
+                        }
+                        meshes.retain(|m| groups.contains(&m.name));
+                    }
+
+                    // Each MTL material used by the model gets its own entry in the tables.
+                    let mut mtl_ids: BTreeMap<String, MaterialHandle> = BTreeMap::new();
+                    let mut default_id = None;
+
+                    for obj_mesh in meshes {
+                        let id = match (material, &obj_mesh.material) {
+                            (Some(id), _) => id,
+                            (None, Some(name)) => *mtl_ids.entry(name.clone())
+                                .or_insert_with(|| add_material(self.materials, obj_materials[name].mapping())),
+                            (None, None) => *default_id
+                                .get_or_insert_with(|| add_material(self.materials, MtlMaterial::default().mapping()))
+                        };
+
+                        let mesh = Arc::new(obj_mesh.mesh.with_material(id));
+                        primitives.extend(TriangleMesh::triangles(&mesh).into_iter().map(Primitive::Triangle));
+                    }
+                }
+            }
+        }
+        return Ok(primitives);
+    }
+
+    /// The BVH of the object called `name`, shared by all of its instances.
+    fn object(&mut self, name: &str, label: &str) -> Result<Arc<Bvh<Primitive>>, SceneError> {
+        if let Some(object) = self.built.get(name) {
+            return Ok(Arc::clone(object));
+        }
+        let invalid = |message: String| SceneError::InvalidValue { context: label.to_string(), message };
+
+        let description = self.objects.get(name)
+            .ok_or_else(|| invalid(format!("object '{}' is not defined under [objects]", name)))?;
+        if self.building.iter().any(|other| other == name) {
+            return Err(invalid(format!("object '{}' contains an instance of itself", name)));
+        }
+
+        self.building.push(name.to_string());
+        let primitives = self.build(&description.shapes, Some(name))?;
+        self.building.pop();
+
+        let object = Arc::new(Bvh::from_primitives(primitives));
+        self.built.insert(name.to_string(), Arc::clone(&object));
+        return Ok(object);
+    }
+}
+
+fn scale_factors(scale: &ScaleDescription) -> Vec3 {
+    match scale {
+        ScaleDescription::Uniform(factor) => return Vec3::from_uniform_value(*factor),
+        ScaleDescription::PerAxis(factors) => return to_vec3(*factors)
+    }
+}
+
+/// The object to world transform of an instance: the scale, then the rotations around
+/// x, y and z in turn, then the translation, blended from `start` to `end` over time,
+/// and finally `matrix`.
+fn instance_transform(
+    label: &str,
+    start: TransformParts,
+    end: TransformParts,
+    matrix: Option<[[f32; 4]; 4]>) -> Result<AnimatedTransform, SceneError> {
+
+    let invalid = |message: &str| SceneError::InvalidValue { context: label.to_string(), message: message.to_string() };
+
+    let after = match matrix.map(Matrix4::new) {
+        Some(m) if !m.is_affine() => return Err(invalid("the last row of matrix must be [0, 0, 0, 1], projective matrices are not supported")),
+        Some(m) => Transform::new(m).ok_or_else(|| invalid("matrix cannot be inverted"))?,
+        None => Transform::translate(Vec3::zero())
+    };
+
+    return AnimatedTransform::new(start, end, after)
+        .ok_or_else(|| invalid("scale factors must not be zero or change sign between scale and end_scale"));
+}
+
+/// Reads and builds the scene stored at `path`. Files referenced by the scene, such as
+/// OBJ models, are resolved relative to the scene file's directory.
+pub fn load(path: &str) -> Result<Scene, SceneError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| SceneError::Io { path: path.to_string(), message: e.to_string() })?;
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    return parse(&contents, base_dir)
+        .map_err(|e| match e {
+            SceneError::Parse { message, .. } => SceneError::Parse { path: path.to_string(), message },
+            other => other
+        });
+}
+
+/// Builds a scene from the contents of a scene file.
+///
+/// # Parameters
+///
+/// - `contents` : &str
+/// The TOML text of the scene
+/// - `base_dir` : &Path
+/// The directory relative file paths inside the scene are resolved against
+///
+/// # Returns
+///
+/// The scene, or the first problem found. Material references are all resolved here so
+/// rendering can index the material tables without checks.
+pub fn parse(contents: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let description: SceneDescription = toml::from_str(contents)
+        .map_err(|e| SceneError::Parse { path: String::new(), message: e.to_string() })?;
+
+    let background = match &description.background {
+        None => Background::sky(),
+        Some(BackgroundDescription::Constant { color }) => Background::Constant(to_vec3(*color)),
+        Some(BackgroundDescription::Gradient { bottom, top }) =>
+            Background::Gradient { bottom: to_vec3(*bottom), top: to_vec3(*top) },
+        Some(BackgroundDescription::Environment { path, rotation, intensity }) => {
+            if *intensity < 0.0 {
+                return Err(SceneError::InvalidValue {
+                    context: String::from("background"),
+                    message: format!("intensity must not be negative, found {}", intensity)
+                });
+            }
+            let image = image_reader::load(&base_dir.join(path).to_string_lossy()).map_err(SceneError::Image)?;
+            Background::Environment(EnvironmentMap::new(image, *rotation, *intensity))
+        }
+    };
+
+    let mut textures: BTreeMap<&str, Arc<dyn Texture>> = BTreeMap::new();
+    for (name, texture) in &description.textures {
+        textures.insert(name.as_str(), build_texture(name, texture, base_dir)?);
+    }
+
+    let mut materials = MaterialRegistry::new();
+    let mut material_ids: BTreeMap<&str, MaterialHandle> = BTreeMap::new();
+
+    for (name, material) in &description.materials {
+        let texture = |source: &ColorSource| -> Result<Arc<dyn Texture>, SceneError> {
+            match source {
+                ColorSource::Color(color) => return Ok(Arc::new(SolidColor::new(to_vec3(*color)))),
+                ColorSource::Texture(texture) => return textures.get(texture.as_str()).cloned()
+                    .ok_or_else(|| SceneError::InvalidValue {
+                        context: format!("material '{}'", name),
+                        message: format!("texture '{}' is not defined under [textures]", texture)
+                    })
+            }
+        };
+
+        let invalid = |message: String| SceneError::InvalidValue { context: format!("material '{}'", name), message };
+        let check_roughness = |roughness: f32| -> Result<f32, SceneError> {
+            if !(0.0..=1.0).contains(&roughness) {
+                return Err(invalid(format!("roughness must be between 0 and 1, found {}", roughness)));
+            }
+            return Ok(roughness);
+        };
+
+        let handle = match material {
+            MaterialDescription::Lambertian { albedo } => materials.add(Lambertian::with_texture(texture(albedo)?)),
+            MaterialDescription::Metal { albedo, fuzz } => materials.add(Metal::with_texture(texture(albedo)?, *fuzz)),
+            MaterialDescription::Dielectric { ref_idx } => {
+                if *ref_idx <= 0.0 {
+                    return Err(invalid(format!("ref_idx must be positive, found {}", ref_idx)));
+                }
+                add_material(&mut materials, MaterialMapping::Dielectric { ref_idx: *ref_idx })
+            },
+            MaterialDescription::DiffuseLight { emit } =>
+                add_material(&mut materials, MaterialMapping::DiffuseLight { emit: to_vec3(*emit) }),
+            MaterialDescription::Conductor { metal, eta, k, roughness } => {
+                let roughness = check_roughness(*roughness)?;
+                let conductor = match (metal, eta, k) {
+                    (Some(metal), None, None) => Conductor::named(metal, roughness)
+                        .ok_or_else(|| invalid(format!("unknown metal '{}', expected gold, copper, aluminium or silver", metal)))?,
+                    (None, Some(eta), Some(k)) => Conductor::new(to_vec3(*eta), to_vec3(*k), roughness),
+                    _ => return Err(invalid(String::from("expected either metal or both eta and k")))
+                };
+                materials.add(conductor)
+            },
+            MaterialDescription::RoughDielectric { ior, roughness } => {
+                if *ior <= 0.0 {
+                    return Err(invalid(format!("ior must be positive, found {}", ior)));
+                }
+                materials.add(RoughDielectric::new(*ior, check_roughness(*roughness)?))
+            },
+            MaterialDescription::Principled {
+                base_color, metallic, roughness, specular, specular_tint,
+                sheen, clearcoat, clearcoat_roughness, transmission, ior
+            } => {
+                let mut principled = Principled::default();
+                let scalars = [
+                    ("metallic", metallic, &mut principled.metallic),
+                    ("roughness", roughness, &mut principled.roughness),
+                    ("specular", specular, &mut principled.specular),
+                    ("specular_tint", specular_tint, &mut principled.specular_tint),
+                    ("sheen", sheen, &mut principled.sheen),
+                    ("clearcoat", clearcoat, &mut principled.clearcoat),
+                    ("clearcoat_roughness", clearcoat_roughness, &mut principled.clearcoat_roughness),
+                    ("transmission", transmission, &mut principled.transmission)
+                ];
+                for (parameter, source, target) in scalars {
+                    match source {
+                        Some(ScalarSource::Value(value)) => {
+                            if !(0.0..=1.0).contains(value) {
+                                return Err(invalid(format!("{} must be between 0 and 1, found {}", parameter, value)));
+                            }
+                            *target = Arc::new(SolidColor::new(Vec3::from_uniform_value(*value)));
+                        },
+                        Some(ScalarSource::Texture(name)) => *target = texture(&ColorSource::Texture(name.clone()))?,
+                        None => {}
+                    }
+                }
+                if let Some(base_color) = base_color {
+                    principled.base_color = texture(base_color)?;
+                }
+                if let Some(ior) = ior {
+                    if *ior <= 0.0 {
+                        return Err(invalid(format!("ior must be positive, found {}", ior)));
+                    }
+                    principled.ior = *ior;
+                }
+                materials.add(principled)
+            }
+        };
+        material_ids.insert(name.as_str(), handle);
+    }
+
+    let mut builder = ShapeBuilder {
+        base_dir,
+        material_ids: &material_ids,
+        materials: &mut materials,
+        objects: &description.objects,
+        built: BTreeMap::new(),
+        building: Vec::new()
+    };
+    let primitives = builder.build(&description.shapes, None)?;
+
+    let mut media = Vec::new();
+    for (index, medium) in description.media.iter().enumerate() {
+        let context = format!("medium #{}", index);
+        let invalid = |message: String| SceneError::InvalidValue { context: context.clone(), message };
+
+        let boundary = match &medium.boundary {
+            BoundaryDescription::Sphere { center, radius } => {
+                check_radius(&context, *radius)?;
+                if *radius < 0.0 {
+                    return Err(invalid(format!("radius must be positive, found {}", radius)));
+                }
+                Primitive::Sphere(Sphere::new(to_vec3(*center), *radius))
+            },
+            BoundaryDescription::Box { min, max } => {
+                let (min, max) = (to_vec3(*min), to_vec3(*max));
+                if min.x >= max.x || min.y >= max.y || min.z >= max.z {
+                    return Err(invalid(String::from("the box must have min below max along every axis")));
+                }
+                Primitive::Cuboid(Cuboid::new(min, max))
+            }
+        };
+
+        let density = check_density(&context, medium.density)?;
+        let density = match &medium.grid {
+            None => Density::Constant(density),
+            Some(grid) => {
+                let grid = DensityGrid::new(grid.resolution, grid.values.clone(), boundary.bounding_box())
+                    .map_err(invalid)?;
+                Density::Grid(grid, density)
+            }
+        };
+
+        let material = volume_material(&mut materials, &context, medium.albedo, &medium.phase)?;
+        media.push(Medium::new(MediumBounds::Shape(boundary), density, material));
+    }
+
+    if let Some(fog) = &description.fog {
+        let density = check_density("fog", fog.density)?;
+        let material = volume_material(&mut materials, "fog", fog.albedo, &fog.phase)?;
+        let bounds = match fog.height {
+            Some(height) => MediumBounds::Below(height),
+            None => MediumBounds::Everywhere
+        };
+        media.push(Medium::new(bounds, Density::Constant(density), material));
+    }
+
+    let camera = &description.camera;
+    for (context, time) in [("camera.shutter_open", camera.shutter_open), ("camera.shutter_close", camera.shutter_close)] {
+        if !(0.0..=1.0).contains(&time) {
+            return Err(SceneError::InvalidValue {
+                context: String::from(context),
+                message: format!("expected a time in [0, 1], found {}", time)
+            });
+        }
+    }
+    if camera.shutter_open > camera.shutter_close {
+        return Err(SceneError::InvalidValue {
+            context: String::from("camera.shutter_close"),
+            message: format!("expected a time not before shutter_open, found {}", camera.shutter_close)
+        });
+    }
+    let lookfrom = to_vec3(camera.lookfrom);
+    let lookat = to_vec3(camera.lookat);
+
+    let render = RenderSettings {
+        width: check_positive("render.width", description.render.width)?,
+        height: check_positive("render.height", description.render.height)?,
+        samples: check_positive("render.samples", description.render.samples)?,
+        max_depth: check_positive("render.max_depth", description.render.max_depth)?,
+        diffuse_depth: check_non_negative("render.diffuse_depth", description.render.diffuse_depth)?,
+        specular_depth: check_non_negative("render.specular_depth", description.render.specular_depth)?,
+        transmission_depth: check_non_negative("render.transmission_depth", description.render.transmission_depth)?,
+        roulette_depth: check_non_negative("render.roulette_depth", description.render.roulette_depth)?
+    };
+
+    return Ok(Scene {
+        primitives,
+        media,
+        materials,
+        camera: CameraSettings {
+            projection: match camera.projection {
+                ProjectionDescription::Perspective => Projection::Perspective,
+                ProjectionDescription::Orthographic => Projection::Orthographic,
+                ProjectionDescription::FisheyeEquidistant => Projection::Fisheye(FisheyeMapping::Equidistant),
+                ProjectionDescription::FisheyeEquisolid => Projection::Fisheye(FisheyeMapping::Equisolid),
+                ProjectionDescription::Equirectangular => Projection::Equirectangular
+            },
+            lookfrom,
+            lookat,
+            vup: to_vec3(camera.vup),
+            vfov: camera.vfov,
+            aperture: camera.aperture,
+            focus_dist: camera.focus_dist.unwrap_or_else(|| (lookat - lookfrom).length()),
+            shutter_open: camera.shutter_open,
+            shutter_close: camera.shutter_close
+        },
+        render,
+        background
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A camera and a material, which the shapes under test are appended to.
+    const HEADER: &str = r#"
+[camera]
+lookfrom = [0.0, 0.0, -5.0]
+lookat = [0.0, 0.0, 0.0]
+vfov = 40.0
+
+[materials.grey]
+type = "lambertian"
+albedo = [0.5, 0.5, 0.5]
+"#;
+
+    fn parse_scene(shapes: &str) -> Result<Scene, SceneError> {
+        return parse(&format!("{}\n{}", HEADER, shapes), Path::new(""));
+    }
+
+    #[test]
+    fn builds_shapes_with_their_materials() {
+        let scene = parse_scene(r#"
+[[shapes]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "grey"
+"#).unwrap();
+
+        assert_eq!(scene.primitives.len(), 1);
+        assert!(scene.check_materials().is_ok());
+        assert_eq!(scene.camera.focus_dist, 5.0);
+    }
+
+    #[test]
+    fn unknown_field_is_a_parse_error() {
+        let err = parse_scene(r#"
+[[shapes]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+colour = [1.0, 0.0, 0.0]
+material = "grey"
+"#).err().unwrap();
+
+        match err {
+            SceneError::Parse { message, .. } => assert!(message.contains("colour"), "{}", message),
+            other => panic!("expected a parse error, found {}", other)
+        }
+    }
+
+    #[test]
+    fn undefined_material_names_the_shape() {
+        let err = parse_scene(r#"
+[[shapes]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "grey"
+
+[[shapes]]
+type = "sphere"
+center = [2.0, 0.0, 0.0]
+radius = 1.0
+material = "gold"
+"#).err().unwrap();
+
+        match err {
+            SceneError::UnknownMaterial { shape, name } => {
+                assert_eq!(shape, "shape #1");
+                assert_eq!(name, "gold");
+            },
+            other => panic!("expected an unknown material, found {}", other)
+        }
+    }
+
+    #[test]
+    fn degenerate_quads_are_rejected() {
+        let parallel = parse_scene(r#"
+[[shapes]]
+type = "quad"
+corner = [0.0, 0.0, 0.0]
+u = [1.0, 0.0, 0.0]
+v = [2.0, 0.0, 0.0]
+material = "grey"
+"#).err().unwrap();
+        assert!(matches!(parallel, SceneError::InvalidValue { ref context, .. } if context == "shape #0"), "{}", parallel);
+
+        let not_flat = parse_scene(r#"
+[[shapes]]
+type = "rectangle"
+min = [0.0, 0.0, 0.0]
+max = [1.0, 1.0, 1.0]
+material = "grey"
+"#).err().unwrap();
+        assert!(matches!(not_flat, SceneError::InvalidValue { .. }), "{}", not_flat);
+    }
+
+    #[test]
+    fn flat_disks_and_boxes_are_rejected() {
+        let disk = parse_scene(r#"
+[[shapes]]
+type = "disk"
+center = [0.0, 0.0, 0.0]
+normal = [0.0, 1.0, 0.0]
+radius = 0.0
+material = "grey"
+"#).err().unwrap();
+        assert!(matches!(disk, SceneError::InvalidValue { ref context, .. } if context == "shape #0"), "{}", disk);
+
+        let flat = parse_scene(r#"
+[[shapes]]
+type = "box"
+min = [0.0, 0.0, 0.0]
+max = [1.0, 0.0, 1.0]
+material = "grey"
+"#).err().unwrap();
+        assert!(matches!(flat, SceneError::InvalidValue { ref context, .. } if context == "shape #0"), "{}", flat);
+    }
+
+    #[test]
+    fn instance_matrices_must_be_affine() {
+        let instance = |last_row: &str| format!(r#"
+[[objects.ball.shapes]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = "grey"
+
+[[shapes]]
+type = "instance"
+object = "ball"
+matrix = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], {}]
+"#, last_row);
+
+        assert!(parse_scene(&instance("[0.0, 0.0, 0.0, 1.0]")).is_ok());
+        let err = parse_scene(&instance("[0.0, 0.0, 1.0, 1.0]")).err().unwrap();
+        assert!(matches!(err, SceneError::InvalidValue { ref context, .. } if context == "shape #0"), "{}", err);
+    }
+
+    #[test]
+    fn spheres_need_a_finite_radius_other_than_zero() {
+        for radius in ["0.0", "inf", "nan"] {
+            let err = parse_scene(&format!(r#"
+[[shapes]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = {}
+material = "grey"
+"#, radius)).err().unwrap();
+            assert!(matches!(err, SceneError::InvalidValue { ref context, .. } if context == "shape #0"), "{}", err);
+        }
+
+        // Negative radii make hollow glass.
+        assert!(parse_scene(r#"
+[[shapes]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = -0.5
+material = "grey"
+"#).is_ok());
+    }
+
+    #[test]
+    fn shutter_times_must_lie_in_the_scene() {
+        let contents = HEADER.replace("vfov = 40.0", "vfov = 40.0\nshutter_open = 0.5\nshutter_close = 2.0");
+        let err = parse(&contents, Path::new("")).err().unwrap();
+        assert!(matches!(err, SceneError::InvalidValue { ref context, .. } if context == "camera.shutter_close"), "{}", err);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::background::Background;
+use crate::camera::Projection;
+use crate::material::{Lambertian, Metal, Dielectric, DiffuseLight, MaterialHandle, MaterialRegistry};
+use crate::medium::Medium;
+use crate::random_utils::{generate_normalized_ran, RandomContext};
+use crate::shapes::{Cuboid, MaterialAccessor, Plane, Primitive, Quad, Sphere};
+use crate::vec3::Vec3;
+
+/// The names accepted by `builtin`.
+pub const BUILTIN_SCENES: [&str; 3] = ["random", "spheres", "cornell"];
+
+/// Where the camera sits and how it is focused, before the output resolution is known.
+#[derive(Clone, Copy)]
+pub struct CameraSettings {
+    pub projection: Projection,
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vup: Vec3,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focus_dist: f32,
+    /// Moving objects are at their start at time 0 and their end at time 1.
+    pub shutter_open: f32,
+    pub shutter_close: f32
+}
+
+/// Output resolution, sampling budget and path length limits. Scenes may leave any of
+/// them unset, in which case the command line or the renderer's defaults decide.
+#[derive(Clone, Copy, Default)]
+pub struct RenderSettings {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub samples: Option<i32>,
+    pub max_depth: Option<i32>,
+    pub diffuse_depth: Option<i32>,
+    pub specular_depth: Option<i32>,
+    pub transmission_depth: Option<i32>,
+    pub roulette_depth: Option<i32>
+}
+
+/// Everything needed to render: the shapes, the media, the materials they refer to
+/// and the scene's preferred camera and render settings.
+pub struct Scene {
+    pub primitives: Vec<Primitive>,
+    /// Volumes scattering light, such as smoke or fog, filling space between surfaces.
+    pub media: Vec<Medium>,
+    pub materials: MaterialRegistry,
+    pub camera: CameraSettings,
+    pub render: RenderSettings,
+    /// What rays leaving the scene see.
+    pub background: Background
+}
+
+impl Scene {
+    /// Confirms every primitive and medium refers to one of the scene's materials, so
+    /// rendering can look them up without checks.
+    pub fn check_materials(&self) -> Result<(), String> {
+        for (index, medium) in self.media.iter().enumerate() {
+            self.materials.check(medium.material())
+                .map_err(|message| format!("Medium #{} uses {}", index, message))?;
+        }
+        return self.check_primitives(&self.primitives);
+    }
+
+    /// Instances keeping their object's materials are checked part by part.
+    fn check_primitives(&self, primitives: &[Primitive]) -> Result<(), String> {
+        for (index, primitive) in primitives.iter().enumerate() {
+            if let Primitive::Instance(instance) = primitive {
+                if instance.material() == MaterialHandle::UNASSIGNED {
+                    self.check_primitives(instance.object().primitives())?;
+                    continue;
+                }
+            }
+            self.materials.check(primitive.material())
+                .map_err(|message| format!("Primitive #{} uses {}", index, message))?;
+        }
+        return Ok(());
+    }
+}
+
+/// Builds one of the scenes compiled into the renderer, see `BUILTIN_SCENES`.
+pub fn builtin(name: &str, rng: &mut RandomContext) -> Option<Scene> {
+    match name {
+        "random" => return Some(random_world(rng)),
+        "spheres" => return Some(three_spheres()),
+        "cornell" => return Some(cornell_box()),
+        _ => return None
+    }
+}
+
+/// The cover scene of "Ray Tracing in One Weekend", hundreds of small random spheres
+/// around three large ones.
+pub fn random_world(rng: &mut RandomContext) -> Scene {
+    let mut spheres : Vec<Sphere> = Vec::with_capacity(500);
+
+    let mut materials = MaterialRegistry::new();
+    let glass = materials.add(Dielectric::new(1.5));
+    let ground = materials.add(Lambertian::with_albedo(Vec3::from_uniform_value(0.5)));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = generate_normalized_ran(rng);
+            let center = Vec3::new(a as f32 + 0.9 * generate_normalized_ran(rng), 0.2, b as f32 + 0.9 * generate_normalized_ran(rng));
+
+            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                if choose_mat < 0.8 {
+                    let lamb = Lambertian::with_albedo(
+                        Vec3::new(
+                            generate_normalized_ran(rng) * generate_normalized_ran(rng), 
+                            generate_normalized_ran(rng) * generate_normalized_ran(rng), 
+                            generate_normalized_ran(rng) * generate_normalized_ran(rng))
+                    );
+                    spheres.push(Sphere::new(center, 0.2).with_material(materials.add(lamb)))
+                } else if choose_mat < 0.95 {
+                    let metal = Metal::with_properties(
+                        Vec3::new(
+                            0.5 * (1.0 + generate_normalized_ran(rng)),
+                            0.5 * (1.0 + generate_normalized_ran(rng)),
+                            0.5 * (1.0 + generate_normalized_ran(rng))
+                        ), 
+                        0.5 * generate_normalized_ran(rng));
+                    spheres.push(Sphere::new(center, 0.2).with_material(materials.add(metal)));
+                } else {
+                    spheres.push(Sphere::new(center, 0.2).with_material(glass));
+                }
+            }
+        }
+    }
+
+    let polished = materials.add(Metal::with_properties(Vec3::new(0.7, 0.6, 0.5), 0.0));
+    let brown = materials.add(Lambertian::with_albedo(Vec3::new(0.4, 0.4, 0.1)));
+
+    spheres.push(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0).with_material(glass));
+    // list[i++] = new sphere(vec3(-4, 1, 0), 1.0, new lambertian(vec3(0.4, 0.2, 0.1)));
+    // list[i++] = new sphere(vec3(4, 1, 0), 1.0, new metal(vec3(0.7, 0.6, 0.5), 0.0));
+    spheres.push(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0).with_material(brown));
+    spheres.push(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0).with_material(polished));
+
+    let mut primitives = vec![Primitive::Plane(Plane::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0)).with_material(ground))];
+    primitives.extend(spheres.into_iter().map(Primitive::Sphere));
+
+    return Scene {
+        primitives,
+        media: Vec::new(),
+        materials,
+        camera: CameraSettings {
+            projection: Projection::Perspective,
+            lookfrom: Vec3::new(13.0, 2.0, 3.0),
+            lookat: Vec3::new(0.0, 0.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 20.0,
+            aperture: 0.1,
+            focus_dist: 10.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0
+        },
+        render: RenderSettings::default(),
+        background: Background::sky()
+    };
+}
+
+/// A small scene with a diffuse, a metal and a hollow glass sphere on a large ground sphere.
+pub fn three_spheres() -> Scene {
+    let mut materials = MaterialRegistry::new();
+    let blue = materials.add(Lambertian::with_albedo(Vec3::new(0.1, 0.2, 0.5)));
+    let ground = materials.add(Lambertian::with_albedo(Vec3::new(0.8, 0.8, 0.0)));
+    let gold = materials.add(Metal::with_properties(Vec3::new(0.8, 0.6, 0.2), 0.3));
+    let glass = materials.add(Dielectric::new(1.5));
+
+    let spheres = vec![
+        Sphere::new(Vec3::new(0.0, 0.0, 1.0), 0.5).with_material(blue),
+        Sphere::new(Vec3::new(0.0, -100.5, 1.0), 100.0).with_material(ground),
+        Sphere::new(Vec3::new(1.0, 0.0, 1.5), 0.5).with_material(gold),
+        Sphere::new(Vec3::new(-1.0, 0.0, 0.5), 0.5).with_material(glass),
+        Sphere::new(Vec3::new(-1.0, 0.0, 0.5), -0.45).with_material(glass)
+    ];
+
+    return Scene {
+        primitives: spheres.into_iter().map(Primitive::Sphere).collect(),
+        media: Vec::new(),
+        materials,
+        camera: CameraSettings {
+            projection: Projection::Perspective,
+            lookfrom: Vec3::new(0.0, 0.5, -1.5),
+            lookat: Vec3::new(0.0, 0.0, 1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 60.0,
+            aperture: 0.0,
+            focus_dist: 2.5,
+            shutter_open: 0.0,
+            shutter_close: 1.0
+        },
+        render: RenderSettings::default(),
+        background: Background::sky()
+    };
+}
+
+/// A box of the given size standing on the y = 0 plane with a corner at the origin,
+/// turned by `angle` degrees around the y axis and then moved by `offset`.
+fn rotated_box(size: Vec3, angle: f32, offset: Vec3, material: MaterialHandle) -> Primitive {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let cuboid = Cuboid::oriented(
+        offset,
+        size.x * Vec3::new(cos, 0.0, -sin),
+        Vec3::new(0.0, size.y, 0.0),
+        size.z * Vec3::new(sin, 0.0, cos));
+    return Primitive::Cuboid(cuboid.with_material(material));
+}
+
+/// The quad spanned by `u` and `v` from `corner`.
+fn quad(corner: Vec3, u: Vec3, v: Vec3, material: MaterialHandle) -> Primitive {
+    return Primitive::Quad(Quad::new(corner, u, v).with_material(material));
+}
+
+/// The Cornell box: a closed room with a red and a green wall, lit only by an area
+/// light in the ceiling, holding two white boxes.
+pub fn cornell_box() -> Scene {
+    let mut materials = MaterialRegistry::new();
+    let red = materials.add(Lambertian::with_albedo(Vec3::new(0.65, 0.05, 0.05)));
+    let white = materials.add(Lambertian::with_albedo(Vec3::new(0.73, 0.73, 0.73)));
+    let green = materials.add(Lambertian::with_albedo(Vec3::new(0.12, 0.45, 0.15)));
+    let light = materials.add(DiffuseLight::with_emission(Vec3::from_uniform_value(15.0)));
+
+    let x = Vec3::new(555.0, 0.0, 0.0);
+    let y = Vec3::new(0.0, 555.0, 0.0);
+    let z = Vec3::new(0.0, 0.0, 555.0);
+
+    let primitives = vec![
+        quad(x, y, z, green),
+        quad(Vec3::zero(), y, z, red),
+        quad(Vec3::zero(), x, z, white),
+        quad(y, x, z, white),
+        quad(z, x, y, white),
+        quad(
+            Vec3::new(213.0, 554.0, 227.0),
+            Vec3::new(130.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 105.0),
+            light),
+        rotated_box(Vec3::new(165.0, 330.0, 165.0), 15.0, Vec3::new(265.0, 0.0, 295.0), white),
+        rotated_box(Vec3::new(165.0, 165.0, 165.0), -18.0, Vec3::new(130.0, 0.0, 65.0), white)
+    ];
+
+    return Scene {
+        primitives,
+        media: Vec::new(),
+        materials,
+        camera: CameraSettings {
+            projection: Projection::Perspective,
+            lookfrom: Vec3::new(278.0, 278.0, -800.0),
+            lookat: Vec3::new(278.0, 278.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 40.0,
+            aperture: 0.0,
+            focus_dist: 10.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0
+        },
+        render: RenderSettings {
+            width: Some(400),
+            height: Some(400),
+            samples: Some(200),
+            ..RenderSettings::default()
+        },
+        background: Background::Constant(Vec3::zero())
+    };
+}
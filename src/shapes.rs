@@ -1,105 +1,780 @@
-use crate::ray::Ray;
-use crate::vec3::Vec3;
-use crate::math;
-
-#[derive(Clone, Copy)]
-pub struct HitRecord {
-    pub t: f32,
-    pub p: Vec3,
-    pub normal: Vec3,
-    pub material_index: i32,
-    pub material_type: i32      // TODO: Use an enum
-}
-
-#[derive(Copy, Clone)]
-pub struct Sphere {
-    center: Vec3,
-    radius: f32,
-    material_type: i32,
-    material_index: i32
-}
-
-pub trait Hitable {
-    fn hit(self, r: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> bool;
-}
-
-pub trait MaterialAccessor {
-    fn get_material_info(self) -> (i32, i32);
-}
-
-impl HitRecord {
-    pub fn default() -> HitRecord {
-        HitRecord {
-            t: 0.0,
-            p: Vec3::from_uniform_value(0.0),
-            normal: Vec3::from_uniform_value(0.0),
-            material_index: -1,
-            material_type: -1
-        }
-    }
-}
-
-impl std::fmt::Display for HitRecord {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "(t: {}, Point: {}, {}, {}, Normal: {}, {}, {})", 
-            self.t, 
-            self.p.x, self.p.y, self.p.z, 
-            self.normal.x, self.normal.y, self.normal.z)
-    }
-}
-
-impl Sphere {
-    pub fn new(cent: Vec3, r: f32) -> Sphere {
-        return Sphere { 
-            center: cent, 
-            radius: r, 
-            material_type: -1, 
-            material_index: -1 
-        }
-    }
-
-    pub fn with_material(mut self, mat_type: i32, index: i32) -> Sphere {
-        self.material_type = mat_type;
-        self.material_index = index;
-        return self;
-    }
-}
-
-impl Hitable for Sphere {
-    fn hit(self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
-        let oc = r.origin - self.center;
-
-        let a = math::dot(r.direction, r.direction);
-        let b = math::dot(oc, r.direction);
-        let c = math::dot(oc, oc) - self.radius * self.radius;
-
-        let discriminant = b * b - a * c;
-
-        if discriminant > 0.0 {
-            let mut temp = (-b - (b * b - a * c).sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                rec.p = r.point_at_parameter(temp);
-                rec.t = temp;
-                rec.normal = (rec.p - self.center) / self.radius;
-                return true;
-            }
-
-            temp = (-b + (b * b - a * c).sqrt()) / a;
-
-            if temp < t_max && temp > t_min {
-                rec.p = r.point_at_parameter(temp);
-                rec.t = temp;
-                rec.normal = (rec.p - self.center) / self.radius;
-                return true;
-            }
-        }
-
-        return false;
-    }
-}
-
-impl MaterialAccessor for Sphere {
-    fn get_material_info(self) -> (i32, i32) {
-        return (self.material_type, self.material_index);
-    }
-}
+use crate::aabb::Aabb;
+use crate::instance::Instance;
+use crate::material::MaterialHandle;
+use crate::mesh::Triangle;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use crate::math;
+use crate::random_utils;
+
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy)]
+pub struct HitRecord {
+    pub t: f32,
+    pub p: Vec3,
+    pub normal: Vec3,
+    /// Surface parametrization at the hit point, used for texturing.
+    pub u: f32,
+    pub v: f32,
+    pub material: MaterialHandle,
+    /// Index of the primitive hit in `Bvh::primitives`, so a light hit by chance can be
+    /// told apart from the others.
+    pub primitive: usize
+}
+
+#[derive(Copy, Clone)]
+pub struct Sphere {
+    /// The center at time 0.
+    center: Vec3,
+    /// How far the center moves from time 0 to time 1.
+    motion: Vec3,
+    radius: f32,
+    material: MaterialHandle
+}
+
+pub trait Hitable {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, hit_record: &mut HitRecord) -> bool;
+
+    /// The world space box enclosing the shape, used to build the BVH.
+    fn bounding_box(&self) -> Aabb;
+}
+
+/// A point picked on a shape as seen from some other point in the scene.
+#[derive(Clone, Copy)]
+pub struct ShapeSample {
+    pub p: Vec3,
+    pub normal: Vec3,
+    /// Density of the sample with respect to solid angle at the viewing point.
+    pub pdf: f32
+}
+
+/// Shapes which can be sampled directly, which is what lets them be used as area lights.
+pub trait Sampleable {
+    /// Picks a point on the shape visible from `origin`, using the two uniform numbers `u`.
+    /// Moving shapes are sampled where they are at `time`.
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample>;
+
+    /// The solid angle density `sample` would have chosen `direction` from `origin`
+    /// at `time` with, 0 when the direction misses the shape.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32;
+
+    /// Whether `sample` can return points at all. Shapes which cannot are left out of
+    /// the lights direct lighting picks from.
+    fn is_sampleable(&self) -> bool {
+        return true;
+    }
+}
+
+pub trait MaterialAccessor {
+    /// The material of the whole shape. Shapes made of parts with their own materials,
+    /// like instances, return `MaterialHandle::UNASSIGNED` and fill in the part's
+    /// material when hit.
+    fn material(&self) -> MaterialHandle;
+}
+
+impl HitRecord {
+    pub fn default() -> HitRecord {
+        HitRecord {
+            t: 0.0,
+            p: Vec3::from_uniform_value(0.0),
+            normal: Vec3::from_uniform_value(0.0),
+            u: 0.0,
+            v: 0.0,
+            material: MaterialHandle::UNASSIGNED,
+            primitive: usize::MAX
+        }
+    }
+}
+
+impl std::fmt::Display for HitRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(t: {}, Point: {}, {}, {}, Normal: {}, {}, {})", 
+            self.t, 
+            self.p.x, self.p.y, self.p.z, 
+            self.normal.x, self.normal.y, self.normal.z)
+    }
+}
+
+impl Sphere {
+    pub fn new(cent: Vec3, r: f32) -> Sphere {
+        return Sphere { 
+            center: cent, 
+            motion: Vec3::zero(),
+            radius: r, 
+            material: MaterialHandle::UNASSIGNED
+        }
+    }
+
+    pub fn with_material(mut self, material: MaterialHandle) -> Sphere {
+        self.material = material;
+        return self;
+    }
+
+    /// Moves the sphere in a straight line, from its center at time 0 to `end` at time 1.
+    pub fn with_motion(mut self, end: Vec3) -> Sphere {
+        self.motion = end - self.center;
+        return self;
+    }
+
+    /// The center at `time`, holding still before 0 and after 1 like the bounding box.
+    fn center(&self, time: f32) -> Vec3 {
+        return self.center + time.clamp(0.0, 1.0) * self.motion;
+    }
+
+    /// Spherical coordinates of `p` on the sphere centered at `center`: `u` goes once
+    /// around the y axis starting from -x, `v` runs from the bottom pole at 0 to the top
+    /// one at 1.
+    fn uv(center: Vec3, p: Vec3) -> (f32, f32) {
+        let d = (p - center).unit_vector();
+        let theta = (-d.y).clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + PI;
+        return (phi / (2.0 * PI), theta / PI);
+    }
+}
+
+impl Hitable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let center = self.center(r.time);
+        let oc = r.origin - center;
+
+        let a = math::dot(r.direction, r.direction);
+        let b = math::dot(oc, r.direction);
+        let c = math::dot(oc, oc) - self.radius * self.radius;
+
+        let discriminant = b * b - a * c;
+
+        if discriminant > 0.0 {
+            let mut temp = (-b - (b * b - a * c).sqrt()) / a;
+            if temp < t_max && temp > t_min {
+                rec.p = r.point_at_parameter(temp);
+                rec.t = temp;
+                rec.normal = (rec.p - center) / self.radius;
+                (rec.u, rec.v) = Sphere::uv(center, rec.p);
+                return true;
+            }
+
+            temp = (-b + (b * b - a * c).sqrt()) / a;
+
+            if temp < t_max && temp > t_min {
+                rec.p = r.point_at_parameter(temp);
+                rec.t = temp;
+                rec.normal = (rec.p - center) / self.radius;
+                (rec.u, rec.v) = Sphere::uv(center, rec.p);
+                return true;
+            }
+        }
+
+        return false;
+    }
+
+    /// Encloses the sphere over its whole motion from time 0 to 1.
+    fn bounding_box(&self) -> Aabb {
+        // Negative radii are used for hollow glass, so the extent must not flip.
+        let r = Vec3::from_uniform_value(self.radius.abs());
+        let end = self.center(1.0);
+        return Aabb::new(self.center - r, self.center + r).surrounding(Aabb::new(end - r, end + r));
+    }
+}
+
+impl Sampleable for Sphere {
+    /// Samples the cone of directions subtended by the sphere, or the whole sphere of
+    /// directions when `origin` lies inside it.
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample> {
+        let radius = self.radius.abs();
+        let center = self.center(time);
+        let to_center = center - origin;
+        let dist_sq = to_center.length_sq();
+
+        if dist_sq <= radius * radius {
+            let direction = random_utils::uniform_sphere(u);
+            let mut rec = HitRecord::default();
+            if !self.hit(&Ray::new(&origin, &direction).with_time(time), 0.0, f32::MAX, &mut rec) {
+                return None;
+            }
+            return Some(ShapeSample { p: rec.p, normal: rec.normal, pdf: 1.0 / (4.0 * PI) });
+        }
+
+        let cos_theta_max = (1.0 - radius * radius / dist_sq).max(0.0).sqrt();
+        let cos_theta = 1.0 - u.0 * (1.0 - cos_theta_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+
+        let w = to_center / dist_sq.sqrt();
+        let (t, b) = math::orthonormal_basis(w);
+        let direction = sin_theta * phi.cos() * t + sin_theta * phi.sin() * b + cos_theta * w;
+
+        // Distance to the sphere along the sampled direction, clamped for directions
+        // grazing the silhouette where rounding leaves the discriminant just below 0.
+        let along = math::dot(to_center, direction);
+        let dist = along - (radius * radius - (dist_sq - along * along)).max(0.0).sqrt();
+        let p = origin + dist * direction;
+
+        return Some(ShapeSample {
+            p,
+            normal: (p - center) / radius,
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+        });
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        let mut rec = HitRecord::default();
+        if !self.hit(&Ray::new(&origin, &direction).with_time(time), 0.001, f32::MAX, &mut rec) {
+            return 0.0;
+        }
+
+        let radius = self.radius.abs();
+        let dist_sq = (self.center(time) - origin).length_sq();
+        if dist_sq <= radius * radius {
+            return 1.0 / (4.0 * PI);
+        }
+        let cos_theta_max = (1.0 - radius * radius / dist_sq).max(0.0).sqrt();
+        return 1.0 / (2.0 * PI * (1.0 - cos_theta_max));
+    }
+}
+
+impl MaterialAccessor for Sphere {
+    fn material(&self) -> MaterialHandle {
+        return self.material;
+    }
+}
+
+/// Planes have no end, but the BVH needs finite bounds to sort them, so they are
+/// bounded this far out from the origin along the directions they extend in.
+const PLANE_EXTENT: f32 = 1e6;
+
+/// Converts the density of uniformly picking `p` on a surface of `area` to solid angle
+/// as seen from `origin`.
+fn area_to_solid_angle(origin: Vec3, p: Vec3, normal: Vec3, area: f32) -> f32 {
+    let to_point = p - origin;
+    let dist_sq = to_point.length_sq();
+    let cos = math::dot(normal, to_point).abs() / dist_sq.sqrt();
+    if area <= 0.0 || cos == 0.0 {
+        return 0.0;
+    }
+    return dist_sq / (cos * area);
+}
+
+/// An infinite plane through `point`. Texture coordinates are the hit's position along
+/// two tangents, in scene units, so image textures tile across it.
+#[derive(Clone, Copy)]
+pub struct Plane {
+    point: Vec3,
+    normal: Vec3,
+    material: MaterialHandle
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3) -> Plane {
+        return Plane {
+            point,
+            normal: normal.unit_vector(),
+            material: MaterialHandle::UNASSIGNED
+        }
+    }
+
+    pub fn with_material(mut self, material: MaterialHandle) -> Plane {
+        self.material = material;
+        return self;
+    }
+}
+
+impl Hitable for Plane {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let denom = math::dot(self.normal, r.direction);
+        if denom == 0.0 {
+            return false;
+        }
+        let t = math::dot(self.point - r.origin, self.normal) / denom;
+        if !(t > t_min && t < t_max) {
+            return false;
+        }
+
+        let (tangent, bitangent) = math::orthonormal_basis(self.normal);
+        rec.t = t;
+        rec.p = r.point_at_parameter(t);
+        rec.normal = self.normal;
+        rec.u = math::dot(rec.p - self.point, tangent);
+        rec.v = math::dot(rec.p - self.point, bitangent);
+        return true;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // Planes facing down an axis stay thin along it, the others fill the whole box.
+        let mut min = Vec3::from_uniform_value(-PLANE_EXTENT);
+        let mut max = Vec3::from_uniform_value(PLANE_EXTENT);
+        for axis in 0..3 {
+            if self.normal[axis].abs() == 1.0 {
+                min[axis] = self.point[axis] - 1e-4;
+                max[axis] = self.point[axis] + 1e-4;
+            }
+        }
+        return Aabb::new(min, max);
+    }
+}
+
+impl Sampleable for Plane {
+    /// An infinite plane has no finite area to pick points from, so it cannot be a
+    /// sampled light. Hitting it still picks up its emission.
+    #[allow(unused_variables)]
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample> {
+        return None;
+    }
+
+    #[allow(unused_variables)]
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        return 0.0;
+    }
+
+    fn is_sampleable(&self) -> bool {
+        return false;
+    }
+}
+
+impl MaterialAccessor for Plane {
+    fn material(&self) -> MaterialHandle {
+        return self.material;
+    }
+}
+
+/// The parallelogram spanned by the edges `u` and `v` from `corner`, a rectangle when
+/// they are perpendicular. It faces along `u x v` and its texture coordinates run from
+/// 0 to 1 along each edge.
+#[derive(Clone, Copy)]
+pub struct Quad {
+    corner: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    /// `u x v` divided by its squared length, which turns a point on the plane into
+    /// its coordinates along the edges.
+    w: Vec3,
+    area: f32,
+    material: MaterialHandle
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3) -> Quad {
+        let n = u.cross(v);
+        return Quad {
+            corner,
+            u,
+            v,
+            normal: n.unit_vector(),
+            w: n / n.length_sq(),
+            area: n.length(),
+            material: MaterialHandle::UNASSIGNED
+        }
+    }
+
+    /// The rectangle with opposite corners `a` and `b`, which must share exactly one
+    /// coordinate. It faces down the positive direction of that axis.
+    ///
+    /// # Returns
+    ///
+    /// The rectangle, or `None` when the corners do not lie in an axis aligned plane.
+    pub fn axis_aligned(a: Vec3, b: Vec3) -> Option<Quad> {
+        let min = a.min(b);
+        let size = a.max(b) - min;
+        let flat: Vec<usize> = (0..3).filter(|axis| size[*axis] == 0.0).collect();
+        if flat.len() != 1 {
+            return None;
+        }
+
+        // The two other axes in cyclic order, so that u x v points along +axis.
+        let axis = flat[0];
+        let (first, second) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut u = Vec3::zero();
+        let mut v = Vec3::zero();
+        u[first] = size[first];
+        v[second] = size[second];
+        return Some(Quad::new(min, u, v));
+    }
+
+    pub fn with_material(mut self, material: MaterialHandle) -> Quad {
+        self.material = material;
+        return self;
+    }
+
+    /// The six faces of the parallelepiped spanned by `x`, `y` and `z` from `corner`,
+    /// all facing outwards when the edges form a right handed frame.
+    fn box_faces(corner: Vec3, x: Vec3, y: Vec3, z: Vec3) -> [Quad; 6] {
+        let far = corner + x + y + z;
+        return [
+            Quad::new(corner, y, x),
+            Quad::new(corner, z, y),
+            Quad::new(corner, x, z),
+            Quad::new(far, -x, -y),
+            Quad::new(far, -y, -z),
+            Quad::new(far, -z, -x)
+        ];
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let denom = math::dot(self.normal, r.direction);
+        if denom == 0.0 {
+            return false;
+        }
+        let t = math::dot(self.corner - r.origin, self.normal) / denom;
+        if !(t > t_min && t < t_max) {
+            return false;
+        }
+
+        let p = r.point_at_parameter(t);
+        let offset = p - self.corner;
+        let alpha = math::dot(self.w, offset.cross(self.v));
+        let beta = math::dot(self.w, self.u.cross(offset));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = p;
+        rec.normal = self.normal;
+        rec.u = alpha;
+        rec.v = beta;
+        return true;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let bounds = Aabb::new(self.corner, self.corner)
+            .grow(self.corner + self.u)
+            .grow(self.corner + self.v)
+            .grow(self.corner + self.u + self.v);
+
+        // Axis aligned quads have a flat box, pad it so the slab test stays reliable.
+        let pad = Vec3::from_uniform_value(1e-4);
+        return Aabb::new(bounds.min - pad, bounds.max + pad);
+    }
+}
+
+impl Sampleable for Quad {
+    /// Samples the quad uniformly by area and converts the density to solid angle.
+    #[allow(unused_variables)]
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample> {
+        let p = self.corner + u.0 * self.u + u.1 * self.v;
+        let pdf = area_to_solid_angle(origin, p, self.normal, self.area);
+        if pdf == 0.0 {
+            return None;
+        }
+        return Some(ShapeSample { p, normal: self.normal, pdf });
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        let mut rec = HitRecord::default();
+        if !self.hit(&Ray::new(&origin, &direction).with_time(time), 0.001, f32::MAX, &mut rec) {
+            return 0.0;
+        }
+        return area_to_solid_angle(origin, rec.p, self.normal, self.area);
+    }
+}
+
+impl MaterialAccessor for Quad {
+    fn material(&self) -> MaterialHandle {
+        return self.material;
+    }
+}
+
+/// A flat disk facing along `normal`. Texture coordinates are polar, `u` going once
+/// around the center and `v` running from the center at 0 to the rim at 1.
+#[derive(Clone, Copy)]
+pub struct Disk {
+    center: Vec3,
+    normal: Vec3,
+    radius: f32,
+    material: MaterialHandle
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f32) -> Disk {
+        return Disk {
+            center,
+            normal: normal.unit_vector(),
+            radius: radius.abs(),
+            material: MaterialHandle::UNASSIGNED
+        }
+    }
+
+    pub fn with_material(mut self, material: MaterialHandle) -> Disk {
+        self.material = material;
+        return self;
+    }
+
+    fn area(&self) -> f32 {
+        return PI * self.radius * self.radius;
+    }
+}
+
+impl Hitable for Disk {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let denom = math::dot(self.normal, r.direction);
+        if denom == 0.0 {
+            return false;
+        }
+        let t = math::dot(self.center - r.origin, self.normal) / denom;
+        if !(t > t_min && t < t_max) {
+            return false;
+        }
+
+        let p = r.point_at_parameter(t);
+        let offset = p - self.center;
+        let dist_sq = offset.length_sq();
+        if dist_sq > self.radius * self.radius {
+            return false;
+        }
+
+        let (tangent, bitangent) = math::orthonormal_basis(self.normal);
+        let phi = math::dot(offset, bitangent).atan2(math::dot(offset, tangent));
+        rec.t = t;
+        rec.p = p;
+        rec.normal = self.normal;
+        rec.u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        rec.v = dist_sq.sqrt() / self.radius;
+        return true;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // The disk extends radius * sin(angle between the normal and the axis) along
+        // each axis.
+        let n = self.normal;
+        let extent = self.radius * Vec3::new(
+            (1.0 - n.x * n.x).max(0.0).sqrt(),
+            (1.0 - n.y * n.y).max(0.0).sqrt(),
+            (1.0 - n.z * n.z).max(0.0).sqrt()) + Vec3::from_uniform_value(1e-4);
+        return Aabb::new(self.center - extent, self.center + extent);
+    }
+}
+
+impl Sampleable for Disk {
+    /// Samples the disk uniformly by area and converts the density to solid angle.
+    #[allow(unused_variables)]
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample> {
+        let (tangent, bitangent) = math::orthonormal_basis(self.normal);
+        let r = self.radius * u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p = self.center + r * phi.cos() * tangent + r * phi.sin() * bitangent;
+
+        let pdf = area_to_solid_angle(origin, p, self.normal, self.area());
+        if pdf == 0.0 {
+            return None;
+        }
+        return Some(ShapeSample { p, normal: self.normal, pdf });
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        let mut rec = HitRecord::default();
+        if !self.hit(&Ray::new(&origin, &direction).with_time(time), 0.001, f32::MAX, &mut rec) {
+            return 0.0;
+        }
+        return area_to_solid_angle(origin, rec.p, self.normal, self.area());
+    }
+}
+
+impl MaterialAccessor for Disk {
+    fn material(&self) -> MaterialHandle {
+        return self.material;
+    }
+}
+
+/// A closed box made of six quads facing outwards, either axis aligned or spanned by
+/// three edges from a corner.
+#[derive(Clone)]
+pub struct Cuboid {
+    faces: Box<[Quad; 6]>,
+    material: MaterialHandle
+}
+
+impl Cuboid {
+    /// The axis aligned box with opposite corners `a` and `b`.
+    pub fn new(a: Vec3, b: Vec3) -> Cuboid {
+        let min = a.min(b);
+        let size = a.max(b) - min;
+        return Cuboid::oriented(
+            min,
+            Vec3::new(size.x, 0.0, 0.0),
+            Vec3::new(0.0, size.y, 0.0),
+            Vec3::new(0.0, 0.0, size.z));
+    }
+
+    /// The box spanned by the edges `x`, `y` and `z` from `corner`, which should be
+    /// perpendicular and right handed for the faces to point outwards.
+    pub fn oriented(corner: Vec3, x: Vec3, y: Vec3, z: Vec3) -> Cuboid {
+        return Cuboid {
+            faces: Box::new(Quad::box_faces(corner, x, y, z)),
+            material: MaterialHandle::UNASSIGNED
+        }
+    }
+
+    pub fn with_material(mut self, material: MaterialHandle) -> Cuboid {
+        self.material = material;
+        return self;
+    }
+
+    fn area(&self) -> f32 {
+        return self.faces.iter().map(|face| face.area).sum();
+    }
+}
+
+impl Hitable for Cuboid {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let mut hit_anything = false;
+        let mut closest = t_max;
+        for face in self.faces.iter() {
+            if face.hit(r, t_min, closest, rec) {
+                hit_anything = true;
+                closest = rec.t;
+            }
+        }
+        return hit_anything;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.faces.iter().fold(Aabb::empty(), |acc, face| acc.surrounding(face.bounding_box()));
+    }
+}
+
+impl Sampleable for Cuboid {
+    /// Picks a face with a probability proportional to its area, then a point on it.
+    /// Points on the faces turned away from `origin` end up shadowed by the near ones.
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample> {
+        let total = self.area();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut start = 0.0;
+        for (index, face) in self.faces.iter().enumerate() {
+            let probability = face.area / total;
+            if u.0 < start + probability || index == self.faces.len() - 1 {
+                // Reuse the part of the number within the face's interval.
+                let u0 = ((u.0 - start) / probability).clamp(0.0, 1.0 - f32::EPSILON);
+                let sample = face.sample(origin, time, (u0, u.1))?;
+                return Some(ShapeSample { pdf: probability * sample.pdf, ..sample });
+            }
+            start += probability;
+        }
+        return None;
+    }
+
+    /// Only the face the direction reaches first can be lit from `origin`, so the
+    /// density is that of picking a point on it.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        let total = self.area();
+        let ray = Ray::new(&origin, &direction).with_time(time);
+        let mut rec = HitRecord::default();
+        let mut closest = f32::MAX;
+        let mut nearest = None;
+        for face in self.faces.iter() {
+            if face.hit(&ray, 0.001, closest, &mut rec) {
+                closest = rec.t;
+                nearest = Some(face);
+            }
+        }
+
+        match nearest {
+            Some(face) if total > 0.0 => return face.area / total * face.pdf_value(origin, direction, time),
+            _ => return 0.0
+        }
+    }
+}
+
+impl MaterialAccessor for Cuboid {
+    fn material(&self) -> MaterialHandle {
+        return self.material;
+    }
+}
+
+/// Every kind of shape the world can be built from, so a single BVH can hold them all.
+///
+/// The bulky parts of some shapes, a cuboid's six faces and an instance's transforms,
+/// are boxed so they do not grow every other primitive to their size.
+#[derive(Clone)]
+pub enum Primitive {
+    Sphere(Sphere),
+    Triangle(Triangle),
+    Plane(Plane),
+    Quad(Quad),
+    Disk(Disk),
+    Cuboid(Cuboid),
+    Instance(Instance)
+}
+
+impl Hitable for Primitive {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        match self {
+            Primitive::Sphere(sphere) => return sphere.hit(r, t_min, t_max, rec),
+            Primitive::Triangle(triangle) => return triangle.hit(r, t_min, t_max, rec),
+            Primitive::Plane(plane) => return plane.hit(r, t_min, t_max, rec),
+            Primitive::Quad(quad) => return quad.hit(r, t_min, t_max, rec),
+            Primitive::Disk(disk) => return disk.hit(r, t_min, t_max, rec),
+            Primitive::Cuboid(cuboid) => return cuboid.hit(r, t_min, t_max, rec),
+            Primitive::Instance(instance) => return instance.hit(r, t_min, t_max, rec)
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            Primitive::Sphere(sphere) => return sphere.bounding_box(),
+            Primitive::Triangle(triangle) => return triangle.bounding_box(),
+            Primitive::Plane(plane) => return plane.bounding_box(),
+            Primitive::Quad(quad) => return quad.bounding_box(),
+            Primitive::Disk(disk) => return disk.bounding_box(),
+            Primitive::Cuboid(cuboid) => return cuboid.bounding_box(),
+            Primitive::Instance(instance) => return instance.bounding_box()
+        }
+    }
+}
+
+impl MaterialAccessor for Primitive {
+    fn material(&self) -> MaterialHandle {
+        match self {
+            Primitive::Sphere(sphere) => return sphere.material(),
+            Primitive::Triangle(triangle) => return triangle.material(),
+            Primitive::Plane(plane) => return plane.material(),
+            Primitive::Quad(quad) => return quad.material(),
+            Primitive::Disk(disk) => return disk.material(),
+            Primitive::Cuboid(cuboid) => return cuboid.material(),
+            Primitive::Instance(instance) => return instance.material()
+        }
+    }
+}
+
+impl Sampleable for Primitive {
+    fn sample(&self, origin: Vec3, time: f32, u: (f32, f32)) -> Option<ShapeSample> {
+        match self {
+            Primitive::Sphere(sphere) => return sphere.sample(origin, time, u),
+            Primitive::Triangle(triangle) => return triangle.sample(origin, time, u),
+            Primitive::Plane(plane) => return plane.sample(origin, time, u),
+            Primitive::Quad(quad) => return quad.sample(origin, time, u),
+            Primitive::Disk(disk) => return disk.sample(origin, time, u),
+            Primitive::Cuboid(cuboid) => return cuboid.sample(origin, time, u),
+            Primitive::Instance(instance) => return instance.sample(origin, time, u)
+        }
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, time: f32) -> f32 {
+        match self {
+            Primitive::Sphere(sphere) => return sphere.pdf_value(origin, direction, time),
+            Primitive::Triangle(triangle) => return triangle.pdf_value(origin, direction, time),
+            Primitive::Plane(plane) => return plane.pdf_value(origin, direction, time),
+            Primitive::Quad(quad) => return quad.pdf_value(origin, direction, time),
+            Primitive::Disk(disk) => return disk.pdf_value(origin, direction, time),
+            Primitive::Cuboid(cuboid) => return cuboid.pdf_value(origin, direction, time),
+            Primitive::Instance(instance) => return instance.pdf_value(origin, direction, time)
+        }
+    }
+
+    fn is_sampleable(&self) -> bool {
+        match self {
+            Primitive::Sphere(sphere) => return sphere.is_sampleable(),
+            Primitive::Triangle(triangle) => return triangle.is_sampleable(),
+            Primitive::Plane(plane) => return plane.is_sampleable(),
+            Primitive::Quad(quad) => return quad.is_sampleable(),
+            Primitive::Disk(disk) => return disk.is_sampleable(),
+            Primitive::Cuboid(cuboid) => return cuboid.is_sampleable(),
+            Primitive::Instance(instance) => return instance.is_sampleable()
+        }
+    }
+}
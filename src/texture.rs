@@ -0,0 +1,299 @@
+use std::sync::Arc;
+
+use crate::math::dot;
+use crate::random_utils::{self, RandomContext};
+use crate::render::Framebuffer;
+use crate::vec3::Vec3;
+
+/// A color varying over a surface, looked up with the hit's texture coordinates and
+/// position. Textures are shared by all render threads.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3;
+}
+
+/// The same color everywhere.
+#[derive(Clone, Copy)]
+pub struct SolidColor {
+    color: Vec3
+}
+
+impl SolidColor {
+    pub fn new(color: Vec3) -> SolidColor {
+        return SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        return self.color;
+    }
+}
+
+/// Alternates between two textures in a 3D grid of cubes `scale` units wide, so it does
+/// not depend on the surface having sensible texture coordinates.
+pub struct Checker {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: f32
+}
+
+impl Checker {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f32) -> Checker {
+        return Checker { even, odd, scale }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        let cell = (p.x / self.scale).floor() as i64 + (p.y / self.scale).floor() as i64 + (p.z / self.scale).floor() as i64;
+        if cell.rem_euclid(2) == 0 {
+            return self.even.value(u, v, p);
+        }
+        return self.odd.value(u, v, p);
+    }
+}
+
+/// What an image texture shows outside the [0, 1] range of texture coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    /// Tiles the image.
+    Repeat,
+    /// Tiles the image, flipping every other copy so the edges line up.
+    Mirror,
+    /// Stretches the border pixels.
+    Clamp
+}
+
+impl WrapMode {
+    pub fn from_name(name: &str) -> Option<WrapMode> {
+        match name {
+            "repeat" => return Some(WrapMode::Repeat),
+            "mirror" => return Some(WrapMode::Mirror),
+            "clamp" => return Some(WrapMode::Clamp),
+            _ => return None
+        }
+    }
+
+    /// Maps a pixel index which may lie outside the image back inside `0..size`.
+    fn wrap(self, index: i64, size: i64) -> i64 {
+        match self {
+            WrapMode::Repeat => return index.rem_euclid(size),
+            WrapMode::Mirror => {
+                let period = index.rem_euclid(2 * size);
+                return if period < size { period } else { 2 * size - 1 - period };
+            },
+            WrapMode::Clamp => return index.clamp(0, size - 1)
+        }
+    }
+}
+
+/// An image mapped onto the texture coordinates, with `v = 0` at the bottom row, and
+/// filtered bilinearly.
+pub struct ImageTexture {
+    image: Framebuffer,
+    wrap: WrapMode
+}
+
+impl ImageTexture {
+    /// Wraps an image of linear values, as returned by `image_reader::load`.
+    pub fn new(image: Framebuffer, wrap: WrapMode) -> ImageTexture {
+        return ImageTexture { image, wrap }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let (width, height) = (self.image.width as i64, self.image.height as i64);
+        let x = self.wrap.wrap(x, width);
+        // Rows are stored top to bottom.
+        let y = height - 1 - self.wrap.wrap(y, height);
+        return self.image.pixels[(y * width + x) as usize];
+    }
+}
+
+impl Texture for ImageTexture {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        if self.image.pixels.is_empty() || !u.is_finite() || !v.is_finite() {
+            return Vec3::zero();
+        }
+
+        // Pixel centers sit at half integer coordinates.
+        let x = u * self.image.width as f32 - 0.5;
+        let y = v * self.image.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let bottom = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let top = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        return (1.0 - fy) * bottom + fy * top;
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+
+/// Ken Perlin's gradient noise, built from random unit gradients on a lattice and
+/// smoothly interpolated between them.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>
+}
+
+impl Perlin {
+    /// Creates the lattice tables from `rng`, the same generator always giving the same
+    /// noise.
+    pub fn new(rng: &mut RandomContext) -> Perlin {
+        let gradients = (0..PERLIN_POINTS)
+            .map(|_| random_utils::uniform_sphere((rng.next_f32(), rng.next_f32())))
+            .collect();
+
+        return Perlin {
+            gradients,
+            perm_x: Perlin::permutation(rng),
+            perm_y: Perlin::permutation(rng),
+            perm_z: Perlin::permutation(rng)
+        }
+    }
+
+    fn permutation(rng: &mut RandomContext) -> Vec<usize> {
+        let mut perm: Vec<usize> = (0..PERLIN_POINTS).collect();
+        for i in (1..PERLIN_POINTS).rev() {
+            let target = ((rng.next_f32() * (i + 1) as f32) as usize).min(i);
+            perm.swap(i, target);
+        }
+        return perm;
+    }
+
+    /// Noise at `p`, roughly in [-1, 1].
+    pub fn noise(&self, p: Vec3) -> f32 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // Hermite smoothing hides the lattice, blending the corner gradients' ramps.
+        let (uu, vv, ww) = (u * u * (3.0 - 2.0 * u), v * v * (3.0 - 2.0 * v), w * w * (3.0 - 2.0 * w));
+        let mask = PERLIN_POINTS as i64 - 1;
+
+        let mut sum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.perm_x[((i + di) & mask) as usize]
+                        ^ self.perm_y[((j + dj) & mask) as usize]
+                        ^ self.perm_z[((k + dk) & mask) as usize];
+                    let (a, b, c) = (di as f32, dj as f32, dk as f32);
+                    let weight = Vec3::new(u - a, v - b, w - c);
+
+                    sum += (a * uu + (1.0 - a) * (1.0 - uu))
+                        * (b * vv + (1.0 - b) * (1.0 - vv))
+                        * (c * ww + (1.0 - c) * (1.0 - ww))
+                        * dot(self.gradients[index], weight);
+                }
+            }
+        }
+        return sum;
+    }
+
+    /// Sum of `octaves` layers of noise, each at twice the frequency and half the
+    /// amplitude of the last, taking the absolute value to form creases.
+    pub fn turbulence(&self, p: Vec3, octaves: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut point = p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            sum += weight * self.noise(point);
+            weight *= 0.5;
+            point = point * 2.0;
+        }
+        return sum.abs();
+    }
+}
+
+/// The patterns a `NoiseTexture` can draw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoisePattern {
+    /// Plain noise, a soft blotchy gray.
+    Perlin,
+    /// Several octaves of noise, like smoke.
+    Turbulence,
+    /// Stripes along z distorted by turbulence.
+    Marble
+}
+
+impl NoisePattern {
+    pub fn from_name(name: &str) -> Option<NoisePattern> {
+        match name {
+            "perlin" => return Some(NoisePattern::Perlin),
+            "turbulence" => return Some(NoisePattern::Turbulence),
+            "marble" => return Some(NoisePattern::Marble),
+            _ => return None
+        }
+    }
+}
+
+/// A procedural texture blending between black and `color` with noise evaluated at the
+/// hit position, `scale` being the frequency of the pattern.
+pub struct NoiseTexture {
+    perlin: Perlin,
+    pattern: NoisePattern,
+    color: Vec3,
+    scale: f32,
+    octaves: u32
+}
+
+impl NoiseTexture {
+    pub fn new(pattern: NoisePattern, color: Vec3, scale: f32, octaves: u32, rng: &mut RandomContext) -> NoiseTexture {
+        return NoiseTexture {
+            perlin: Perlin::new(rng),
+            pattern,
+            color,
+            scale,
+            octaves
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        let p = p * self.scale;
+        let amount = match self.pattern {
+            NoisePattern::Perlin => 0.5 * (1.0 + self.perlin.noise(p)),
+            NoisePattern::Turbulence => self.perlin.turbulence(p, self.octaves),
+            NoisePattern::Marble => 0.5 * (1.0 + (p.z + 10.0 * self.perlin.turbulence(p, self.octaves)).sin())
+        };
+        return self.color * amount.clamp(0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_modes_map_indices_past_the_edges() {
+        let wrap = |mode: WrapMode| -> Vec<i64> { (-5..9).map(|i| mode.wrap(i, 4)).collect() };
+        assert_eq!(wrap(WrapMode::Repeat), [3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0]);
+        assert_eq!(wrap(WrapMode::Mirror), [3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0]);
+        assert_eq!(wrap(WrapMode::Clamp), [0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3]);
+
+        for mode in [WrapMode::Repeat, WrapMode::Mirror, WrapMode::Clamp] {
+            assert!((-3..3).all(|i| mode.wrap(i, 1) == 0));
+        }
+    }
+
+    #[test]
+    fn image_texture_puts_v_zero_at_the_bottom_row() {
+        let mut image = Framebuffer::new(2, 2);
+        image.set(0, 1, Vec3::new(1.0, 0.0, 0.0));
+        image.set(1, 0, Vec3::new(0.0, 0.0, 1.0));
+        let texture = ImageTexture::new(image, WrapMode::Clamp);
+
+        let bottom_left = texture.value(0.0, 0.0, Vec3::zero());
+        assert_eq!([bottom_left.x, bottom_left.y, bottom_left.z], [1.0, 0.0, 0.0]);
+        let top_right = texture.value(1.0, 1.0, Vec3::zero());
+        assert_eq!([top_right.x, top_right.y, top_right.z], [0.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,310 @@
+use crate::render::Framebuffer;
+use crate::vec3::Vec3;
+
+/// How scene radiance is compressed into the [0, 1] range of a display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// Everything above 1 is cut off.
+    Clamp,
+    /// L / (1 + L) on the luminance, never quite reaching white.
+    Reinhard,
+    /// Reinhard with a white point: luminance `white` and above maps to 1.
+    ReinhardExtended { white: f32 },
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable,
+    /// Krzysztof Narkowicz's fit of the ACES filmic reference rendering transform.
+    Aces
+}
+
+/// The curve encoding display linear values for storage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Srgb,
+    /// A pure power law, `value^(1 / gamma)`.
+    Gamma(f32),
+    Linear
+}
+
+impl ToneMapOperator {
+    /// Parses a name as accepted by `--tonemap`. Reinhard extended takes its white point
+    /// separately.
+    pub fn from_name(name: &str, white: f32) -> Option<ToneMapOperator> {
+        match name {
+            "clamp" => return Some(ToneMapOperator::Clamp),
+            "reinhard" => return Some(ToneMapOperator::Reinhard),
+            "reinhard-extended" => return Some(ToneMapOperator::ReinhardExtended { white }),
+            "hable" => return Some(ToneMapOperator::Hable),
+            "aces" => return Some(ToneMapOperator::Aces),
+            _ => return None
+        }
+    }
+
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        match self {
+            ToneMapOperator::Clamp => return color,
+            ToneMapOperator::Reinhard => {
+                let l = luminance(color);
+                return scale_luminance(color, l, l / (1.0 + l));
+            },
+            ToneMapOperator::ReinhardExtended { white } => {
+                let l = luminance(color);
+                return scale_luminance(color, l, l * (1.0 + l / (white * white)) / (1.0 + l));
+            },
+            ToneMapOperator::Hable => {
+                // The curve is normalized so that the linear white point W maps to 1,
+                // after the exposure bias of 2 used in the original.
+                const W: f32 = 11.2;
+                let white_scale = 1.0 / hable_partial(W);
+                return Vec3::new(
+                    hable_partial(2.0 * color.x) * white_scale,
+                    hable_partial(2.0 * color.y) * white_scale,
+                    hable_partial(2.0 * color.z) * white_scale);
+            },
+            ToneMapOperator::Aces => {
+                // The fit expects the exposure the ACES reference applies, about 0.6.
+                return Vec3::new(aces_fitted(0.6 * color.x), aces_fitted(0.6 * color.y), aces_fitted(0.6 * color.z));
+            }
+        }
+    }
+}
+
+impl Transfer {
+    /// Parses `srgb`, `linear` or a gamma value such as `2.2`.
+    pub fn from_name(name: &str) -> Option<Transfer> {
+        match name {
+            "srgb" => return Some(Transfer::Srgb),
+            "linear" => return Some(Transfer::Linear),
+            _ => match name.parse::<f32>() {
+                Ok(gamma) if gamma.is_finite() && gamma > 0.0 => return Some(Transfer::Gamma(gamma)),
+                _ => return None
+            }
+        }
+    }
+
+    pub fn encode(self, value: f32) -> f32 {
+        match self {
+            Transfer::Srgb => return linear_to_srgb(value),
+            Transfer::Gamma(gamma) => return value.powf(1.0 / gamma),
+            Transfer::Linear => return value
+        }
+    }
+}
+
+/// The sRGB transfer curve, mapping a linear value in [0, 1] to its encoded value.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        return 12.92 * value;
+    }
+    return 1.055 * value.powf(1.0 / 2.4) - 0.055;
+}
+
+/// The inverse of `linear_to_srgb`, used to read 8 bit images.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        return value / 12.92;
+    }
+    return ((value + 0.055) / 1.055).powf(2.4);
+}
+
+/// Relative luminance of a linear sRGB / Rec. 709 color.
+pub fn luminance(color: Vec3) -> f32 {
+    return 0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+}
+
+fn scale_luminance(color: Vec3, from: f32, to: f32) -> Vec3 {
+    if from <= 0.0 {
+        return Vec3::zero();
+    }
+    return color * (to / from);
+}
+
+fn hable_partial(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+fn aces_fitted(x: f32) -> f32 {
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+type Matrix3 = [[f32; 3]; 3];
+
+fn mul_matrix(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+        }
+    }
+    return result;
+}
+
+fn mul_vector(m: &Matrix3, v: Vec3) -> Vec3 {
+    return Vec3::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z);
+}
+
+#[allow(clippy::excessive_precision)]
+const SRGB_TO_XYZ: Matrix3 = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041]
+];
+
+#[allow(clippy::excessive_precision)]
+const XYZ_TO_SRGB: Matrix3 = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252]
+];
+
+const BRADFORD: Matrix3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296]
+];
+
+#[allow(clippy::excessive_precision)]
+const BRADFORD_INVERSE: Matrix3 = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867]
+];
+
+/// XYZ (with Y = 1) of a black body at `kelvin`, using the cubic fit of the Planckian
+/// locus by Kang et al. (2002), valid from 1667 K to 25000 K.
+#[allow(clippy::excessive_precision)]
+fn planckian_xyz(kelvin: f32) -> Vec3 {
+    let t = kelvin.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+
+    return Vec3::new(x / y, 1.0, (1.0 - x - y) / y);
+}
+
+/// The linear sRGB matrix making a surface lit by a black body at `kelvin` look white,
+/// a Bradford chromatic adaptation from that illuminant to D65.
+fn white_balance_matrix(kelvin: f32) -> Matrix3 {
+    let d65 = Vec3::new(0.95047, 1.0, 1.08883);
+    let source = mul_vector(&BRADFORD, planckian_xyz(kelvin));
+    let target = mul_vector(&BRADFORD, d65);
+
+    let scale = [
+        [target.x / source.x, 0.0, 0.0],
+        [0.0, target.y / source.y, 0.0],
+        [0.0, 0.0, target.z / source.z]
+    ];
+    let adapt = mul_matrix(&BRADFORD_INVERSE, &mul_matrix(&scale, &BRADFORD));
+    return mul_matrix(&XYZ_TO_SRGB, &mul_matrix(&adapt, &SRGB_TO_XYZ));
+}
+
+/// The post-processing turning the rendered radiance into displayable values.
+///
+/// Exposure and white balance work on linear values and are applied to every output.
+/// The tone map and transfer curve then only produce the encoded [0, 1] values of the
+/// integer formats.
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayTransform {
+    /// Exposure compensation in stops, every step doubles the brightness.
+    pub exposure: f32,
+    /// Color temperature of the light to neutralize, in kelvin.
+    pub white_balance: Option<f32>,
+    pub operator: ToneMapOperator,
+    pub transfer: Transfer
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        return DisplayTransform {
+            exposure: 0.0,
+            white_balance: None,
+            operator: ToneMapOperator::Clamp,
+            transfer: Transfer::Srgb
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// Applies exposure and white balance, keeping the result linear and unbounded.
+    pub fn adjust(&self, framebuffer: &Framebuffer) -> Framebuffer {
+        let scale = 2f32.powf(self.exposure);
+        let balance = self.white_balance.map(white_balance_matrix);
+
+        let mut result = framebuffer.clone();
+        for pixel in result.pixels.iter_mut() {
+            let mut color = *pixel * scale;
+            if let Some(matrix) = &balance {
+                color = mul_vector(matrix, color).max(Vec3::zero());
+            }
+            *pixel = color;
+        }
+        return result;
+    }
+
+    /// Applies the whole pipeline, returning encoded values in [0, 1].
+    pub fn display(&self, framebuffer: &Framebuffer) -> Framebuffer {
+        let mut result = self.adjust(framebuffer);
+        for pixel in result.pixels.iter_mut() {
+            let mapped = self.operator.apply(*pixel);
+            let clamp = |v: f32| if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+            *pixel = Vec3::new(
+                self.transfer.encode(clamp(mapped.x)),
+                self.transfer.encode(clamp(mapped.y)),
+                self.transfer.encode(clamp(mapped.z)));
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_encoding_and_decoding_are_inverses() {
+        for i in 0..=1000 {
+            let value = i as f32 / 1000.0;
+            assert!((srgb_to_linear(linear_to_srgb(value)) - value).abs() < 1e-5, "{}", value);
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-5, "{}", value);
+        }
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-6);
+        // The two pieces meet at the threshold.
+        assert!((linear_to_srgb(0.0031308) - 0.04045).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reinhard_extended_maps_the_white_point_to_one() {
+        for &white in &[1.0, 4.0, 11.2] {
+            let operator = ToneMapOperator::ReinhardExtended { white };
+            let grey = operator.apply(Vec3::new(white, white, white));
+            assert!((luminance(grey) - 1.0).abs() < 1e-5, "{}", white);
+
+            // Below the white point the luminance stays below 1, with the hue kept.
+            let color = operator.apply(Vec3::new(0.5, 0.25, 0.125) * white);
+            assert!(luminance(color) < 1.0);
+            assert!((color.x / color.y - 2.0).abs() < 1e-5);
+        }
+        assert_eq!(luminance(ToneMapOperator::ReinhardExtended { white: 4.0 }.apply(Vec3::zero())), 0.0);
+    }
+}
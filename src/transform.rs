@@ -0,0 +1,403 @@
+use std::ops::Mul;
+
+use crate::aabb::Aabb;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// A 4x4 matrix acting on points and vectors as column vectors, `m[row][column]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f32; 4]; 4]
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f32; 4]; 4]) -> Matrix4 {
+        return Matrix4 { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Matrix4 {
+        return Matrix4::new([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0]
+        ]);
+    }
+
+    pub fn scaling(factors: Vec3) -> Matrix4 {
+        return Matrix4::new([
+            [factors.x, 0.0, 0.0, 0.0],
+            [0.0, factors.y, 0.0, 0.0],
+            [0.0, 0.0, factors.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ]);
+    }
+
+    /// Counterclockwise rotation by `degrees` around `axis` when looking down the axis
+    /// towards the origin (Rodrigues' formula).
+    pub fn rotation(axis: Vec3, degrees: f32) -> Matrix4 {
+        let a = axis.unit_vector();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        return Matrix4::new([
+            [t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.0],
+            [t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.0],
+            [t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ]);
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (row, values) in m.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = self.m[column][row];
+            }
+        }
+        return Matrix4::new(m);
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, carried out in double precision.
+    ///
+    /// # Returns
+    ///
+    /// The inverse, or `None` when the matrix is singular, such as a scale by zero.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        // The matrix on the left and the identity on the right, which the row
+        // operations turn into the identity and the inverse.
+        let mut a = [[0.0f64; 8]; 4];
+        for (row, values) in a.iter_mut().enumerate() {
+            for (value, element) in values.iter_mut().zip(self.m[row].iter()) {
+                *value = *element as f64;
+            }
+            values[4 + row] = 1.0;
+        }
+
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap_or(column);
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+
+            let scale = 1.0 / a[column][column];
+            for value in a[column].iter_mut() {
+                *value *= scale;
+            }
+            let pivot_row = a[column];
+            for (row, values) in a.iter_mut().enumerate() {
+                if row != column {
+                    let factor = values[column];
+                    for (value, pivot_value) in values.iter_mut().zip(pivot_row.iter()) {
+                        *value -= factor * pivot_value;
+                    }
+                }
+            }
+        }
+
+        let mut m = [[0.0; 4]; 4];
+        for (values, solved) in m.iter_mut().zip(a.iter()) {
+            for (value, element) in values.iter_mut().zip(solved[4..].iter()) {
+                *value = *element as f32;
+            }
+        }
+        return Some(Matrix4::new(m));
+    }
+
+    /// Whether the bottom row is `[0, 0, 0, 1]`, so that the matrix keeps straight lines
+    /// straight and evenly spaced. Transforms of rays rely on it to leave their `t`
+    /// alone.
+    pub fn is_affine(&self) -> bool {
+        return self.m[3] == [0.0, 0.0, 0.0, 1.0];
+    }
+
+    /// Transforms the point `p`, including the translation. The matrix must be affine.
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        debug_assert!(self.is_affine(), "projective matrix {:?}", self);
+        let m = &self.m;
+        return Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3]);
+    }
+
+    /// Transforms the direction `v`, which translations leave alone.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        return Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z);
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+    fn mul(self, rhs: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (row, values) in m.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[row][k] * rhs.m[k][column]).sum();
+            }
+        }
+        return Matrix4::new(m);
+    }
+}
+
+/// An invertible affine transform, stored along with its inverse so that rays can be
+/// taken into an object's space and hits brought back out without inverting per ray.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4
+}
+
+impl Transform {
+    /// # Returns
+    ///
+    /// The transform applying `matrix`, or `None` when it cannot be inverted.
+    pub fn new(matrix: Matrix4) -> Option<Transform> {
+        return matrix.inverse().map(|inverse| Transform { matrix, inverse });
+    }
+
+    pub fn translate(offset: Vec3) -> Transform {
+        return Transform {
+            matrix: Matrix4::translation(offset),
+            inverse: Matrix4::translation(-offset)
+        }
+    }
+
+    /// Scales by `factors` around the origin. A zero factor has no inverse and gives
+    /// `None`.
+    pub fn scale(factors: Vec3) -> Option<Transform> {
+        if factors.x == 0.0 || factors.y == 0.0 || factors.z == 0.0 {
+            return None;
+        }
+        return Some(Transform {
+            matrix: Matrix4::scaling(factors),
+            inverse: Matrix4::scaling(Vec3::new(1.0 / factors.x, 1.0 / factors.y, 1.0 / factors.z))
+        });
+    }
+
+    pub fn rotate(axis: Vec3, degrees: f32) -> Transform {
+        let matrix = Matrix4::rotation(axis, degrees);
+        // Rotations are orthogonal, their inverse is their transpose.
+        return Transform { matrix, inverse: matrix.transpose() };
+    }
+
+    /// The transform applying `self` first and then `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        return Transform {
+            matrix: next.matrix * self.matrix,
+            inverse: self.inverse * next.inverse
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        return Transform { matrix: self.inverse, inverse: self.matrix };
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        return self.matrix.transform_point(p);
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        return self.matrix.transform_vector(v);
+    }
+
+    /// Transforms a surface normal with the inverse transpose, which keeps it
+    /// perpendicular to the transformed surface under non uniform scaling. The result
+    /// is not normalized.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.inverse.m;
+        return Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z);
+    }
+
+    /// Transforms the ray without normalizing its direction, so a hit keeps the same
+    /// parameter `t` on both sides of the transform.
+    pub fn ray(&self, r: &Ray) -> Ray {
+        return Ray::new(&self.point(r.origin), &self.vector(r.direction)).with_time(r.time);
+    }
+
+    /// The box enclosing the transformed corners of `bounds`.
+    pub fn bounding_box(&self, bounds: Aabb) -> Aabb {
+        let mut result = Aabb::empty();
+        for corner in 0..8 {
+            let p = Vec3::new(
+                if corner & 1 != 0 { bounds.max.x } else { bounds.min.x },
+                if corner & 2 != 0 { bounds.max.y } else { bounds.min.y },
+                if corner & 4 != 0 { bounds.max.z } else { bounds.min.z });
+            result = result.grow(self.point(p));
+        }
+        return result;
+    }
+}
+
+/// A transform given by the parts it is built from, which can be blended smoothly: a
+/// scale, rotations in degrees around x, y and z in turn, then a translation.
+#[derive(Clone, Copy)]
+pub struct TransformParts {
+    pub scale: Vec3,
+    pub rotate: Vec3,
+    pub translate: Vec3
+}
+
+impl TransformParts {
+    /// # Returns
+    ///
+    /// The combined transform, or `None` when a scale factor is zero.
+    pub fn transform(&self) -> Option<Transform> {
+        let scaling = Transform::scale(self.scale)?;
+        return Some(scaling
+            .then(&Transform::rotate(Vec3::new(1.0, 0.0, 0.0), self.rotate.x))
+            .then(&Transform::rotate(Vec3::new(0.0, 1.0, 0.0), self.rotate.y))
+            .then(&Transform::rotate(Vec3::new(0.0, 0.0, 1.0), self.rotate.z))
+            .then(&Transform::translate(self.translate)));
+    }
+
+    /// Blends each part linearly, `t` = 0 giving `self` and 1 giving `other`.
+    pub fn lerp(&self, other: &TransformParts, t: f32) -> TransformParts {
+        return TransformParts {
+            scale: (1.0 - t) * self.scale + t * other.scale,
+            rotate: (1.0 - t) * self.rotate + t * other.rotate,
+            translate: (1.0 - t) * self.translate + t * other.translate
+        }
+    }
+}
+
+/// Number of points in time the motion of an animated transform is bounded at.
+const MOTION_STEPS: usize = 32;
+
+/// A transform changing over time, blending its parts from `start` at time 0 to `end`
+/// at time 1 and then applying the fixed `after`.
+#[derive(Clone, Copy)]
+pub struct AnimatedTransform {
+    start: TransformParts,
+    end: TransformParts,
+    after: Transform,
+    /// The whole transform at time 0, all there is to it when nothing moves.
+    fixed: Transform,
+    moving: bool
+}
+
+impl AnimatedTransform {
+    /// # Returns
+    ///
+    /// The animated transform, or `None` when a scale factor is zero at some time,
+    /// including factors changing sign along the way.
+    pub fn new(start: TransformParts, end: TransformParts, after: Transform) -> Option<AnimatedTransform> {
+        for axis in 0..3 {
+            if start.scale[axis] * end.scale[axis] <= 0.0 {
+                return None;
+            }
+        }
+
+        let moving = start.scale != end.scale || start.rotate != end.rotate || start.translate != end.translate;
+        return Some(AnimatedTransform {
+            start,
+            end,
+            after,
+            fixed: start.transform()?.then(&after),
+            moving
+        });
+    }
+
+    /// The transform at `time`, holding still before 0 and after 1.
+    pub fn at(&self, time: f32) -> Transform {
+        if !self.moving {
+            return self.fixed;
+        }
+        let parts = self.start.lerp(&self.end, time.clamp(0.0, 1.0));
+        return parts.transform()
+            .expect("Scale factors keep their sign between the ends")
+            .then(&self.after);
+    }
+
+    /// The box enclosing `bounds` transformed at every time between 0 and 1.
+    pub fn bounding_box(&self, bounds: Aabb) -> Aabb {
+        if !self.moving {
+            return self.fixed.bounding_box(bounds);
+        }
+
+        let mut result = Aabb::empty();
+        for step in 0..=MOTION_STEPS {
+            let parts = self.start.lerp(&self.end, step as f32 / MOTION_STEPS as f32);
+            let transform = parts.transform().expect("Scale factors keep their sign between the ends");
+            result = result.surrounding(transform.bounding_box(bounds));
+        }
+
+        // Scaling and moving are linear in time, so the corners at the steps bound them.
+        // Rotating corners bulge out in between, by less than the arc they sweep through
+        // in one step.
+        let turn = self.end.rotate - self.start.rotate;
+        let step_angle = (turn.x.abs() + turn.y.abs() + turn.z.abs()).to_radians() / MOTION_STEPS as f32;
+        let largest_scale = self.start.scale.abs().max(self.end.scale.abs());
+        let reach = bounds.min.abs().max(bounds.max.abs()) * largest_scale;
+        let pad = Vec3::from_uniform_value(reach.length() * step_angle);
+
+        return self.after.bounding_box(Aabb::new(result.min - pad, result.max + pad));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::dot;
+
+    fn assert_identity(m: &Matrix4) {
+        for row in 0..4 {
+            for column in 0..4 {
+                let expected = if row == column { 1.0 } else { 0.0 };
+                assert!((m.m[row][column] - expected).abs() < 1e-5, "{:?}", m);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let m = Matrix4::translation(Vec3::new(1.0, -2.0, 3.0))
+            * Matrix4::rotation(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Matrix4::scaling(Vec3::new(2.0, 0.5, 3.0));
+        let inverse = m.inverse().unwrap();
+        assert_identity(&(inverse * m));
+        assert_identity(&(m * inverse));
+
+        // A zero on the diagonal needs the rows swapped to find a pivot.
+        let swapped = Matrix4::new([
+            [0.0, 1.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 2.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ]);
+        assert_identity(&(swapped.inverse().unwrap() * swapped));
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        assert!(Matrix4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        assert!(Transform::new(Matrix4::new([[0.0; 4]; 4])).is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let transform = Transform::rotate(Vec3::new(0.0, 0.0, 1.0), 20.0)
+            .then(&Transform::scale(Vec3::new(4.0, 1.0, 0.5)).unwrap());
+
+        // A tilted plane, its normal and two tangents lying in it.
+        let n = Vec3::new(1.0, 1.0, 1.0);
+        let tangents = [Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, -1.0)];
+
+        let transformed = transform.normal(n);
+        for &t in &tangents {
+            let tangent = transform.vector(t);
+            assert!(dot(transformed, tangent).abs() < 1e-5 * tangent.length() * transformed.length());
+            // Transforming the normal like a vector would tilt it off the surface.
+            assert!(dot(transform.vector(n), tangent).abs() > 0.1);
+        }
+    }
+}
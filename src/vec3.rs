@@ -1,6 +1,6 @@
-use std::{fmt::{self, Display, Result}, ops::{Add, Mul, Div, Sub }};
+use std::{fmt::{self, Display, Result}, ops::{Add, Mul, Div, Sub, Neg, Index, IndexMut }};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -29,7 +29,7 @@ impl Vec3 {
     pub fn cross(self, other: Vec3) -> Vec3 {
         return Vec3 {
             x: (self.y * other.z) - (self.z * other.y),
-            y: (-(self.x * other.z) - (self.z * other.x)),
+            y: (self.z * other.x) - (self.x * other.z),
             z: (self.x * other.y) - (self.y * other.x)
         }
     }
@@ -56,6 +56,44 @@ impl Vec3 {
     pub fn length(&self) -> f32 {
         return self.length_sq().sqrt()
     }
+
+    /// Component-wise minimum of two vectors.
+    pub fn min(self, other: Vec3) -> Vec3 {
+        return Vec3::new(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    /// Component-wise maximum of two vectors.
+    pub fn max(self, other: Vec3) -> Vec3 {
+        return Vec3::new(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    /// Component-wise absolute value.
+    pub fn abs(self) -> Vec3 {
+        return Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+}
+
+impl Index<usize> for Vec3 {
+    type Output = f32;
+    fn index(&self, axis: usize) -> &f32 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", axis)
+        }
+    }
+}
+
+impl IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, axis: usize) -> &mut f32 {
+        match axis {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vec3 index out of range: {}", axis)
+        }
+    }
 }
 
 impl Display for Vec3 {
@@ -86,6 +124,17 @@ impl Sub for Vec3 {
     }
 }
 
+impl Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 {
+        return Vec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z
+        }
+    }
+}
+
 impl Mul<Vec3> for Vec3 {
     type Output = Vec3;
     fn mul(self, other: Vec3) -> Vec3 {
@@ -152,3 +201,33 @@ impl Div<i32> for Vec3 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-6, "expected ({}, {}, {}), found ({}, {}, {})", b.x, b.y, b.z, a.x, a.y, a.z);
+    }
+
+    #[test]
+    fn cross_of_axes_follows_right_hand_rule() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+
+        assert_close(x.cross(y), z);
+        assert_close(y.cross(z), x);
+        assert_close(z.cross(x), y);
+    }
+
+    #[test]
+    fn cross_is_anti_commutative() {
+        let a = Vec3::new(1.5, -2.0, 0.25);
+        let b = Vec3::new(-0.5, 3.0, 4.0);
+
+        assert_close(a.cross(b), -b.cross(a));
+        assert!(a.cross(b).dot(a).abs() < 1e-5);
+        assert!(a.cross(b).dot(b).abs() < 1e-5);
+    }
+}